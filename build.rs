@@ -65,7 +65,9 @@ fn generate_vial_config() {
         Err(e) => println!("Cannot find vial.json {:?}: {}", p, e),
     };
 
-    let vial_cfg = json::stringify(json::parse(&content).unwrap());
+    let vial_json = json::parse(&content).unwrap();
+    let num_encoder = count_vial_encoders(&vial_json);
+    let vial_cfg = json::stringify(vial_json);
     let mut keyboard_def_compressed: Vec<u8> = Vec::new();
     XzEncoder::new(vial_cfg.as_bytes(), 6)
         .read_to_end(&mut keyboard_def_compressed)
@@ -75,8 +77,42 @@ fn generate_vial_config() {
     let const_declarations = [
         const_declaration!(pub VIAL_KEYBOARD_DEF = keyboard_def_compressed),
         const_declaration!(pub VIAL_KEYBOARD_ID = keyboard_id),
+        const_declaration!(pub VIAL_NUM_ENCODER = num_encoder),
     ]
     .map(|s| "#[allow(clippy::redundant_static_lifetimes)]\n".to_owned() + s.as_str())
     .join("\n");
     fs::write(out_file, const_declarations).unwrap();
 }
+
+/// Vial shows an encoder as two keys in the layout, labelled "<index>,<direction>" with an "e"
+/// in the 10th legend slot (direction 0 = CCW, 1 = CW). Every encoder needs both, otherwise
+/// Vial can't remap it.
+fn count_vial_encoders(vial_json: &json::JsonValue) -> usize {
+    let mut directions: Vec<[bool; 2]> = Vec::new();
+    for row in vial_json["layouts"]["keymap"].members() {
+        for key in row.members().filter_map(|key| key.as_str()) {
+            let legends: Vec<&str> = key.split('\n').collect();
+            if legends.get(9) != Some(&"e") {
+                continue;
+            }
+            let (index, direction) = legends[0]
+                .split_once(',')
+                .and_then(|(i, d)| Some((i.parse::<usize>().ok()?, d.parse::<usize>().ok()?)))
+                .filter(|&(_, d)| d < 2)
+                .unwrap_or_else(|| panic!("vial.json: bad encoder key {:?}", key));
+            if directions.len() <= index {
+                directions.resize(index + 1, [false; 2]);
+            }
+            directions[index][direction] = true;
+        }
+    }
+    for (index, found) in directions.iter().enumerate() {
+        if *found != [true; 2] {
+            panic!(
+                "vial.json: encoder {} needs both a \"{},0\" and a \"{},1\" key",
+                index, index, index
+            );
+        }
+    }
+    directions.len()
+}
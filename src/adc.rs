@@ -0,0 +1,114 @@
+use core::sync::atomic::Ordering;
+
+use defmt::{info, warn};
+use embassy_nrf::saadc::Saadc;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use rmk::event::{BatteryAdcEvent, publish_event};
+use rmk::input_device::Runnable;
+
+#[cfg(feature = "ambient-light")]
+use crate::ambient_light::AMBIENT_LIGHT_RAW;
+use crate::idle::LOW_POWER_IDLE;
+
+// SAADC offset drifts with die temperature, so the boot calibration goes stale over a long session
+const ADC_RECALIBRATION_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+// Same rate NrfAdc had for the battery
+const BATTERY_SAMPLE_INTERVAL: Duration = Duration::from_secs(12);
+
+// In low-power idle (idle.rs) nobody's typing to see a change that fast, and the charge
+// barely moves in a minute
+const BATTERY_SAMPLE_INTERVAL_IDLE: Duration = Duration::from_secs(60);
+
+// Calibration and a sample take well under a millisecond, anything longer means the
+// peripheral stopped responding
+const ADC_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The SAADC, in place of RMK's `NrfAdc` in builds without the joystick. `NrfAdc` (ca38784)
+/// takes the `Saadc` for good and has no calibrate hook, so this samples it itself: the battery
+/// channel goes out as the same `BatteryAdcEvent` NrfAdc sends, every BATTERY_SAMPLE_INTERVAL
+/// (BATTERY_SAMPLE_INTERVAL_IDLE in low-power idle), and every ADC_RECALIBRATION_INTERVAL a
+/// `calibrate()` runs right before the next sample.
+/// With `ambient-light` the light sensor on the last channel comes along in the same sample
+/// and goes to `ambient_light_task`.
+/// Both happen in this one task, so they can never collide, and both are async, so key
+/// scanning keeps running.
+///
+/// With `joystick` RMK's `NrfAdc` stays, `JoystickProcessor` takes its events. Those builds
+/// only get the boot calibration, and can't have the light sensor: NrfAdc's `AnalogEventType`
+/// only knows `Battery` and `Joystick`, so it would drop that channel (main.rs refuses the
+/// combination).
+///
+/// A temperature trigger would be nicer than a fixed interval, but the TEMP peripheral is
+/// owned by MPSL (it uses it for its own clock calibration), so the reading would have to
+/// come from `mpsl_temperature_get()` rather than `embassy_nrf::temp`.
+pub(crate) struct BatteryAdc<const N: usize> {
+    saadc: Saadc<'static, N>,
+    // Boot calibrated it just before this was built
+    last_calibration: Instant,
+    // Only warn when it stops answering, not every sample after that
+    responding: bool,
+}
+
+impl<const N: usize> BatteryAdc<N> {
+    pub(crate) fn new(saadc: Saadc<'static, N>) -> Self {
+        Self {
+            saadc,
+            last_calibration: Instant::now(),
+            responding: true,
+        }
+    }
+
+    async fn calibrate(&mut self) {
+        self.last_calibration = Instant::now();
+        if with_timeout(ADC_TIMEOUT, self.saadc.calibrate())
+            .await
+            .is_err()
+        {
+            warn!("SAADC recalibration timed out");
+            return;
+        }
+        info!("SAADC recalibrated");
+    }
+
+    /// One sample of every channel, None if the SAADC doesn't answer
+    async fn sample(&mut self) -> Option<[i16; N]> {
+        let mut buf = [0i16; N];
+        let ok = with_timeout(ADC_TIMEOUT, self.saadc.sample(&mut buf))
+            .await
+            .is_ok();
+        if ok != self.responding {
+            if ok {
+                info!("SAADC answering again");
+            } else {
+                warn!("SAADC sample timed out - no battery reading");
+            }
+            self.responding = ok;
+        }
+        ok.then_some(buf)
+    }
+}
+
+// Runs from run_all! like the RMK devices
+impl<const N: usize> Runnable for BatteryAdc<N> {
+    async fn run(&mut self) {
+        loop {
+            if self.last_calibration.elapsed() >= ADC_RECALIBRATION_INTERVAL {
+                self.calibrate().await;
+            }
+            if let Some(buf) = self.sample().await {
+                // Channel 0 is the battery, see ADC_CHANNELS in main.rs
+                publish_event(BatteryAdcEvent(buf[0].max(0) as u16));
+                #[cfg(feature = "ambient-light")]
+                AMBIENT_LIGHT_RAW.signal(buf[N - 1]);
+            }
+            // Checked per sample, so leaving idle takes at most one slow interval to show
+            let interval = if LOW_POWER_IDLE.load(Ordering::Relaxed) {
+                BATTERY_SAMPLE_INTERVAL_IDLE
+            } else {
+                BATTERY_SAMPLE_INTERVAL
+            };
+            Timer::after(interval).await;
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use defmt::info;
+use embassy_time::{Duration, Instant};
+
+// Advertising backoff: fast right after advertising starts so it's obvious the board wants a
+// host, then slow once nobody has connected for ADVERTISING_FAST_DURATION. Any keypress while
+// advertising restarts the fast window - someone is at the keyboard and probably wants it to
+// connect. Only the LED blink follows it: RMK (ca38784) sets up its advertising parameters
+// inside its BLE task (`rmk/src/ble/mod.rs`) with a fixed interval and takes none from the
+// keyboard, so the radio advertises at the same rate in both halves. Switching that interval
+// (20ms fast, 1022ms slow per Apple's accessory guidelines) needs RMK to take it where it
+// calls `peripheral.advertise(..)`.
+pub(crate) const ADVERTISING_FAST_DURATION: Duration = Duration::from_secs(30);
+
+/// Tracks which half of the backoff we're in, owned by `StatusLedController` since it already
+/// sees both the BLE state and the key events
+pub(crate) struct AdvertisingBackoff {
+    // None while not advertising
+    fast_until: Option<Instant>,
+    slow: bool,
+}
+
+impl AdvertisingBackoff {
+    pub(crate) fn new(advertising: bool) -> Self {
+        let mut backoff = Self {
+            fast_until: None,
+            slow: false,
+        };
+        if advertising {
+            backoff.start();
+        }
+        backoff
+    }
+
+    /// Advertising (re)started, back to the fast half
+    pub(crate) fn start(&mut self) {
+        self.fast_until = Some(Instant::now() + ADVERTISING_FAST_DURATION);
+        if self.slow {
+            info!("Advertising back to fast");
+        }
+        self.slow = false;
+    }
+
+    /// Connected, or BLE went away
+    pub(crate) fn stop(&mut self) {
+        self.fast_until = None;
+        self.slow = false;
+    }
+
+    /// Restarts the fast window if we're advertising
+    pub(crate) fn on_keypress(&mut self) {
+        if self.fast_until.is_some() {
+            self.start();
+        }
+    }
+
+    /// Drops to the slow half once the fast window ran out, call it from poll()
+    pub(crate) fn update(&mut self) {
+        if let Some(fast_until) = self.fast_until
+            && !self.slow
+            && Instant::now() >= fast_until
+        {
+            info!(
+                "No host after {}s - advertising blink slower",
+                ADVERTISING_FAST_DURATION.as_secs()
+            );
+            self.slow = true;
+        }
+    }
+
+    pub(crate) fn is_slow(&self) -> bool {
+        self.slow
+    }
+}
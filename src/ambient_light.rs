@@ -0,0 +1,82 @@
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+// Ambient light sensor (`--features ambient-light`): a phototransistor from VDD to AIN7 (P0_31)
+// with a pull-down resistor, so the reading rises with the light. It's the SAADC's second
+// channel, sampled together with the battery every 12s by `BatteryAdc` (adc.rs). Not with
+// `joystick`, RMK's NrfAdc takes over the SAADC there and has no event type for it.
+//
+// Curve: phototransistor current is roughly linear in lux, but eyes are closer to logarithmic,
+// so brightness is linear in log2 of the reading. Dark room (DARK_RAW and below) gets
+// BRIGHTNESS_MIN, daylight (BRIGHT_RAW and up) gets full brightness.
+const DARK_RAW: u16 = 16;
+const BRIGHT_RAW: u16 = 2048;
+const BRIGHTNESS_MIN: u8 = 24;
+const BRIGHTNESS_MAX: u8 = 255;
+
+// Smoothing: readings go through an exponential moving average (1/4 of each new reading), and
+// a new brightness is only sent once it differs from the last one by more than the deadband.
+// A hand passing over the sensor moves one reading, not the average, so it doesn't flicker.
+const SMOOTHING_SHIFT: u32 = 2;
+const BRIGHTNESS_DEADBAND: u8 = 12;
+
+/// Raw 12-bit readings of the light sensor channel, from `BatteryAdc` after every sample
+pub(crate) static AMBIENT_LIGHT_RAW: Signal<CriticalSectionRawMutex, i16> = Signal::new();
+
+/// Brightness scale for the current room, picked up by `StatusLedController` on its next poll
+pub(crate) static AMBIENT_BRIGHTNESS: Signal<CriticalSectionRawMutex, u8> = Signal::new();
+
+/// log2 with 4 fractional bits, linear between powers of two. 0 counts as 1.
+const fn log2_q4(x: u16) -> u16 {
+    let x = if x == 0 { 1 } else { x };
+    let whole = x.ilog2();
+    let frac = (((x as u32) << 4) >> whole) as u16 & 0xF;
+    (whole as u16) * 16 + frac
+}
+
+const fn light_to_brightness(raw: u16) -> u8 {
+    let low = log2_q4(DARK_RAW);
+    let high = log2_q4(BRIGHT_RAW);
+    let log = log2_q4(raw);
+    if log <= low {
+        return BRIGHTNESS_MIN;
+    }
+    if log >= high {
+        return BRIGHTNESS_MAX;
+    }
+    let span = (BRIGHTNESS_MAX - BRIGHTNESS_MIN) as u32;
+    BRIGHTNESS_MIN + ((log - low) as u32 * span / (high - low) as u32) as u8
+}
+
+const _: () = assert!(light_to_brightness(0) == BRIGHTNESS_MIN);
+const _: () = assert!(light_to_brightness(DARK_RAW) == BRIGHTNESS_MIN);
+const _: () = assert!(light_to_brightness(BRIGHT_RAW) == BRIGHTNESS_MAX);
+const _: () = assert!(light_to_brightness(4095) == BRIGHTNESS_MAX);
+// Halfway in log space (log2_q4(192) is 7.5) lands halfway in brightness
+const _: () = assert!(log2_q4(192) == 120);
+const _: () = assert!(light_to_brightness(192) == 139);
+const _: () = assert!(light_to_brightness(100) < light_to_brightness(1000));
+
+#[embassy_executor::task]
+pub(crate) async fn ambient_light_task() -> ! {
+    // Fixed point with SMOOTHING_SHIFT fractional bits
+    let mut average: Option<u32> = None;
+    let mut sent: Option<u8> = None;
+    loop {
+        let raw = AMBIENT_LIGHT_RAW.wait().await.max(0) as u32;
+        let smoothed = match average {
+            // The first reading seeds the average, no slow fade-in at boot
+            None => raw << SMOOTHING_SHIFT,
+            Some(average) => average - (average >> SMOOTHING_SHIFT) + raw,
+        };
+        average = Some(smoothed);
+
+        let brightness = light_to_brightness((smoothed >> SMOOTHING_SHIFT) as u16);
+        if sent.is_none_or(|sent| sent.abs_diff(brightness) > BRIGHTNESS_DEADBAND) {
+            info!("Ambient light {} -> brightness {}", raw, brightness);
+            sent = Some(brightness);
+            AMBIENT_BRIGHTNESS.signal(brightness);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use defmt::{Format, info};
+use embassy_nrf::pwm::{Prescaler, SimplePwm};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+#[cfg(feature = "ble")]
+use rmk::ble::BleState;
+use rmk::embassy_futures::select::{Either, select};
+#[cfg(feature = "ble")]
+use rmk::event::BleStateChangeEvent;
+#[cfg(feature = "ble")]
+use rmk::macros::controller;
+
+// Piezo buzzer (`--features buzzer`) on PWM0, see main.rs for the pin. Short tunes for a BLE
+// connect, the battery dropping low (LowBatteryAlert, see battery_alert.rs) and bootloader
+// entry.
+//
+// One tune plays at a time. A new one cuts off whatever is playing if it's at least as
+// important (Tune order, bootloader first), otherwise it's dropped - a connect chime never
+// talks over the bootloader tone, and nothing queues up to play late.
+
+/// One note, 0 Hz is a rest
+#[derive(Clone, Copy)]
+pub(crate) struct Note {
+    pub(crate) freq_hz: u16,
+    pub(crate) ms: u16,
+}
+
+const fn note(freq_hz: u16, ms: u16) -> Note {
+    Note { freq_hz, ms }
+}
+
+const C6: u16 = 1047;
+const E6: u16 = 1319;
+const G6: u16 = 1568;
+const G5: u16 = 784;
+const BEEP: u16 = 2000;
+const REST: u16 = 0;
+
+// Rising triad
+const CONNECT_CHIME: &[Note] = &[note(C6, 60), note(E6, 60), note(G6, 90)];
+// Two flat beeps
+const LOW_BATTERY_BEEP: &[Note] = &[note(BEEP, 120), note(REST, 80), note(BEEP, 120)];
+// Falling, and shorter than the bootloader LED flashes so it's done before the jump
+const BOOTLOADER_TONE: &[Note] = &[note(G6, 80), note(C6, 80), note(G5, 160)];
+
+const fn tune_ms(notes: &[Note]) -> u32 {
+    let mut total = 0;
+    let mut i = 0;
+    while i < notes.len() {
+        total += notes[i].ms as u32;
+        i += 1;
+    }
+    total
+}
+
+// 3 purple flashes of 80ms on and off, see `StatusLedController::enter_bootloader`
+const _: () = assert!(tune_ms(BOOTLOADER_TONE) < 3 * 2 * 80);
+
+/// Most important first
+#[derive(Clone, Copy, PartialEq, PartialOrd, Format)]
+pub(crate) enum Tune {
+    Bootloader,
+    LowBattery,
+    Connect,
+}
+
+impl Tune {
+    const fn notes(self) -> &'static [Note] {
+        match self {
+            Tune::Bootloader => BOOTLOADER_TONE,
+            Tune::LowBattery => LOW_BATTERY_BEEP,
+            Tune::Connect => CONNECT_CHIME,
+        }
+    }
+}
+
+static TUNE: Signal<CriticalSectionRawMutex, Tune> = Signal::new();
+
+/// Asks the player for a tune, see above for what happens to the one already playing
+pub(crate) fn play(tune: Tune) {
+    TUNE.signal(tune);
+}
+
+/// Plays what `play` asks for, forever. Runs next to `BuzzerController`.
+pub(crate) async fn run_player(mut pwm: SimplePwm<'_>) {
+    // 16MHz / 16 = 1MHz counter, so the top value is the period in us (784Hz and up fit a u16)
+    pwm.set_prescaler(Prescaler::Div16);
+    pwm.disable();
+    let mut next = None;
+    loop {
+        let tune = match next.take() {
+            Some(tune) => tune,
+            None => TUNE.wait().await,
+        };
+        info!("Buzzer: {}", tune);
+        for note in tune.notes() {
+            if note.freq_hz == REST {
+                pwm.disable();
+            } else {
+                let period_us = (1_000_000 / note.freq_hz as u32) as u16;
+                pwm.set_max_duty(period_us);
+                pwm.set_duty(0, period_us / 2);
+                pwm.enable();
+            }
+            match select(Timer::after_millis(note.ms as u64), TUNE.wait()).await {
+                Either::First(()) => {}
+                Either::Second(new) if new <= tune => {
+                    next = Some(new);
+                    break;
+                }
+                // Less important than this one, dropped
+                Either::Second(_) => {}
+            }
+        }
+        pwm.disable();
+    }
+}
+
+/// Connect chime, once per connection
+#[cfg(feature = "ble")]
+#[controller(subscribe = [BleStateChangeEvent])]
+pub struct BuzzerController {
+    connected: bool,
+}
+
+#[cfg(feature = "ble")]
+impl BuzzerController {
+    pub fn new() -> Self {
+        Self { connected: false }
+    }
+
+    async fn on_ble_state_change_event(&mut self, event: BleStateChangeEvent) {
+        let connected = matches!(event.state, BleState::Connected);
+        if connected && !self.connected {
+            play(Tune::Connect);
+        }
+        self.connected = connected;
+    }
+}
@@ -0,0 +1,97 @@
+use defmt::{Format, warn};
+use rmk::event::BatteryStateEvent;
+use rmk::macros::controller;
+
+// Low battery alert that doesn't need anyone watching the LEDs: one beep on the way down past
+// each of LOW_BATTERY_LEVELS, never again for the same level while the battery hovers around
+// it. A level re-arms once the battery is LOW_BATTERY_REARM_PERCENT back above it, which in
+// practice means it was charged.
+//
+// The beep needs the buzzer, so it's only on by default in `--features buzzer` builds. Without
+// it the default is Off: setting Beep there only gets the warning in the log.
+#[derive(Clone, Copy, PartialEq, Format)]
+pub(crate) enum AlertKind {
+    /// Low battery tune on the buzzer, just the log line without `buzzer`
+    #[cfg_attr(not(feature = "buzzer"), allow(dead_code))]
+    Beep,
+    Off,
+}
+
+#[cfg(feature = "buzzer")]
+pub(crate) const LOW_BATTERY_ALERT: AlertKind = AlertKind::Beep;
+#[cfg(not(feature = "buzzer"))]
+pub(crate) const LOW_BATTERY_ALERT: AlertKind = AlertKind::Off;
+// Highest first
+const LOW_BATTERY_LEVELS: [u8; 2] = [15, 5];
+const LOW_BATTERY_REARM_PERCENT: u8 = 5;
+
+const _: () = assert!(LOW_BATTERY_LEVELS[0] > LOW_BATTERY_LEVELS[1]);
+
+/// Lowest level alerted so far after a new reading, and whether to alert now.
+/// `last` is an index into LOW_BATTERY_LEVELS, None = nothing alerted (or all re-armed).
+pub(crate) const fn next_alert(last: Option<usize>, percent: u8) -> (Option<usize>, bool) {
+    // Re-arm the levels the battery climbed back above
+    let mut last = last;
+    while let Some(level) = last
+        && percent >= LOW_BATTERY_LEVELS[level] + LOW_BATTERY_REARM_PERCENT
+    {
+        last = level.checked_sub(1);
+    }
+    // Deepest level the reading is under
+    let mut deepest = None;
+    let mut i = 0;
+    while i < LOW_BATTERY_LEVELS.len() {
+        if percent < LOW_BATTERY_LEVELS[i] {
+            deepest = Some(i);
+        }
+        i += 1;
+    }
+    match (deepest, last) {
+        (Some(level), None) => (Some(level), true),
+        (Some(level), Some(alerted)) if level > alerted => (Some(level), true),
+        _ => (last, false),
+    }
+}
+
+// Crossing 15% alerts once, hovering at the edge doesn't
+const _: () = assert!(matches!(next_alert(None, 16), (None, false)));
+const _: () = assert!(matches!(next_alert(None, 14), (Some(0), true)));
+const _: () = assert!(matches!(next_alert(Some(0), 15), (Some(0), false)));
+const _: () = assert!(matches!(next_alert(Some(0), 14), (Some(0), false)));
+// Down past 5% is the next one, and jumping straight there is a single alert
+const _: () = assert!(matches!(next_alert(Some(0), 4), (Some(1), true)));
+const _: () = assert!(matches!(next_alert(None, 4), (Some(1), true)));
+// Charged back up re-arms both, a partial recovery only the lower one
+const _: () = assert!(matches!(next_alert(Some(1), 25), (None, false)));
+const _: () = assert!(matches!(next_alert(Some(1), 10), (Some(0), false)));
+
+/// Fires LOW_BATTERY_ALERT, see above
+#[controller(subscribe = [BatteryStateEvent])]
+pub struct LowBatteryAlert {
+    last_alerted: Option<usize>,
+}
+
+impl LowBatteryAlert {
+    pub fn new() -> Self {
+        Self { last_alerted: None }
+    }
+
+    async fn on_battery_state_event(&mut self, event: BatteryStateEvent) {
+        let BatteryStateEvent::Normal(percent) = event else {
+            return;
+        };
+        let (last_alerted, alert) = next_alert(self.last_alerted, percent);
+        self.last_alerted = last_alerted;
+        if !alert || LOW_BATTERY_ALERT == AlertKind::Off {
+            return;
+        }
+        warn!("Battery at {}% - {} alert", percent, LOW_BATTERY_ALERT);
+        match LOW_BATTERY_ALERT {
+            AlertKind::Beep => {
+                #[cfg(feature = "buzzer")]
+                crate::audio::play(crate::audio::Tune::LowBattery);
+            }
+            AlertKind::Off => {}
+        }
+    }
+}
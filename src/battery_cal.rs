@@ -0,0 +1,175 @@
+use defmt::{info, warn};
+use rmk::event::KeyEvent;
+use rmk::macros::controller;
+use rmk::types::action::KeyAction;
+use smart_leds::RGB8;
+
+use crate::led::{LED_READOUT, LedReadout};
+use crate::settings::{SETTINGS_CHANNEL, SettingsUpdate};
+use crate::user_actions::{BATT_CAL, BATT_CHECK};
+
+// Two-point battery calibration against a bench supply, for boards whose divider or SAADC
+// is off from the nominal 1000/1400:
+// 1. Set the supply to CAL_LOW_MV, connect it to the battery terminals and power the board up.
+// 2. Hold BATT_CHECK, tap BATT_CAL and let go of both (the battery bar covers the feedback
+//    while BATT_CHECK is held). 1 LED lights: the low point is saved.
+// 3. Set the supply to CAL_HIGH_MV and power-cycle the board (the capture uses the reading
+//    taken at boot, before RMK's ADC task owns the SAADC).
+// 4. Same combo again. 2 LEDs: the high point is saved.
+// The LEDs are green once both points are in, amber while one is still missing, and a single
+// red LED means the reading was nowhere near either voltage (or the ADC didn't come up).
+// Which point a capture fills is picked from the uncalibrated reading, so the order doesn't
+// matter. The new calibration is used from the next boot.
+pub(crate) const CAL_LOW_MV: u32 = 3300;
+pub(crate) const CAL_HIGH_MV: u32 = 4200;
+// An uncalibrated reading further than this from a point isn't captured as that point
+const CAL_MAX_ERROR_MV: u32 = 300;
+
+const CAL_DONE_COLOR: RGB8 = RGB8 { r: 0, g: 40, b: 0 };
+const CAL_PARTIAL_COLOR: RGB8 = RGB8 { r: 40, g: 20, b: 0 };
+const CAL_FAILED_COLOR: RGB8 = RGB8 { r: 40, g: 0, b: 0 };
+
+/// Raw SAADC readings at CAL_LOW_MV and CAL_HIGH_MV, 0 = not captured.
+/// Stored as 4 bytes, both readings little endian.
+pub(crate) type BatteryCalibration = [u16; 2];
+
+pub(crate) const fn calibration_to_bytes(cal: BatteryCalibration) -> [u8; 4] {
+    let [low, high] = [cal[0].to_le_bytes(), cal[1].to_le_bytes()];
+    [low[0], low[1], high[0], high[1]]
+}
+
+pub(crate) const fn calibration_from_bytes(bytes: [u8; 4]) -> BatteryCalibration {
+    [
+        u16::from_le_bytes([bytes[0], bytes[1]]),
+        u16::from_le_bytes([bytes[2], bytes[3]]),
+    ]
+}
+
+pub(crate) const fn is_complete(cal: BatteryCalibration) -> bool {
+    cal[0] != 0 && cal[1] > cal[0]
+}
+
+/// 12-bit reading with 1/6 gain and 0.6V reference => 3.6V full scale
+pub(crate) const fn adc_to_mv(raw: u16) -> u32 {
+    raw as u32 * 3600 / 4096
+}
+
+/// Raw reading for a battery voltage through a divider, `battery_mv` the other way round
+pub(crate) const fn battery_raw(mv: u32, divider_measured: u32, divider_total: u32) -> u16 {
+    let scale = divider_total as u64 * 3600;
+    ((mv as u64 * divider_measured as u64 * 4096 + scale / 2) / scale) as u16
+}
+
+/// Battery voltage for a raw reading: straight through both calibration points once they're
+/// captured, otherwise the nominal divider
+pub(crate) const fn battery_mv(
+    raw: u16,
+    cal: BatteryCalibration,
+    divider_measured: u32,
+    divider_total: u32,
+) -> u32 {
+    if !is_complete(cal) {
+        return adc_to_mv(raw) * divider_total / divider_measured;
+    }
+    let offset = raw as i32 - cal[0] as i32;
+    let mv =
+        CAL_LOW_MV as i32 + offset * (CAL_HIGH_MV - CAL_LOW_MV) as i32 / (cal[1] - cal[0]) as i32;
+    if mv < 0 { 0 } else { mv as u32 }
+}
+
+// A board that matches the nominal 1000/1400 divider reads 2682 at 3.3V and 3413 at 4.2V,
+// its calibration has to agree with the uncalibrated math
+const NOMINAL: BatteryCalibration = [2682, 3413];
+const _: () = assert!(battery_raw(CAL_LOW_MV, 1000, 1400) == NOMINAL[0]);
+const _: () = assert!(battery_raw(CAL_HIGH_MV, 1000, 1400) == NOMINAL[1]);
+const _: () = assert!(battery_mv(2682, NOMINAL, 1000, 1400) == CAL_LOW_MV);
+const _: () = assert!(battery_mv(3413, NOMINAL, 1000, 1400) == CAL_HIGH_MV);
+const _: () = assert!(battery_mv(3047, NOMINAL, 1000, 1400).abs_diff(3749) <= 5);
+const _: () = assert!(battery_mv(3047, [0, 3413], 1000, 1400) == 3749);
+const _: () = assert!(calibration_from_bytes(calibration_to_bytes(NOMINAL))[1] == 3413);
+
+/// Captures calibration points, see the flow above
+#[controller(subscribe = [KeyEvent])]
+pub struct BatteryCalibrator {
+    boot_raw: Option<u16>,
+    calibration: BatteryCalibration,
+    divider_measured: u32,
+    divider_total: u32,
+    check_held: bool,
+    cal_held: bool,
+}
+
+impl BatteryCalibrator {
+    /// `boot_raw` is the SAADC battery reading from boot, None if the ADC didn't come up
+    pub fn new(
+        boot_raw: Option<u16>,
+        calibration: BatteryCalibration,
+        divider_measured: u32,
+        divider_total: u32,
+    ) -> Self {
+        Self {
+            boot_raw,
+            calibration,
+            divider_measured,
+            divider_total,
+            check_held: false,
+            cal_held: false,
+        }
+    }
+
+    async fn capture(&mut self) {
+        let Some(raw) = self.boot_raw else {
+            warn!("Battery calibration: no boot reading");
+            LED_READOUT.signal(LedReadout {
+                count: 1,
+                color: CAL_FAILED_COLOR,
+            });
+            return;
+        };
+        let mv = battery_mv(raw, [0, 0], self.divider_measured, self.divider_total);
+        let point = if mv.abs_diff(CAL_LOW_MV) <= CAL_MAX_ERROR_MV {
+            0
+        } else if mv.abs_diff(CAL_HIGH_MV) <= CAL_MAX_ERROR_MV {
+            1
+        } else {
+            warn!(
+                "Battery calibration: {}mV is near neither {}mV nor {}mV",
+                mv, CAL_LOW_MV, CAL_HIGH_MV
+            );
+            LED_READOUT.signal(LedReadout {
+                count: 1,
+                color: CAL_FAILED_COLOR,
+            });
+            return;
+        };
+        self.calibration[point] = raw;
+        let complete = is_complete(self.calibration);
+        info!(
+            "Battery calibration point {} = raw {} (complete: {})",
+            point, raw, complete
+        );
+        SETTINGS_CHANNEL
+            .send(SettingsUpdate::BatteryCalibration(self.calibration))
+            .await;
+        LED_READOUT.signal(LedReadout {
+            count: point as u8 + 1,
+            color: if complete {
+                CAL_DONE_COLOR
+            } else {
+                CAL_PARTIAL_COLOR
+            },
+        });
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        // Same toggle trick as StatusLedController, every event flips the held state
+        if event.key_action == KeyAction::Single(BATT_CHECK) {
+            self.check_held = !self.check_held;
+        } else if event.key_action == KeyAction::Single(BATT_CAL) {
+            self.cal_held = !self.cal_held;
+            if self.cal_held && self.check_held {
+                self.capture().await;
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use core::sync::atomic::Ordering;
+
+use defmt::{Format, info};
+use rmk::event::{BatteryAdcEvent, BatteryStateEvent, publish_event};
+use rmk::macros::processor;
+
+use crate::battery_cal::{self, BatteryCalibration, CAL_HIGH_MV, CAL_LOW_MV};
+use crate::raw_adc::BATTERY_RAW;
+
+// Our own raw reading -> percentage mapping, in place of RMK's `BatteryProcessor`, so the
+// numbers behind the battery bar are checked right here (the asserts below) instead of trusted.
+// Both curves work on raw SAADC readings between the raw value at BATTERY_EMPTY_MV and the one
+// at BATTERY_FULL_MV (see `endpoints`), so the boot reading and every later one agree:
+// - Linear: straight line from empty to full. Simple, but a LiPo spends most of its charge
+//   at 3.7-3.9V, so the bar drops fast at the top and then sits in the middle for ages.
+// - Lipo: LIPO_CURVE, a typical single cell discharge curve at light load, interpolated
+//   between its points. Closer to the charge actually left, but it's a typical cell, not ours.
+pub(crate) const BATTERY_EMPTY_MV: u32 = 3300;
+pub(crate) const BATTERY_FULL_MV: u32 = 4200;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Format)]
+pub(crate) enum BatteryCurve {
+    Linear,
+    Lipo,
+}
+
+pub(crate) const BATTERY_CURVE: BatteryCurve = BatteryCurve::Linear;
+
+// (mV, percentage), from empty to full
+const LIPO_CURVE: [(u32, u8); 21] = [
+    (3300, 0),
+    (3610, 5),
+    (3690, 10),
+    (3710, 15),
+    (3730, 20),
+    (3750, 25),
+    (3770, 30),
+    (3790, 35),
+    (3800, 40),
+    (3820, 45),
+    (3840, 50),
+    (3850, 55),
+    (3870, 60),
+    (3910, 65),
+    (3950, 70),
+    (3980, 75),
+    (4020, 80),
+    (4080, 85),
+    (4110, 90),
+    (4150, 95),
+    (4200, 100),
+];
+
+// Calibration points are captured at the same voltages, so they're the endpoints as they are
+const _: () = assert!(CAL_LOW_MV == BATTERY_EMPTY_MV && CAL_HIGH_MV == BATTERY_FULL_MV);
+const _: () = assert!(LIPO_CURVE[0].0 == BATTERY_EMPTY_MV);
+const _: () = assert!(LIPO_CURVE[LIPO_CURVE.len() - 1].0 == BATTERY_FULL_MV);
+
+/// Raw readings at BATTERY_EMPTY_MV and BATTERY_FULL_MV: the calibration points once both are
+/// in, otherwise worked out from the nominal divider
+pub(crate) const fn endpoints(
+    cal: BatteryCalibration,
+    divider_measured: u32,
+    divider_total: u32,
+) -> (u16, u16) {
+    if battery_cal::is_complete(cal) {
+        return (cal[0], cal[1]);
+    }
+    (
+        battery_cal::battery_raw(BATTERY_EMPTY_MV, divider_measured, divider_total),
+        battery_cal::battery_raw(BATTERY_FULL_MV, divider_measured, divider_total),
+    )
+}
+
+/// Linear percentage of `raw` between the `empty` and `full` readings
+pub(crate) const fn adc_to_percentage(raw: u16, empty: u16, full: u16) -> u8 {
+    if raw <= empty {
+        0
+    } else if raw >= full {
+        100
+    } else {
+        ((raw - empty) as u32 * 100 / (full - empty) as u32) as u8
+    }
+}
+
+/// LIPO_CURVE percentage of `raw`, with `empty` and `full` the readings at the curve's ends
+pub(crate) const fn lipo_curve(raw: u16, empty: u16, full: u16) -> u8 {
+    if raw <= empty {
+        return 0;
+    }
+    if raw >= full {
+        return 100;
+    }
+    let mv = BATTERY_EMPTY_MV
+        + (raw - empty) as u32 * (BATTERY_FULL_MV - BATTERY_EMPTY_MV) / (full - empty) as u32;
+    let mut i = 1;
+    while LIPO_CURVE[i].0 < mv {
+        i += 1;
+    }
+    let (low_mv, low) = LIPO_CURVE[i - 1];
+    let (high_mv, high) = LIPO_CURVE[i];
+    low + ((mv - low_mv) * (high - low) as u32 / (high_mv - low_mv)) as u8
+}
+
+/// Percentage of `raw` on `curve`
+pub(crate) const fn percentage(curve: BatteryCurve, raw: u16, empty: u16, full: u16) -> u8 {
+    match curve {
+        BatteryCurve::Linear => adc_to_percentage(raw, empty, full),
+        BatteryCurve::Lipo => lipo_curve(raw, empty, full),
+    }
+}
+
+// Nominal 1000/1400 divider
+const _: () = assert!(matches!(endpoints([0, 0], 1000, 1400), (2682, 3413)));
+const EMPTY: u16 = 2682;
+const FULL: u16 = 3413;
+const HALF: u16 = (EMPTY + FULL) / 2;
+const _: () = assert!(adc_to_percentage(0, EMPTY, FULL) == 0);
+const _: () = assert!(adc_to_percentage(EMPTY, EMPTY, FULL) == 0);
+const _: () = assert!(adc_to_percentage(HALF, EMPTY, FULL) == 49);
+const _: () = assert!(adc_to_percentage(FULL, EMPTY, FULL) == 100);
+const _: () = assert!(adc_to_percentage(u16::MAX, EMPTY, FULL) == 100);
+// Points on the curve come out as they are, in between is interpolated
+const _: () = assert!(lipo_curve(EMPTY, EMPTY, FULL) == 0 && lipo_curve(FULL, EMPTY, FULL) == 100);
+// 3840mV and 3700mV through the nominal divider
+const _: () = assert!(lipo_curve(battery_cal::battery_raw(3840, 1000, 1400), EMPTY, FULL) == 50);
+const _: () = assert!(lipo_curve(battery_cal::battery_raw(3700, 1000, 1400), EMPTY, FULL) == 12);
+// A cell at 3.75V is a quarter full on the curve, not half as the straight line has it
+const AT_3750: u16 = battery_cal::battery_raw(3750, 1000, 1400);
+const _: () = assert!(lipo_curve(AT_3750, EMPTY, FULL) == 25);
+const _: () = assert!(adc_to_percentage(AT_3750, EMPTY, FULL) == 50);
+
+// Neither curve ever goes back down or past 100, across every reading
+const fn monotonic(curve: BatteryCurve, empty: u16, full: u16) -> bool {
+    let mut last = 0;
+    let mut raw = 0;
+    while raw <= 4095 {
+        let p = percentage(curve, raw, empty, full);
+        if p < last || p > 100 {
+            return false;
+        }
+        last = p;
+        raw += 1;
+    }
+    true
+}
+
+const _: () = assert!(monotonic(BatteryCurve::Linear, EMPTY, FULL));
+const _: () = assert!(monotonic(BatteryCurve::Lipo, EMPTY, FULL));
+
+/// Turns battery ADC readings into `BatteryStateEvent`s on BATTERY_CURVE
+#[processor(subscribe = [BatteryAdcEvent])]
+pub struct CurveBatteryProcessor {
+    empty: u16,
+    full: u16,
+}
+
+impl CurveBatteryProcessor {
+    pub fn new(cal: BatteryCalibration, divider_measured: u32, divider_total: u32) -> Self {
+        let (empty, full) = endpoints(cal, divider_measured, divider_total);
+        info!(
+            "Battery curve: {:?}, raw {} = 0%, raw {} = 100%",
+            BATTERY_CURVE, empty, full
+        );
+        Self { empty, full }
+    }
+
+    async fn on_battery_adc_event(&mut self, event: BatteryAdcEvent) {
+        BATTERY_RAW.store(event.0, Ordering::Relaxed);
+        let percentage = percentage(BATTERY_CURVE, event.0, self.empty, self.full);
+        publish_event(BatteryStateEvent::Normal(percentage));
+    }
+}
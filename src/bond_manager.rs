@@ -0,0 +1,95 @@
+use defmt::warn;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use rmk::channel::{FLASH_CHANNEL, FLASH_OPERATION_FINISHED};
+use rmk::event::KeyEvent;
+use rmk::macros::controller;
+use rmk::storage::FlashOperationMessage;
+use rmk::types::action::{Action, KeyAction};
+use smart_leds::RGB8;
+
+use crate::keymap::BOND_DELETE_HOLD_MS;
+use crate::led::{LED_READOUT, LedReadout};
+use crate::user_actions::{BOND_DEL_1, BOND_DEL_2, BOND_DEL_3};
+
+// Single bond deletion from the bond management layer (layer 6, see keymap.rs): a tap on a
+// profile key switches to it, holding it deletes that profile's bond and leaves the other two
+// alone. The profile can then pair with something new, the others keep their hosts.
+//
+// The bond record is cleared through RMK's storage task (`ClearSlot`, the same message its
+// BLE_CLR uses for the slot), then the keyboard reboots. RMK (ca38784) keeps the trouble-host
+// stack inside its BLE task with no call to drop one bond from it, but it loads the bonds from
+// storage at boot, so after the reboot the stack has never heard of that host. The other two
+// profiles come back from storage as before.
+//
+// "Bond X deleted" is X red LEDs (BLE1 = 1, BLE3 = 3) shown as a readout, so the count tells
+// which one went - the same place the other readouts show up. The reboot waits for it.
+const BOND_DELETED_COLOR: RGB8 = RGB8 { r: 60, g: 0, b: 0 };
+
+const BOND_DELETE_ACTIONS: [Action; 3] = [BOND_DEL_1, BOND_DEL_2, BOND_DEL_3];
+
+// Long enough for the readout (READOUT_DURATION_MS in status_controller.rs)
+const BOND_DELETED_REBOOT_DELAY: Duration = Duration::from_millis(1500);
+
+// The slot erase is one flash page, anything longer means the storage task is stuck. The
+// reboot happens anyway, the bond is then still there and the hold can be tried again.
+const BOND_CLEAR_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Deletes single bonds and shows the confirmation, see above
+#[controller(subscribe = [KeyEvent], poll_interval = 100)]
+pub struct BondManager {
+    // Profile key held down and since when
+    held: Option<(usize, Instant)>,
+}
+
+impl BondManager {
+    pub fn new() -> Self {
+        Self { held: None }
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        // Key events carry the key as mapped, not what RMK resolved it to, so the hold is
+        // timed here too. BOND_DEL_n itself does nothing in RMK, it just keeps a hold from
+        // switching profiles.
+        let KeyAction::TapHold(_, hold, _) = event.key_action else {
+            return;
+        };
+        let Some(profile) = BOND_DELETE_ACTIONS
+            .iter()
+            .position(|&action| action == hold)
+        else {
+            return;
+        };
+        self.held = event
+            .keyboard_event
+            .pressed
+            .then(|| (profile, Instant::now()));
+    }
+
+    async fn poll(&mut self) {
+        let Some((profile, since)) = self.held else {
+            return;
+        };
+        if since.elapsed() < Duration::from_millis(BOND_DELETE_HOLD_MS as u64) {
+            return;
+        }
+        // Once per hold
+        self.held = None;
+        warn!("Deleting the bond of BLE{}", profile + 1);
+        FLASH_CHANNEL
+            .send(FlashOperationMessage::ClearSlot(profile as u8))
+            .await;
+        if with_timeout(BOND_CLEAR_TIMEOUT, FLASH_OPERATION_FINISHED.wait())
+            .await
+            .is_err()
+        {
+            warn!("Bond slot clear timed out");
+        }
+        LED_READOUT.signal(LedReadout {
+            count: profile as u8 + 1,
+            color: BOND_DELETED_COLOR,
+        });
+        Timer::after(BOND_DELETED_REBOOT_DELAY).await;
+        warn!("Rebooting so the BLE stack reloads its bonds");
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+}
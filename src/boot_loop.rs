@@ -0,0 +1,70 @@
+use core::mem::MaybeUninit;
+use core::ptr::addr_of_mut;
+
+use defmt::{info, warn};
+use embassy_time::{Duration, Timer};
+
+// Reset loop detection. Every boot bumps a counter that lives in RAM the startup code doesn't
+// touch, and STABLE_UPTIME after boot it's cleared again. So the counter only climbs while the
+// board keeps resetting before reaching that, and from RAPID_REBOOT_THRESHOLD boots in a row
+// the startup animation is skipped - it powers every LED for ~1.7s, and the LED rail is what
+// usually causes the brownout in the first place.
+//
+// The counter sits in `.uninit` (cortex-m-rt leaves it out of the zeroing and the data copy),
+// so it survives soft resets, watchdog and pin resets. A real power-on starts with random RAM,
+// which the magic word catches. A brownout is a power-on reset on the nRF52840, but the cell
+// rarely sags far enough for RAM to lose its contents, so loops caused by it are caught too.
+// If the bootloader ever uses that part of RAM the magic is gone as well - then every boot
+// just looks like the first one, nothing worse.
+const RAPID_REBOOT_THRESHOLD: u32 = 3;
+const STABLE_UPTIME: Duration = Duration::from_secs(10);
+const MAGIC: u32 = 0x5A4D_B007;
+
+#[repr(C)]
+struct RetainedBoots {
+    magic: u32,
+    count: u32,
+}
+
+#[unsafe(link_section = ".uninit.boot_loop")]
+static mut RETAINED: MaybeUninit<RetainedBoots> = MaybeUninit::uninit();
+
+/// Counts this boot and returns whether we're in a reset loop.
+/// Call once, early in main, before anything else could reset us.
+pub(crate) fn record_boot() -> bool {
+    // Only ever touched here and in stable_uptime_task, through volatile accesses since the
+    // contents outlive the program
+    let retained = unsafe { (*addr_of_mut!(RETAINED)).as_mut_ptr() };
+    let count = unsafe {
+        let magic = addr_of_mut!((*retained).magic);
+        let count = addr_of_mut!((*retained).count);
+        let previous = if magic.read_volatile() == MAGIC {
+            count.read_volatile()
+        } else {
+            0
+        };
+        let current = previous.saturating_add(1);
+        magic.write_volatile(MAGIC);
+        count.write_volatile(current);
+        current
+    };
+    let rapid = count >= RAPID_REBOOT_THRESHOLD;
+    if rapid {
+        warn!(
+            "{} boots in a row without {}s uptime - reset loop?",
+            count,
+            STABLE_UPTIME.as_secs()
+        );
+    } else if count > 1 {
+        info!("Boot {} in a row under {}s", count, STABLE_UPTIME.as_secs());
+    }
+    rapid
+}
+
+/// Clears the boot counter once we've stayed up long enough
+#[embassy_executor::task]
+pub(crate) async fn stable_uptime_task() {
+    Timer::after(STABLE_UPTIME).await;
+    let retained = unsafe { (*addr_of_mut!(RETAINED)).as_mut_ptr() };
+    unsafe { addr_of_mut!((*retained).count).write_volatile(0) };
+}
@@ -0,0 +1,119 @@
+use defmt::info;
+use rmk::event::{BatteryStateEvent, KeyEvent};
+use rmk::macros::controller;
+use rmk::types::action::KeyAction;
+use smart_leds::RGB8;
+
+use crate::led::{LED_READOUT, LedReadout};
+use crate::settings::{SETTINGS_CHANNEL, SettingsUpdate};
+use crate::user_actions::CHARGE_CYCLES_SHOW;
+
+// Accuracy: this counts charge put back in from battery percentage rises, not real coulombs.
+// - Percentage comes from a voltage curve, which is flat in the middle and sags under load,
+//   so a single reading can be several % off. Rises smaller than the noise floor are dropped,
+//   which slightly undercounts lots of tiny top-ups.
+// - Without the charge pin wired (see main.rs) there's no Charging event, so charging is
+//   only seen as the percentage going up, and `Charged` as a jump to 100%.
+// - Capacity fade isn't modelled, 100% of an old cell is less charge than 100% of a new one.
+// Good enough for "roughly how many cycles has this cell seen", not for health estimates.
+const CYCLE_NOISE_PERCENT: u8 = 3;
+
+// Save progress every this much accumulated charge, plus whenever a cycle completes
+const CYCLE_SAVE_STEP_PERCENT: u8 = 25;
+
+// One LED per hundred cycles (1 LED = under 100), LiPos are usually good for 300-500
+const READOUT_COLOR: RGB8 = RGB8 { r: 40, g: 0, b: 40 };
+
+/// Accumulates battery percentage rises into full charge cycles and saves them
+#[controller(subscribe = [BatteryStateEvent, KeyEvent])]
+pub struct ChargeCycleCounter {
+    cycles: u16,
+    partial_percent: u8,
+    last_percent: Option<u8>,
+    charging: bool,
+    show_key_held: bool,
+}
+
+impl ChargeCycleCounter {
+    pub fn new(cycles: u16, partial_percent: u8) -> Self {
+        Self {
+            cycles,
+            partial_percent,
+            last_percent: None,
+            charging: false,
+            show_key_held: false,
+        }
+    }
+
+    async fn add_charge(&mut self, percent: u8) {
+        let before = self.partial_percent;
+        let total = self.partial_percent as u16 + percent as u16;
+        self.cycles = self.cycles.saturating_add(total / 100);
+        self.partial_percent = (total % 100) as u8;
+
+        let crossed_step =
+            before / CYCLE_SAVE_STEP_PERCENT != self.partial_percent / CYCLE_SAVE_STEP_PERCENT;
+        if total >= 100 {
+            info!("Charge cycle complete - {} cycles", self.cycles);
+        } else if !crossed_step {
+            return;
+        }
+        SETTINGS_CHANNEL
+            .send(SettingsUpdate::ChargeCycles {
+                cycles: self.cycles,
+                partial_percent: self.partial_percent,
+            })
+            .await;
+    }
+
+    async fn on_battery_state_event(&mut self, event: BatteryStateEvent) {
+        match event {
+            BatteryStateEvent::Normal(percent) => {
+                let Some(last) = self.last_percent else {
+                    self.last_percent = Some(percent);
+                    return;
+                };
+                if percent < last {
+                    // Discharging, follow it down so the next rise is measured from the low point
+                    self.last_percent = Some(percent);
+                } else if percent >= last + CYCLE_NOISE_PERCENT {
+                    self.last_percent = Some(percent);
+                    self.add_charge(percent - last).await;
+                }
+            }
+            BatteryStateEvent::Charging => {
+                if !self.charging {
+                    info!("Charging started - counting from {:?}%", self.last_percent);
+                }
+                self.charging = true;
+            }
+            BatteryStateEvent::Charged => {
+                self.charging = false;
+                if let Some(last) = self.last_percent {
+                    self.add_charge(100 - last.min(100)).await;
+                }
+                self.last_percent = Some(100);
+            }
+            BatteryStateEvent::NotAvailable => {}
+        }
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        if event.key_action != KeyAction::Single(CHARGE_CYCLES_SHOW) {
+            return;
+        }
+        // Same toggle trick as User7, only show on the press
+        self.show_key_held = !self.show_key_held;
+        if !self.show_key_held {
+            return;
+        }
+        info!(
+            "Charge cycles: {} (+{}%)",
+            self.cycles, self.partial_percent
+        );
+        LED_READOUT.signal(LedReadout {
+            count: (self.cycles / 100 + 1).min(u8::MAX as u16) as u8,
+            color: READOUT_COLOR,
+        });
+    }
+}
@@ -0,0 +1,97 @@
+use core::sync::atomic::Ordering;
+
+use embassy_time::Instant;
+use rmk::debounce::{DebounceState, DebouncerTrait};
+use rmk::matrix::KeyState;
+
+use crate::keyboard_lock::{KEYBOARD_LOCKED, LockCombo};
+use crate::led::{LED_WAKE, LEDS_ASLEEP, TAP_TO_WAKE_SWALLOWS_KEY};
+
+// Debounce thresholds, in ms of stable signal before a change is accepted
+// - Cherry-style (MX) switches are specced at <5ms bounce, 10ms leaves margin for worn switches
+// - Encoder push-buttons (EC11 style detent switches) are much bouncier, 20-30ms is typical
+pub(crate) const MATRIX_DEBOUNCE_MS: u16 = 10;
+#[allow(dead_code)] // Used once the encoder push-button is wired up, see main.rs
+pub(crate) const ENCODER_BUTTON_DEBOUNCE_MS: u16 = 20;
+
+/// Same counter algorithm as RMK's `DefaultDebouncer`, but the threshold is a type
+/// parameter instead of a crate-wide constant, so each input device gets its own.
+/// Also swallows the press that wakes the LEDs, see `TAP_TO_WAKE_SWALLOWS_KEY`, and every press
+/// while the keyboard is locked, see keyboard_lock.rs.
+pub(crate) struct TimedDebouncer<const INPUT: usize, const OUTPUT: usize, const THRESHOLD_MS: u16> {
+    last_ms: u32,
+    counters: [[u16; INPUT]; OUTPUT],
+    // Presses eaten as a wake-up tap or while locked, ignored until the key is released
+    swallowed: [[bool; INPUT]; OUTPUT],
+    lock_combo: LockCombo,
+}
+
+impl<const INPUT: usize, const OUTPUT: usize, const THRESHOLD_MS: u16> DebouncerTrait
+    for TimedDebouncer<INPUT, OUTPUT, THRESHOLD_MS>
+{
+    fn new() -> Self {
+        Self {
+            last_ms: 0,
+            counters: [[0; INPUT]; OUTPUT],
+            swallowed: [[false; INPUT]; OUTPUT],
+            lock_combo: LockCombo::new(),
+        }
+    }
+
+    fn detect_change_with_debounce(
+        &mut self,
+        in_idx: usize,
+        out_idx: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        let swallowed = &mut self.swallowed[out_idx][in_idx];
+        if *swallowed {
+            // Key state never saw the press, so the release needs no debouncing either
+            if !pin_state {
+                *swallowed = false;
+                self.lock_combo.update(in_idx, out_idx, false);
+            }
+            return DebounceState::Ignored;
+        }
+
+        let counter = &mut self.counters[out_idx][in_idx];
+        if key_state.pressed == pin_state {
+            // No change (or bounced back), forget any progress
+            *counter = 0;
+            return DebounceState::Ignored;
+        }
+
+        let now_ms = Instant::now().as_millis() as u32;
+        let elapsed = now_ms.wrapping_sub(self.last_ms) as u16;
+        if elapsed > 0 {
+            self.last_ms = now_ms;
+            *counter = counter.saturating_add(elapsed);
+        }
+
+        if *counter >= THRESHOLD_MS {
+            *counter = 0;
+            // Inputs are the rows with COL2ROW, so (in_idx, out_idx) is (row, col)
+            if self.lock_combo.update(in_idx, out_idx, pin_state) {
+                *swallowed = true;
+                return DebounceState::Ignored;
+            }
+            if pin_state && KEYBOARD_LOCKED.load(Ordering::Relaxed) {
+                *swallowed = true;
+                // Wake the LEDs anyway, so the lock indicator shows. Even when they were dark
+                // before sleeping, no key event is coming to wake them.
+                LEDS_ASLEEP.store(false, Ordering::Relaxed);
+                LED_WAKE.signal(());
+                return DebounceState::Ignored;
+            }
+            if pin_state && TAP_TO_WAKE_SWALLOWS_KEY && LEDS_ASLEEP.swap(false, Ordering::Relaxed) {
+                *swallowed = true;
+                LED_WAKE.signal(());
+                return DebounceState::Ignored;
+            }
+            DebounceState::Debounced
+        } else {
+            DebounceState::InProgress
+        }
+    }
+}
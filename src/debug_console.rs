@@ -0,0 +1,128 @@
+use defmt::{Format, info, warn};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Timer;
+use rtt_target::{DownChannel, rtt_init, set_defmt_channel};
+
+use crate::led::UnderglowBytes;
+use crate::led::effects::LedEffect;
+use crate::settings::{SETTINGS_CHANNEL, SettingsUpdate};
+
+// Dev-only text console on RTT down-channel "Console" (`--features dev-console`).
+// Type one command per line in any RTT terminal (e.g. `probe-rs attach`):
+// - `brightness <0-255>`  global LED brightness scale, 255 = colors as written
+// - `effect <status|party|scanner>`  switch the LED effect
+// - `underglow <r> <g> <b> <first> <end>`  always-on underglow on LEDs first..end, saved
+// - `underglow off`  turn it off (also saved)
+// - `battery`  log the last battery percentage
+// - `status`  log the LED controller's state snapshot
+// - `storage-reset`  erase our settings region (RMK's storage is untouched), applies on reboot
+const CONSOLE_POLL_MS: u64 = 50;
+const CONSOLE_LINE_LEN: usize = 32;
+
+#[derive(Clone, Copy, Format)]
+pub(crate) enum ConsoleCommand {
+    Brightness(u8),
+    Effect(LedEffect),
+    Underglow(UnderglowBytes),
+    Battery,
+    Status,
+}
+
+/// LED commands, picked up by `StatusLedController` on its next poll
+pub(crate) static CONSOLE_CHANNEL: Channel<CriticalSectionRawMutex, ConsoleCommand, 4> =
+    Channel::new();
+
+/// Replaces `defmt_rtt`: defmt goes out on up-channel 0, commands come in on down-channel 0.
+/// Has to run before anything logs.
+pub(crate) fn init_rtt() -> DownChannel {
+    let channels = rtt_init! {
+        up: {
+            0: { size: 1024, name: "defmt" }
+        }
+        down: {
+            0: { size: 64, name: "Console" }
+        }
+    };
+    set_defmt_channel(channels.up.0);
+    channels.down.0
+}
+
+/// `<r> <g> <b> <first> <end>`, all 0-255
+fn parse_underglow<'a>(first: &str, rest: impl Iterator<Item = &'a str>) -> Option<UnderglowBytes> {
+    let mut bytes = [0u8; 5];
+    let mut words = core::iter::once(first).chain(rest);
+    for byte in bytes.iter_mut() {
+        *byte = words.next()?.parse().ok()?;
+    }
+    words.next().is_none().then_some(bytes)
+}
+
+async fn dispatch(line: &str) {
+    let mut words = line.split_whitespace();
+    let command = match (words.next(), words.next()) {
+        (Some("brightness"), Some(value)) => match value.parse::<u8>() {
+            Ok(value) => ConsoleCommand::Brightness(value),
+            Err(_) => {
+                warn!("brightness takes 0-255");
+                return;
+            }
+        },
+        (Some("effect"), Some("status")) => ConsoleCommand::Effect(LedEffect::Status),
+        (Some("effect"), Some("party")) => ConsoleCommand::Effect(LedEffect::Party),
+        (Some("effect"), Some("scanner")) => ConsoleCommand::Effect(LedEffect::Scanner),
+        (Some("underglow"), Some("off")) => ConsoleCommand::Underglow([0; 5]),
+        (Some("underglow"), Some(first)) => match parse_underglow(first, words) {
+            Some(bytes) => ConsoleCommand::Underglow(bytes),
+            None => {
+                warn!("underglow takes <r> <g> <b> <first> <end> or off");
+                return;
+            }
+        },
+        (Some("battery"), None) => ConsoleCommand::Battery,
+        (Some("status"), None) => ConsoleCommand::Status,
+        (Some("storage-reset"), None) => {
+            warn!("Erasing settings - reboot to load defaults");
+            SETTINGS_CHANNEL.send(SettingsUpdate::EraseAll).await;
+            return;
+        }
+        _ => {
+            warn!("Unknown command: {}", line);
+            return;
+        }
+    };
+    CONSOLE_CHANNEL.send(command).await;
+}
+
+#[embassy_executor::task]
+pub(crate) async fn debug_console_task(mut input: DownChannel) -> ! {
+    let mut line = [0u8; CONSOLE_LINE_LEN];
+    let mut len = 0;
+    loop {
+        let mut byte = [0u8; 1];
+        // RTT reads don't wait, so poll - slow enough to not matter, fast enough to feel live
+        if input.read(&mut byte) == 0 {
+            Timer::after_millis(CONSOLE_POLL_MS).await;
+            continue;
+        }
+        match byte[0] {
+            b'\n' | b'\r' => {
+                if len > 0 {
+                    match core::str::from_utf8(&line[..len]) {
+                        Ok(text) => {
+                            info!("> {}", text);
+                            dispatch(text).await;
+                        }
+                        Err(_) => warn!("Console input isn't valid UTF-8"),
+                    }
+                }
+                len = 0;
+            }
+            other if len < CONSOLE_LINE_LEN => {
+                line[len] = other;
+                len += 1;
+            }
+            _ => {} // Line too long, the rest gets dropped
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use defmt::{info, warn};
+use embassy_nrf::gpio::{Input, Output};
+use embassy_nrf::spim::Spim;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::Text;
+#[cfg(feature = "ble")]
+use rmk::event::BleProfileChangeEvent;
+use rmk::event::{
+    BatteryStateEvent, ConnectionChangeEvent, ConnectionType, KeyEvent, LayerChangeEvent,
+};
+use rmk::macros::controller;
+
+use crate::led::BATTERY_UNKNOWN;
+
+// 2.13" 250x122 SSD1680 panel (WeAct / Waveshare V4 style), drawn in landscape.
+// The panel's own X axis is the short side, so everything is rotated 90° on the way in.
+const WIDTH: usize = 250;
+const HEIGHT: usize = 122;
+const PANEL_ROW_BYTES: usize = HEIGHT.div_ceil(8);
+const FRAME_BYTES: usize = PANEL_ROW_BYTES * WIDTH;
+
+// Refresh strategy:
+// - A state change only marks the screen stale. It's redrawn once no key was pressed for
+//   REFRESH_IDLE_TIME, so a refresh (visible, and blocking SPI for the transfer) never lands
+//   mid-typing, and a burst of layer switches ends up as one refresh of the final state.
+// - Refreshes are at least MIN_REFRESH_INTERVAL apart. Partial refreshes (no black/white
+//   flashing, ~0.3s) are used in between, every FULL_REFRESH_EVERY-th one is a full refresh
+//   (~2s, flashes) to clear the ghosting partials leave behind.
+// - Between refreshes the controller is in deep sleep, e-paper keeps the image without power.
+const REFRESH_IDLE_TIME: Duration = Duration::from_secs(3);
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+const FULL_REFRESH_EVERY: u8 = 10;
+// A full refresh takes ~2s, anything much longer means the panel isn't answering
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 1 bit per pixel in panel RAM order, bit set = white
+struct FrameBuffer([u8; FRAME_BYTES]);
+
+impl FrameBuffer {
+    fn clear(&mut self) {
+        self.0 = [0xFF; FRAME_BYTES];
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) else {
+                continue;
+            };
+            if x >= WIDTH || y >= HEIGHT {
+                continue;
+            }
+            // Landscape x runs along the gate lines, landscape y backwards along the sources
+            let panel_x = HEIGHT - 1 - y;
+            let index = x * PANEL_ROW_BYTES + panel_x / 8;
+            let bit = 0x80 >> (panel_x % 8);
+            match color {
+                BinaryColor::On => self.0[index] &= !bit,
+                BinaryColor::Off => self.0[index] |= bit,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Refresh {
+    Full,
+    Partial,
+}
+
+/// Minimal async SSD1680 driver, just enough to push whole frames.
+/// BUSY is awaited rather than polled, so key scanning keeps running during a refresh.
+pub struct Ssd1680<'d> {
+    spim: Spim<'d>,
+    cs: Output<'d>,
+    dc: Output<'d>,
+    rst: Output<'d>,
+    busy: Input<'d>,
+}
+
+impl<'d> Ssd1680<'d> {
+    pub fn new(
+        spim: Spim<'d>,
+        cs: Output<'d>,
+        dc: Output<'d>,
+        rst: Output<'d>,
+        busy: Input<'d>,
+    ) -> Self {
+        Self {
+            spim,
+            cs,
+            dc,
+            rst,
+            busy,
+        }
+    }
+
+    /// Command plus up to 4 parameter bytes
+    async fn command(&mut self, command: u8, params: &[u8]) {
+        // EasyDMA only reads from RAM, literals would be in flash
+        let mut buf = [0u8; 4];
+        buf[..params.len()].copy_from_slice(params);
+        self.cs.set_low();
+        self.dc.set_low();
+        let _ = self.spim.write(&[command]).await;
+        if !params.is_empty() {
+            self.dc.set_high();
+            let _ = self.spim.write(&buf[..params.len()]).await;
+        }
+        self.cs.set_high();
+    }
+
+    async fn write_ram(&mut self, command: u8, frame: &[u8; FRAME_BYTES]) {
+        self.command(command, &[]).await;
+        self.cs.set_low();
+        self.dc.set_high();
+        let _ = self.spim.write(frame).await;
+        self.cs.set_high();
+    }
+
+    async fn wait_idle(&mut self) {
+        if with_timeout(BUSY_TIMEOUT, self.busy.wait_for_low())
+            .await
+            .is_err()
+        {
+            warn!("E-ink BUSY stuck high - is the panel connected?");
+        }
+    }
+
+    /// Hardware reset and panel setup, also the only way out of deep sleep
+    async fn wake(&mut self) {
+        self.rst.set_low();
+        Timer::after_millis(10).await;
+        self.rst.set_high();
+        Timer::after_millis(10).await;
+        self.wait_idle().await;
+
+        self.command(0x12, &[]).await; // Software reset
+        self.wait_idle().await;
+        let last_gate = (WIDTH - 1) as u16;
+        self.command(0x01, &[last_gate as u8, (last_gate >> 8) as u8, 0x00])
+            .await; // Driver output control
+        self.command(0x11, &[0x03]).await; // Data entry: X and Y increment
+        self.command(0x44, &[0x00, (PANEL_ROW_BYTES - 1) as u8])
+            .await; // RAM X range
+        self.command(0x45, &[0x00, 0x00, last_gate as u8, (last_gate >> 8) as u8])
+            .await; // RAM Y range
+        self.command(0x3C, &[0x05]).await; // Border waveform
+        self.command(0x18, &[0x80]).await; // Internal temperature sensor
+        self.command(0x4E, &[0x00]).await; // RAM X counter
+        self.command(0x4F, &[0x00, 0x00]).await; // RAM Y counter
+        self.wait_idle().await;
+    }
+
+    async fn show(&mut self, frame: &[u8; FRAME_BYTES], refresh: Refresh) {
+        self.wake().await;
+        self.write_ram(0x24, frame).await; // New image
+        match refresh {
+            Refresh::Full => {
+                // The previous-image RAM becomes the base the next partials diff against
+                self.write_ram(0x26, frame).await;
+                self.command(0x22, &[0xF7]).await;
+            }
+            Refresh::Partial => {
+                self.command(0x3C, &[0x80]).await; // Leave the border alone
+                self.command(0x22, &[0xFF]).await;
+            }
+        }
+        self.command(0x20, &[]).await; // Master activation
+        self.wait_idle().await;
+        if refresh == Refresh::Partial {
+            // What's on screen now is the base for the next partial
+            self.write_ram(0x26, frame).await;
+        }
+        self.command(0x10, &[0x01]).await; // Deep sleep, RAM is kept
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct StatusState {
+    battery: u8,
+    profile: u8,
+    layer: u8,
+    usb: bool,
+}
+
+/// "<label><value><suffix>" without pulling in a formatter
+fn format_line<'a>(buf: &'a mut [u8; 16], label: &str, value: u8, suffix: &str) -> &'a str {
+    let mut len = 0;
+    let mut push = |bytes: &[u8]| {
+        let n = bytes.len().min(buf.len() - len);
+        buf[len..len + n].copy_from_slice(&bytes[..n]);
+        len += n;
+    };
+    push(label.as_bytes());
+    let digits = [value / 100, value / 10 % 10, value % 10];
+    let skip = if value >= 100 {
+        0
+    } else if value >= 10 {
+        1
+    } else {
+        2
+    };
+    for digit in &digits[skip..] {
+        push(&[b'0' + digit]);
+    }
+    push(suffix.as_bytes());
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+/// Battery %, active output/profile and layer on an e-paper panel (`--features eink`).
+/// Runs on its own SPIM, see main.rs.
+#[cfg_attr(
+    feature = "ble",
+    controller(subscribe = [BatteryStateEvent, BleProfileChangeEvent, ConnectionChangeEvent, LayerChangeEvent, KeyEvent], poll_interval = 1000)
+)]
+#[cfg_attr(
+    not(feature = "ble"),
+    controller(subscribe = [BatteryStateEvent, ConnectionChangeEvent, LayerChangeEvent, KeyEvent], poll_interval = 1000)
+)]
+pub struct EinkStatusController<'d> {
+    panel: Ssd1680<'d>,
+    frame: FrameBuffer,
+    state: StatusState,
+    // What's on the panel, None until the first refresh
+    shown: Option<StatusState>,
+    last_key: Instant,
+    last_refresh: Option<Instant>,
+    partials_since_full: u8,
+}
+
+impl<'d> EinkStatusController<'d> {
+    pub fn new(panel: Ssd1680<'d>, battery_percentage: u8) -> Self {
+        Self {
+            panel,
+            frame: FrameBuffer([0xFF; FRAME_BYTES]),
+            state: StatusState {
+                battery: battery_percentage,
+                profile: 0,
+                layer: 0,
+                usb: !cfg!(feature = "ble"),
+            },
+            shown: None,
+            last_key: Instant::now(),
+            last_refresh: None,
+            partials_since_full: 0,
+        }
+    }
+
+    fn draw(&mut self) {
+        let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        let mut buf = [0u8; 16];
+        self.frame.clear();
+        let battery = if self.state.battery == BATTERY_UNKNOWN {
+            "Battery ?"
+        } else {
+            format_line(&mut buf, "Battery ", self.state.battery, "%")
+        };
+        let _ = Text::new(battery, Point::new(8, 28), style).draw(&mut self.frame);
+        let mut buf = [0u8; 16];
+        let output = if self.state.usb {
+            "USB"
+        } else {
+            format_line(&mut buf, "BLE ", self.state.profile + 1, "")
+        };
+        let _ = Text::new(output, Point::new(8, 66), style).draw(&mut self.frame);
+        let mut buf = [0u8; 16];
+        let layer = format_line(&mut buf, "Layer ", self.state.layer, "");
+        let _ = Text::new(layer, Point::new(8, 104), style).draw(&mut self.frame);
+    }
+
+    async fn refresh(&mut self) {
+        let refresh = if self.shown.is_none() || self.partials_since_full + 1 >= FULL_REFRESH_EVERY
+        {
+            self.partials_since_full = 0;
+            Refresh::Full
+        } else {
+            self.partials_since_full += 1;
+            Refresh::Partial
+        };
+        let kind = match refresh {
+            Refresh::Full => "full",
+            Refresh::Partial => "partial",
+        };
+        info!("E-ink refresh ({})", kind);
+        self.draw();
+        self.panel.show(&self.frame.0, refresh).await;
+        self.shown = Some(self.state);
+        self.last_refresh = Some(Instant::now());
+    }
+
+    async fn on_battery_state_event(&mut self, event: BatteryStateEvent) {
+        match event {
+            BatteryStateEvent::Normal(percentage) => self.state.battery = percentage,
+            BatteryStateEvent::Charged => self.state.battery = 100,
+            _ => {}
+        }
+    }
+
+    #[cfg(feature = "ble")]
+    async fn on_ble_profile_change_event(&mut self, event: BleProfileChangeEvent) {
+        self.state.profile = event.profile;
+    }
+
+    async fn on_connection_change_event(&mut self, event: ConnectionChangeEvent) {
+        self.state.usb = matches!(event.connection_type, ConnectionType::Usb);
+    }
+
+    async fn on_layer_change_event(&mut self, event: LayerChangeEvent) {
+        self.state.layer = event.layer;
+    }
+
+    async fn on_key_event(&mut self, _event: KeyEvent) {
+        self.last_key = Instant::now();
+    }
+
+    async fn poll(&mut self) {
+        if self.shown == Some(self.state) || self.last_key.elapsed() < REFRESH_IDLE_TIME {
+            return;
+        }
+        if self
+            .last_refresh
+            .is_some_and(|at| at.elapsed() < MIN_REFRESH_INTERVAL)
+        {
+            return;
+        }
+        self.refresh().await;
+    }
+}
@@ -0,0 +1,3 @@
+// Status displays, each behind its own feature
+#[cfg(feature = "eink")]
+pub mod eink;
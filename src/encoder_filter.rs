@@ -0,0 +1,285 @@
+use core::sync::atomic::Ordering;
+
+use embassy_nrf::gpio::Input;
+use embassy_time::{Instant, Timer};
+use rmk::channel::KEY_EVENT_CHANNEL;
+use rmk::embassy_futures::select::{Either, select};
+use rmk::event::KeyboardEvent;
+use rmk::input_device::Runnable;
+use rmk::input_device::rotary_encoder::Direction;
+
+use crate::keyboard_lock::KEYBOARD_LOCKED;
+use crate::volume::VOLUME_DETENT_STEPS;
+
+// At-rest jitter filter for the rotary encoder. An encoder parked between detents (or on a
+// worn one) can flicker an A/B line from noise or vibration, and RMK turns that into a volume
+// step, usually followed right away by one in the opposite direction as the line flicks back.
+//
+// Mid-turn nothing is filtered: a step in the same direction as one emitted within
+// ENCODER_REST_AFTER_MS goes straight out. The first step from rest (or a reversal) is held
+// back instead, and goes out either
+// - once ENCODER_DWELL_MS pass without a step back the other way. A genuine slow click lands
+//   in the next detent and stays there, jitter flicks back within a few ms. So a single click
+//   from rest costs ENCODER_DWELL_MS of latency, nothing else does.
+// - or right away, together with the next step, if that one is in the same direction - a
+//   confirmed sequence, i.e. the knob is being turned.
+// A step back inside the dwell drops both. A real quick back-and-forth inside 40ms is gone as
+// well, but nobody turns a knob one click each way that fast.
+//
+// RMK's `RotaryEncoder` (ca38784) sends its steps straight from `read_event` with nothing in
+// between, so `FilteredEncoder` below takes its place: it decodes the quadrature itself and
+// hands each step to the filter, then sends what comes out the way RMK's encoder does.
+pub(crate) const ENCODER_DWELL_MS: u64 = 40;
+// No step for this long and the encoder counts as resting again
+pub(crate) const ENCODER_REST_AFTER_MS: u64 = 250;
+
+const _: () = assert!(ENCODER_DWELL_MS < ENCODER_REST_AFTER_MS);
+
+#[derive(Clone, Copy)]
+pub(crate) enum Turn {
+    Clockwise,
+    CounterClockwise,
+}
+
+const fn same(a: Turn, b: Turn) -> bool {
+    matches!(
+        (a, b),
+        (Turn::Clockwise, Turn::Clockwise) | (Turn::CounterClockwise, Turn::CounterClockwise)
+    )
+}
+
+/// Filter state for one encoder, times are ms since boot
+pub(crate) struct RestFilter {
+    // Step from rest waiting for its dwell, and when it came in
+    pending: Option<(Turn, u64)>,
+    // Last step that went out, and when
+    last: Option<(Turn, u64)>,
+}
+
+impl RestFilter {
+    pub(crate) const fn new() -> Self {
+        Self {
+            pending: None,
+            last: None,
+        }
+    }
+
+    const fn emit(&mut self, turn: Turn, now_ms: u64, count: u8) -> u8 {
+        self.pending = None;
+        self.last = Some((turn, now_ms));
+        count
+    }
+
+    /// Feeds a step, returns how many steps of `turn` to send now (0, 1 or 2).
+    /// `poll` has to run at `deadline()`, a pending step that's past it is dropped here.
+    pub(crate) const fn step(&mut self, turn: Turn, now_ms: u64) -> u8 {
+        if let Some((last, at)) = self.last
+            && same(last, turn)
+            && now_ms - at < ENCODER_REST_AFTER_MS
+        {
+            return self.emit(turn, now_ms, 1);
+        }
+        match self.pending {
+            Some((pending, at)) if now_ms - at < ENCODER_DWELL_MS => {
+                if same(pending, turn) {
+                    self.emit(turn, now_ms, 2)
+                } else {
+                    // Flicked straight back, that was jitter
+                    self.pending = None;
+                    0
+                }
+            }
+            _ => {
+                self.pending = Some((turn, now_ms));
+                0
+            }
+        }
+    }
+
+    /// When `poll` has to run next, None while nothing is pending
+    pub(crate) const fn deadline(&self) -> Option<u64> {
+        match self.pending {
+            Some((_, at)) => Some(at + ENCODER_DWELL_MS),
+            None => None,
+        }
+    }
+
+    /// The pending step once it has stayed put for the dwell
+    pub(crate) const fn poll(&mut self, now_ms: u64) -> Option<Turn> {
+        match self.pending {
+            Some((turn, at)) if now_ms - at >= ENCODER_DWELL_MS => {
+                self.emit(turn, now_ms, 1);
+                Some(turn)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Jitter at rest: one step each way inside the dwell, nothing goes out
+const fn jitter_dropped() -> bool {
+    let mut filter = RestFilter::new();
+    filter.step(Turn::Clockwise, 1000) == 0
+        && filter.step(Turn::CounterClockwise, 1005) == 0
+        && filter.poll(1100).is_none()
+}
+
+// A single slow click from rest goes out after the dwell
+const fn slow_click_sent() -> bool {
+    let mut filter = RestFilter::new();
+    filter.step(Turn::Clockwise, 1000) == 0
+        && filter.poll(1000 + ENCODER_DWELL_MS - 1).is_none()
+        && matches!(filter.poll(1000 + ENCODER_DWELL_MS), Some(Turn::Clockwise))
+        // The next click of the same slow turn isn't held back again
+        && filter.step(Turn::Clockwise, 1200) == 1
+}
+
+// A fast turn confirms itself with the second step, no dwell
+const fn fast_turn_sent() -> bool {
+    let mut filter = RestFilter::new();
+    filter.step(Turn::CounterClockwise, 0) == 0
+        && filter.step(Turn::CounterClockwise, 10) == 2
+        && filter.step(Turn::CounterClockwise, 20) == 1
+}
+
+// After a pause the encoder is resting again, the next step waits for its dwell
+const fn rests_again() -> bool {
+    let mut filter = RestFilter::new();
+    filter.step(Turn::Clockwise, 0) == 0
+        && filter.step(Turn::Clockwise, 10) == 2
+        && filter.step(Turn::Clockwise, 10 + ENCODER_REST_AFTER_MS) == 0
+}
+
+const _: () = assert!(jitter_dropped());
+const _: () = assert!(slow_click_sent());
+const _: () = assert!(fast_turn_sent());
+const _: () = assert!(rests_again());
+
+// Quadrature steps by (previous A/B << 2 | current A/B): +1 one way, -1 the other. No change
+// and both lines flipping at once (a bounce, or a missed edge) are 0.
+const QUADRATURE: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+const fn quadrature_step(previous: u8, current: u8) -> i8 {
+    QUADRATURE[((previous << 2) | current) as usize]
+}
+
+// One full cycle each way is 4 steps, a double flip counts for nothing
+const fn cycle(lines: [u8; 5]) -> i8 {
+    let mut total = 0;
+    let mut i = 1;
+    while i < lines.len() {
+        total += quadrature_step(lines[i - 1], lines[i]);
+        i += 1;
+    }
+    total
+}
+const _: () = assert!(cycle([0b00, 0b10, 0b11, 0b01, 0b00]) == 4);
+const _: () = assert!(cycle([0b00, 0b01, 0b11, 0b10, 0b00]) == -4);
+const _: () = assert!(quadrature_step(0b00, 0b11) == 0 && quadrature_step(0b01, 0b10) == 0);
+
+/// The rotary encoder, in place of RMK's `RotaryEncoder`: every `resolution` quadrature steps
+/// are one step for the rest filter, and what it lets through goes out as a press + release
+/// of encoder 0 on `KEY_EVENT_CHANNEL`, the same events RMK's encoder sends - VOLUME_DETENT_STEPS
+/// of them per step (volume.rs). Turning it does nothing while the keyboard is locked
+/// (keyboard_lock.rs), like the matrix.
+pub(crate) struct FilteredEncoder<'d> {
+    pin_a: Input<'d>,
+    pin_b: Input<'d>,
+    resolution: i8,
+    reverse: bool,
+    // A/B as last read, and the quadrature steps since the last encoder step
+    lines: u8,
+    count: i8,
+    filter: RestFilter,
+}
+
+impl<'d> FilteredEncoder<'d> {
+    pub(crate) fn new(pin_a: Input<'d>, pin_b: Input<'d>, resolution: u8, reverse: bool) -> Self {
+        let mut encoder = Self {
+            pin_a,
+            pin_b,
+            resolution: resolution as i8,
+            reverse,
+            lines: 0,
+            count: 0,
+            filter: RestFilter::new(),
+        };
+        encoder.lines = encoder.read_lines();
+        encoder
+    }
+
+    fn read_lines(&self) -> u8 {
+        ((self.pin_a.is_high() as u8) << 1) | self.pin_b.is_high() as u8
+    }
+
+    /// Picks up the current A/B, returns a step once `resolution` quadrature steps add up
+    fn decode(&mut self) -> Option<Turn> {
+        let lines = self.read_lines();
+        self.count += quadrature_step(self.lines, lines);
+        self.lines = lines;
+        if self.count.abs() < self.resolution {
+            return None;
+        }
+        let clockwise = (self.count > 0) != self.reverse;
+        self.count = 0;
+        Some(if clockwise {
+            Turn::Clockwise
+        } else {
+            Turn::CounterClockwise
+        })
+    }
+
+    async fn send(turn: Turn) {
+        if KEYBOARD_LOCKED.load(Ordering::Relaxed) {
+            return;
+        }
+        let direction = match turn {
+            Turn::Clockwise => Direction::Clockwise,
+            Turn::CounterClockwise => Direction::CounterClockwise,
+        };
+        for _ in 0..VOLUME_DETENT_STEPS.load(Ordering::Relaxed) {
+            for pressed in [true, false] {
+                KEY_EVENT_CHANNEL
+                    .send(KeyboardEvent::rotary_encoder(0, direction, pressed))
+                    .await;
+            }
+        }
+    }
+}
+
+// Runs from run_all! like the RMK devices
+impl Runnable for FilteredEncoder<'_> {
+    async fn run(&mut self) {
+        loop {
+            let deadline = self.filter.deadline();
+            let edge = select(
+                self.pin_a.wait_for_any_edge(),
+                self.pin_b.wait_for_any_edge(),
+            );
+            // A pending step needs its poll at the deadline, edge or not
+            let dwell_over = match deadline {
+                Some(deadline) => {
+                    let timer = Timer::at(Instant::from_millis(deadline));
+                    matches!(select(edge, timer).await, Either::Second(_))
+                }
+                None => {
+                    edge.await;
+                    false
+                }
+            };
+            let now_ms = Instant::now().as_millis();
+            if dwell_over {
+                if let Some(turn) = self.filter.poll(now_ms) {
+                    Self::send(turn).await;
+                }
+                continue;
+            }
+            let Some(turn) = self.decode() else {
+                continue;
+            };
+            for _ in 0..self.filter.step(turn, now_ms) {
+                Self::send(turn).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use defmt::{info, panic};
+
+use crate::settings::{SETTINGS_NUM_SECTORS, SETTINGS_SECTOR_SIZE, SETTINGS_START_ADDR};
+
+// nRF52840 flash geometry, with the Adafruit bootloader:
+//   0x00000 - 0x01000  MBR
+//   0x01000 - ...      app (includes the softdevice controller, it's linked in as a library)
+//   0x70000 - 0x7C000  RMK storage (12 sectors), USB-only builds
+//   0xA0000 - 0xAC000  RMK storage (12 sectors), BLE builds
+//   0xAC000 - 0xAE000  our settings (see settings.rs)
+//   0xF4000 - 0x100000 bootloader + its settings, never touch
+pub(crate) const FLASH_SIZE: u32 = 1024 * 1024;
+pub(crate) const FLASH_SECTOR_SIZE: u32 = 4096;
+pub(crate) const BOOTLOADER_START_ADDR: u32 = 0xF4000;
+
+// Without the softdevice controller the image is small enough to start storage lower.
+// Switching a board between BLE and USB-only builds loses RMK's saved keymap and bonds.
+#[cfg(feature = "ble")]
+pub(crate) const RMK_STORAGE_START_ADDR: u32 = 0xA0000;
+#[cfg(not(feature = "ble"))]
+pub(crate) const RMK_STORAGE_START_ADDR: u32 = 0x70000;
+pub(crate) const RMK_STORAGE_NUM_SECTORS: u32 = 12;
+
+unsafe extern "C" {
+    // From cortex-m-rt's link.x - .data is stored in flash right after the code and rodata
+    static __sidata: u32;
+    static __sdata: u32;
+    static __edata: u32;
+}
+
+/// First flash address after the firmware image
+fn image_end() -> u32 {
+    // Only the addresses of the linker symbols are used, never their values
+    let data_len = &raw const __edata as u32 - &raw const __sdata as u32;
+    &raw const __sidata as u32 + data_len
+}
+
+fn validate_region(name: &str, start: u32, num_sectors: u32, image_end: u32) {
+    let end = start + num_sectors * FLASH_SECTOR_SIZE;
+    if !start.is_multiple_of(FLASH_SECTOR_SIZE) {
+        panic!(
+            "{} storage start {=u32:#x} isn't sector aligned",
+            name, start
+        );
+    }
+    if num_sectors < 2 {
+        panic!(
+            "{} storage needs at least 2 sectors, got {}",
+            name, num_sectors
+        );
+    }
+    if start < image_end {
+        panic!(
+            "{} storage at {=u32:#x} overlaps the firmware image (ends at {=u32:#x})",
+            name, start, image_end
+        );
+    }
+    if end > BOOTLOADER_START_ADDR || end > FLASH_SIZE {
+        panic!(
+            "{} storage {=u32:#x}-{=u32:#x} runs into the bootloader at {=u32:#x}",
+            name, start, end, BOOTLOADER_START_ADDR
+        );
+    }
+}
+
+/// Checks both storage regions against the firmware image, the bootloader and each other.
+/// Panics at boot with a readable message instead of silently corrupting code or the bootloader.
+pub(crate) fn validate() {
+    let image_end = image_end();
+    validate_region(
+        "RMK",
+        RMK_STORAGE_START_ADDR,
+        RMK_STORAGE_NUM_SECTORS,
+        image_end,
+    );
+    validate_region(
+        "Settings",
+        SETTINGS_START_ADDR,
+        SETTINGS_NUM_SECTORS,
+        image_end,
+    );
+
+    let rmk_end = RMK_STORAGE_START_ADDR + RMK_STORAGE_NUM_SECTORS * FLASH_SECTOR_SIZE;
+    let settings_end = SETTINGS_START_ADDR + SETTINGS_NUM_SECTORS * SETTINGS_SECTOR_SIZE;
+    if SETTINGS_START_ADDR < rmk_end && RMK_STORAGE_START_ADDR < settings_end {
+        panic!(
+            "Settings {=u32:#x}-{=u32:#x} overlap RMK storage {=u32:#x}-{=u32:#x}",
+            SETTINGS_START_ADDR, settings_end, RMK_STORAGE_START_ADDR, rmk_end
+        );
+    }
+    info!(
+        "Flash layout OK: image ends at {=u32:#x}, {} KB free before storage",
+        image_end,
+        (RMK_STORAGE_START_ADDR.min(SETTINGS_START_ADDR) - image_end) / 1024
+    );
+}
+
+// The sector size is fixed by the chip, settings.rs only repeats it for its own range math
+const _: () = assert!(SETTINGS_SECTOR_SIZE == FLASH_SECTOR_SIZE);
@@ -0,0 +1,79 @@
+use core::sync::atomic::Ordering;
+
+use defmt::info;
+use embassy_time::Instant;
+#[cfg(feature = "ble")]
+use rmk::ble::BleState;
+#[cfg(feature = "ble")]
+use rmk::event::BleStateChangeEvent;
+use rmk::event::{BatteryStateEvent, KeyEvent};
+use rmk::macros::controller;
+
+use crate::led::{BATTERY_UNKNOWN, LEDS_ASLEEP};
+use crate::vbus::VBUS_POWERED;
+
+// Liveness heartbeat for long sessions (`dev` feature only). It runs as a controller in the
+// same run_all! as everything else, so a missing line means that join stopped being polled -
+// a stuck await in one of its futures, or the whole executor is wedged. One short line a
+// minute keeps the RTT traffic negligible.
+
+/// Logs uptime, BLE state, battery, key events and LED/VBUS state once a minute
+#[cfg_attr(
+    feature = "ble",
+    controller(subscribe = [BleStateChangeEvent, BatteryStateEvent, KeyEvent], poll_interval = 60_000)
+)]
+#[cfg_attr(
+    not(feature = "ble"),
+    controller(subscribe = [BatteryStateEvent, KeyEvent], poll_interval = 60_000)
+)]
+pub struct Heartbeat {
+    // 0 = none, 1 = advertising, 2 = connected, matches BleState's order
+    ble_state: u8,
+    battery_percentage: u8,
+    // Key events since the last beat, presses and releases
+    key_events: u16,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self {
+            ble_state: 0,
+            battery_percentage: BATTERY_UNKNOWN,
+            key_events: 0,
+        }
+    }
+
+    #[cfg(feature = "ble")]
+    async fn on_ble_state_change_event(&mut self, event: BleStateChangeEvent) {
+        self.ble_state = match event.state {
+            BleState::None => 0,
+            BleState::Advertising => 1,
+            BleState::Connected => 2,
+        };
+    }
+
+    async fn on_battery_state_event(&mut self, event: BatteryStateEvent) {
+        match event {
+            BatteryStateEvent::Normal(percentage) => self.battery_percentage = percentage,
+            BatteryStateEvent::Charged => self.battery_percentage = 100,
+            BatteryStateEvent::Charging | BatteryStateEvent::NotAvailable => {}
+        }
+    }
+
+    async fn on_key_event(&mut self, _event: KeyEvent) {
+        self.key_events = self.key_events.saturating_add(1);
+    }
+
+    async fn poll(&mut self) {
+        info!(
+            "HB {}s ble={} bat={} keys={} asleep={} vbus={}",
+            Instant::now().as_secs(),
+            self.ble_state,
+            self.battery_percentage,
+            self.key_events,
+            LEDS_ASLEEP.load(Ordering::Relaxed),
+            VBUS_POWERED.load(Ordering::Relaxed)
+        );
+        self.key_events = 0;
+    }
+}
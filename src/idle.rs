@@ -0,0 +1,120 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::info;
+use embassy_time::{Duration, Instant};
+#[cfg(feature = "ble")]
+use rmk::ble::BleState;
+#[cfg(feature = "ble")]
+use rmk::event::BleStateChangeEvent;
+use rmk::event::KeyEvent;
+use rmk::macros::controller;
+
+// How long a BLE link sits with no key activity before it counts as idle.
+//
+// Dropping the link there would save the connection events until the next keypress, but
+// RMK (ca38784) keeps the connection inside its own BLE task and has no public call to
+// disconnect it from outside, so there's no idle disconnect. Only the idle time is tracked,
+// and logged once per idle period. With a disconnect hook in RMK the call would go in `poll`:
+// RMK goes back to advertising on its own once a link drops, so a keypress after that is
+// enough for the bonded host to reconnect.
+pub(crate) const BLE_IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+// Low-power idle: still connected, but no key activity for LOW_POWER_IDLE_TIMEOUT. The
+// background work in this tree backs off:
+// - BatteryAdc samples the battery (and light sensor) every minute instead of every 12s
+//   (adc.rs). Joystick builds keep RMK's NrfAdc, which has no rate to change
+// - thermal_task skips its temperature reads, but only while the LED rail is actually off -
+//   a locked color or a running focus timer keeps the LEDs lit through idle and sleep, and
+//   that's exactly when the rail is loaded
+// - the matrix isn't scanned anyway while every key is up (`async_matrix`), and
+//   StatusLedController has its effects frozen while the LEDs are asleep
+// The first key event leaves it and LOW_POWER_IDLE is cleared, that key itself isn't held
+// back.
+// There's no low-power connection interval to go with it, and so no constant for one: the
+// connection parameters stay whatever RMK (ca38784) negotiated, its BLE task owns the
+// connection and takes no parameter requests from outside.
+// BLE only, USB never gets here.
+pub(crate) const LOW_POWER_IDLE_ENABLED: bool = true;
+pub(crate) const LOW_POWER_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Set while in low-power idle, read by thermal_task and BatteryAdc
+pub(crate) static LOW_POWER_IDLE: AtomicBool = AtomicBool::new(false);
+
+/// Tracks key activity while connected over BLE, see above. In USB-only builds it never sees
+/// a connection, so it never logs or goes low-power.
+#[cfg_attr(
+    feature = "ble",
+    controller(subscribe = [KeyEvent, BleStateChangeEvent], poll_interval = 10000)
+)]
+#[cfg_attr(
+    not(feature = "ble"),
+    controller(subscribe = [KeyEvent], poll_interval = 10000)
+)]
+pub struct IdleMonitor {
+    last_activity: Instant,
+    ble_connected: bool,
+    idle_logged: bool,
+    low_power: bool,
+}
+
+impl IdleMonitor {
+    pub fn new() -> Self {
+        Self {
+            last_activity: Instant::now(),
+            ble_connected: false,
+            idle_logged: false,
+            low_power: false,
+        }
+    }
+
+    fn set_low_power(&mut self, low_power: bool) {
+        if low_power == self.low_power {
+            return;
+        }
+        self.low_power = low_power;
+        LOW_POWER_IDLE.store(low_power, Ordering::Relaxed);
+        if low_power {
+            info!(
+                "No key activity for {}s - low-power idle",
+                LOW_POWER_IDLE_TIMEOUT.as_secs()
+            );
+        } else {
+            info!("Leaving low-power idle");
+        }
+    }
+
+    async fn on_key_event(&mut self, _event: KeyEvent) {
+        self.last_activity = Instant::now();
+        self.idle_logged = false;
+        self.set_low_power(false);
+    }
+
+    #[cfg(feature = "ble")]
+    async fn on_ble_state_change_event(&mut self, event: BleStateChangeEvent) {
+        self.ble_connected = matches!(event.state, BleState::Connected);
+        // Idle time counts from the (re)connection, not from the last key before it
+        self.last_activity = Instant::now();
+        self.idle_logged = false;
+        // A new link starts out on whatever the host picks
+        self.set_low_power(false);
+    }
+
+    async fn poll(&mut self) {
+        if LOW_POWER_IDLE_ENABLED
+            && self.ble_connected
+            && self.last_activity.elapsed() >= LOW_POWER_IDLE_TIMEOUT
+        {
+            self.set_low_power(true);
+        }
+        if !self.ble_connected || self.idle_logged {
+            return;
+        }
+        if self.last_activity.elapsed() >= BLE_IDLE_TIMEOUT {
+            info!(
+                "No key activity for {}s on BLE, link stays up",
+                BLE_IDLE_TIMEOUT.as_secs()
+            );
+            self.idle_logged = true;
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use defmt::{Format, error};
+#[cfg(feature = "ble")]
+use nrf_sdc::{self as sdc, mpsl};
+
+/// A bring-up step that failed before the keyboard could start.
+/// Everything here happens before the LED strip is set up, so the log is the only place it shows.
+#[derive(Format)]
+pub(crate) enum InitError {
+    /// MPSL init - usually the LFCLK not starting, or its interrupts not bound
+    #[cfg(feature = "ble")]
+    Mpsl(mpsl::Error),
+    /// SoftDevice Controller config or enable - usually `sdc::Mem` too small for the config
+    #[cfg(feature = "ble")]
+    Sdc(sdc::Error),
+    /// The hardware RNG couldn't seed the BLE host's RNG
+    #[cfg(feature = "ble")]
+    RngSeed,
+    /// A background task (named) couldn't be spawned - its pool is already full, so it's
+    /// spawned twice
+    Spawn(&'static str),
+}
+
+impl InitError {
+    fn stage(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "ble")]
+            InitError::Mpsl(_) => "MPSL init",
+            #[cfg(feature = "ble")]
+            InitError::Sdc(_) => "SoftDevice Controller build",
+            #[cfg(feature = "ble")]
+            InitError::RngSeed => "RNG seed",
+            InitError::Spawn(_) => "task spawn",
+        }
+    }
+}
+
+/// Logs which stage failed and why, then stops here for good.
+/// No reset on purpose: these don't fix themselves, and a reset loop would bury the log line.
+pub(crate) fn halt(err: InitError) -> ! {
+    error!("Bring-up failed at {}: {:?}", err.stage(), err);
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
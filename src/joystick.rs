@@ -0,0 +1,46 @@
+use embassy_time::Duration;
+
+// Analog thumbstick (`--features joystick`) for cursor control: X on AIN0 (P0_02), Y on
+// AIN1 (P0_03), each wiper between VDD and GND. Those are the e-ink SPI pins, so the two
+// features don't go together, and neither do this and `ambient-light` (see adc.rs). They're
+// SAADC channels 1 and 2, after the battery on 0.
+//
+// Readings go to RMK's `JoystickProcessor`, which turns them into HID mouse reports and sends
+// them on the same report channel as mouse keys - so the cursor moves over USB or the active
+// BLE profile, whichever the keyboard output is on, with nothing else to set up. Per axis it
+// adds the bias, multiplies by the transform and rounds to multiples of the resolution:
+// - JOYSTICK_CENTER: raw reading with the stick at rest, the bias is its negative. With a
+//   3.3V VDD and the 3.6V full scale a centered wiper reads ~1877. Measure the real one
+//   with the stick left alone, every stick is off by a bit.
+// - JOYSTICK_SENSITIVITY: transform diagonal, bigger = faster cursor. Make one negative to
+//   flip that axis, or swap the rows to swap X and Y.
+// - JOYSTICK_DEAD_ZONE: the resolution. A deflection that scales to less than one step comes
+//   out as 0, so the cursor doesn't drift with a stick that doesn't quite recenter, and
+//   bigger moves come in steps of it.
+// The curve is linear, that's all the processor does.
+pub(crate) const JOYSTICK_CENTER: [i16; 2] = [1877, 1877];
+pub(crate) const JOYSTICK_SENSITIVITY: [i16; 2] = [80, 80];
+pub(crate) const JOYSTICK_DEAD_ZONE: u16 = 6;
+
+pub(crate) const JOYSTICK_BIAS: [i16; 2] = [-JOYSTICK_CENTER[0], -JOYSTICK_CENTER[1]];
+pub(crate) const JOYSTICK_TRANSFORM: [[i16; 2]; 2] =
+    [[JOYSTICK_SENSITIVITY[0], 0], [0, JOYSTICK_SENSITIVITY[1]]];
+
+const _: () = assert!(
+    JOYSTICK_CENTER[0] > 0 && JOYSTICK_CENTER[0] < 4095,
+    "JOYSTICK_CENTER is a 12-bit reading"
+);
+const _: () = assert!(
+    JOYSTICK_CENTER[1] > 0 && JOYSTICK_CENTER[1] < 4095,
+    "JOYSTICK_CENTER is a 12-bit reading"
+);
+const _: () = assert!(
+    JOYSTICK_DEAD_ZONE > 0,
+    "JOYSTICK_DEAD_ZONE of 0 never moves"
+);
+
+// The stick needs fast sampling to feel smooth, and every channel is sampled together, so the
+// battery is read this often too. Once the stick has been left alone for a bit NrfAdc drops
+// to its light sleep interval, which is the usual battery one.
+pub(crate) const JOYSTICK_POLL_INTERVAL: Duration = Duration::from_millis(12);
+pub(crate) const JOYSTICK_IDLE_POLL_INTERVAL: Duration = Duration::from_secs(12);
@@ -0,0 +1,78 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::info;
+
+// Keyboard lock: holding every key of KEYBOARD_LOCK_COMBO at once locks the keyboard, and the
+// same combo unlocks it again. While locked no key press reaches RMK at all, so nothing goes to
+// the host (and no User actions or layer changes happen either), and the profile LED is steady
+// red. Empty = off, which is the default. Positions are (row, col) like UNLOCK_KEYS, e.g.
+// `&[(0, 0), (3, 3)]` for the two opposite corners.
+//
+// RMK's key events go from the matrix through a channel straight into `Keyboard`, with no hook
+// in between that could drop them (controllers only get copies). So the gate sits one step
+// earlier, in the matrix debouncer: a press while locked is reported as never having happened,
+// the same way the tap that wakes the LEDs is swallowed. Releases always go through, so a key
+// held down while locking still gets released on the host.
+//
+// The unlock combo is pressed while locked, so none of it is forwarded. When locking, the key
+// that completes the combo is swallowed, but the ones before it have already been sent - pick
+// keys that are harmless to press on their own.
+//
+// The rotary encoder doesn't go through the matrix, `FilteredEncoder` (encoder_filter.rs)
+// checks KEYBOARD_LOCKED itself and drops its steps while locked.
+pub(crate) const KEYBOARD_LOCK_COMBO: &[(u8, u8)] = &[];
+
+const _: () = assert!(
+    KEYBOARD_LOCK_COMBO.len() <= 16,
+    "KEYBOARD_LOCK_COMBO supports up to 16 keys"
+);
+
+/// Set while the keyboard is locked, read by the debouncer and `StatusLedController`
+pub(crate) static KEYBOARD_LOCKED: AtomicBool = AtomicBool::new(false);
+
+/// Bit of the combo key at (row, col), None if it isn't part of the combo
+const fn combo_bit(row: usize, col: usize) -> Option<u16> {
+    let mut i = 0;
+    while i < KEYBOARD_LOCK_COMBO.len() {
+        let (combo_row, combo_col) = KEYBOARD_LOCK_COMBO[i];
+        if combo_row as usize == row && combo_col as usize == col {
+            return Some(1 << i);
+        }
+        i += 1;
+    }
+    None
+}
+
+// Each combo key has its own bit
+const FULL_COMBO: u16 = ((1u32 << KEYBOARD_LOCK_COMBO.len()) - 1) as u16;
+
+/// Which combo keys are physically down, owned by the matrix debouncer
+pub(crate) struct LockCombo {
+    held: u16,
+}
+
+impl LockCombo {
+    pub(crate) const fn new() -> Self {
+        Self { held: 0 }
+    }
+
+    /// Feeds a debounced change of the key at (row, col), swallowed ones included. Returns
+    /// true if this press completed the combo and toggled the lock - don't forward it then.
+    pub(crate) fn update(&mut self, row: usize, col: usize, pressed: bool) -> bool {
+        let Some(bit) = combo_bit(row, col) else {
+            return false;
+        };
+        if !pressed {
+            self.held &= !bit;
+            return false;
+        }
+        self.held |= bit;
+        if self.held != FULL_COMBO {
+            return false;
+        }
+        let locked = !KEYBOARD_LOCKED.load(Ordering::Relaxed);
+        KEYBOARD_LOCKED.store(locked, Ordering::Relaxed);
+        info!("Keyboard {}", if locked { "locked" } else { "unlocked" });
+        true
+    }
+}
@@ -1,8 +1,20 @@
+use defmt::error;
 use rmk::keyboard_macros::{define_macro_sequences, to_macro_sequence};
 use rmk::morse::Morse;
-use rmk::types::action::{Action, EncoderAction, KeyAction, KeyboardAction, MorseMode, MorseProfile};
+use rmk::types::action::{Action, EncoderAction, KeyAction, MorseMode, MorseProfile};
+use rmk::types::keycode::KeyCode;
 use rmk::types::modifier::ModifierCombination;
-use rmk::{a, encoder, k, layer, lt, td};
+use rmk::{a, encoder, k, layer, lt, osm, td, tg};
+use smart_leds::RGB8;
+
+use crate::led::LedReadout;
+use crate::repeat::HoldRepeatConfig;
+use crate::user_actions::{
+    BATT_CAL, BATT_CHECK, BLE_CLR, BLE_NEXT, BLE1, BLE2, BLE3, BOND_DEL_1, BOND_DEL_2, BOND_DEL_3,
+    BOOTLOADER_REQ, CHARGE_CYCLES_SHOW, EFFECT_NEXT, FOCUS_TIMER, HEATMAP_SHOW, HOLD_TIMEOUT_DOWN,
+    HOLD_TIMEOUT_UP, KEYMAP_DUMP, LED_LOCK, LEDS_TOGGLE, ODOMETER_SHOW, RAW_ADC_SHOW, RUNTIME_SHOW,
+    USB_BLE_SW,
+};
 
 // Modifier combination aliases
 const _LCTRL: ModifierCombination = ModifierCombination::LCTRL;
@@ -14,13 +26,17 @@ const _CTRL_SHIFT_GUI: ModifierCombination = ModifierCombination::new()
     .with_left_shift(true)
     .with_left_gui(true);
 
-// BLE profile actions - User(0-2) for BLE1-3, User(5) for clear, User(6) for USB/BLE switch, User(7) for battery check
-const BLE1: Action = Action::User(0);
-const BLE2: Action = Action::User(1);
-const BLE3: Action = Action::User(2);
-const BLE_CLR: Action = Action::User(5);
-const USB_BLE_SW: Action = Action::User(6);
-const BATT_CHECK: Action = Action::User(7);
+// Tapdance 1 hold is watched by StatusLedController to confirm bootloader entry
+pub(crate) const BOOTLOADER_TAPDANCE: u8 = 1;
+pub(crate) const BOOTLOADER_HOLD_TIMEOUT_MS: u16 = 200;
+
+// Destructive actions need a double tap within this window. Only BLE_CLR (User5) for now, in
+// tapdance 0 - bootloader entry has its own hold + confirm tap, and there's no factory reset
+// key. RMK itself only fires the action on the second tap (it's the tapdance gap timeout),
+// StatusLedController just shows the amber "confirm?" while waiting. The window holds back
+// every other tap on the same tapdance too, so keep plain actions off these.
+pub(crate) const CONFIRM_TAPDANCES: [u8; 1] = [0];
+pub(crate) const DESTRUCTIVE_CONFIRM_WINDOW_MS: u16 = 1500;
 
 pub(crate) const COL: usize = 4;
 pub(crate) const ROW: usize = 4;
@@ -28,50 +44,209 @@ pub(crate) const SIZE: usize = 16; // Rows * Cols
 pub(crate) const NUM_LAYER: usize = 8;
 pub(crate) const NUM_ENCODER: usize = 1;
 
-#[rustfmt::skip]
+// Vial only lets you remap encoders that vial.json lays out (see build.rs)
+const _: () = assert!(crate::vial::VIAL_NUM_ENCODER == NUM_ENCODER);
+
+// Default layer per BLE profile (BLE1-3), applied by ProfileLayerSwitcher on profile change.
+// USB always uses layer 0. Off by default - e.g. `Some([0, 2, 0])` puts BLE2 on layer 2.
+pub(crate) const PROFILE_DEFAULT_LAYER: Option<[u8; 3]> = None;
+
+// Keymap profile is picked at build time: media (default) or numpad (`--features keymap-numpad`).
+// Both share layers 1-7, only the base layer and its encoder action differ.
 pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
+    if cfg!(feature = "keymap-numpad") {
+        numpad_keymap()
+    } else {
+        media_keymap()
+    }
+}
+
+pub const fn get_default_encoder_map() -> [[EncoderAction; NUM_ENCODER]; NUM_LAYER] {
+    if cfg!(feature = "keymap-numpad") {
+        numpad_encoder_map()
+    } else {
+        media_encoder_map()
+    }
+}
+
+/// What holding a layer-tap key does, see `layer_tap`
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum LayerHold {
+    /// Just the layer, same as `lt!`
+    Layer(u8),
+    /// The layer with modifiers held too, e.g. Ctrl on a layer with the encoder on the wheel
+    /// for a zoom layer
+    LayerWithModifiers(u8, ModifierCombination),
+    /// Something else instead of a layer
+    Action(Action),
+}
+
+/// Tap-hold key on the default morse profile, so it shares the `lt!` tapping term from
+/// `configure_layer_tap`: released before it = `tap`, held past it = `hold` until released.
+pub const fn layer_tap(tap: Action, hold: LayerHold) -> KeyAction {
+    let hold = match hold {
+        LayerHold::Layer(layer) => Action::LayerOn(layer),
+        LayerHold::LayerWithModifiers(layer, modifiers) => {
+            Action::LayerOnWithModifier(layer, modifiers)
+        }
+        LayerHold::Action(action) => action,
+    };
+    KeyAction::TapHold(tap, hold, MorseProfile::const_default())
+}
+
+// Top-right key of the media base layer: tap = mute, hold = MUTE_HOLD. Layer(1) is the old
+// lt!(1, AudioMute), the key is transparent on layer 1 so holding it keeps the layer up.
+//
+// Timing: the hold action kicks in the moment the tapping term (the tuned hold timeout, 200ms
+// default) passes, and stays until release. A longer hold doing something else on top isn't
+// possible here: RMK's morse (ca38784) decides tap vs hold once, at the hold timeout, and a
+// HOLD pattern has nothing after it. A long-hold threshold would have to sit well past the
+// term in that machinery (`rmk/src/keyboard/morse.rs`), with the hold action already active
+// by then - so it could only add to the layer, not replace it, unless the hold decision waited
+// for it and the layer came up that much later.
+const MUTE_HOLD: LayerHold = LayerHold::Layer(1);
+const MUTE_KEY: KeyAction = layer_tap(Action::Key(KeyCode::AudioMute), MUTE_HOLD);
+
+// Profile keys of the bond management layer: tap = switch to the profile like BLE1-3, hold
+// for BOND_DELETE_HOLD_MS = delete only that profile's bond (BondManager, see
+// bond_manager.rs). Long on purpose, a slow tap mustn't cost a pairing.
+pub(crate) const BOND_DELETE_HOLD_MS: u16 = 2000;
+const BOND_KEY_PROFILE: MorseProfile = MorseProfile::new(
+    None,
+    Some(MorseMode::Normal),
+    Some(BOND_DELETE_HOLD_MS),
+    None,
+);
+
+const fn bond_key(profile: Action, delete: Action) -> KeyAction {
+    KeyAction::TapHold(profile, delete, BOND_KEY_PROFILE)
+}
+
+// Function layer (layer 1, held from MUTE_KEY): every function key as (row, col, action), the
+// one place to move them. The layer is built from this, anything not listed is No.
+// Whatever handles these matches on the action - the User(n) value or the tapdance index -
+// never on where it sits, so a key works wherever it goes. Only (0, 3) can't move: it's
+// MUTE_KEY's own position, transparent so holding that key keeps the layer up.
+// Vial remaps still win over all of this once saved, it's only the default keymap.
+#[rustfmt::skip]
+const FUNCTION_KEYS: [(usize, usize, KeyAction); 15] = [
+    // BLE profiles, and the key that brings layer 1 up
+    (0, 0, usr!(BLE1)),
+    (0, 1, usr!(BLE2)),
+    (0, 2, usr!(BLE3)),
+    (0, 3, a!(Transparent)),
+    // BLE clear on a double tap, with the amber confirm (StatusLedController)
+    (1, 0, td!(CONFIRM_TAPDANCES[0])),
+    // Hold-to-confirm bootloader entry (StatusLedController)
+    (2, 0, td!(BOOTLOADER_TAPDANCE)),
+    // LEDs and readouts
+    (1, 1, usr!(ODOMETER_SHOW)),
+    (1, 2, usr!(LEDS_TOGGLE)),
+    (1, 3, usr!(BATT_CHECK)),
+    (2, 1, usr!(CHARGE_CYCLES_SHOW)),
+    (2, 2, usr!(EFFECT_NEXT)),
+    (3, 2, usr!(HEATMAP_SHOW)),
+    // Output, the tuning layer and media multi-tap
+    (2, 3, usr!(USB_BLE_SW)),
+    (3, 0, tg!(3)),
+    (3, 3, td!(3)),
+];
+
+/// Layer 1 from FUNCTION_KEYS
+const fn function_layer() -> [[KeyAction; COL]; ROW] {
+    let mut layer = [[KeyAction::No; COL]; ROW];
+    let mut i = 0;
+    while i < FUNCTION_KEYS.len() {
+        // Out of bounds fails the build right here
+        let (row, col, action) = FUNCTION_KEYS[i];
+        layer[row][col] = action;
+        i += 1;
+    }
+    layer
+}
+
+/// No two entries on the same position, the later one would silently win
+const fn positions_distinct(keys: &[(usize, usize, KeyAction)]) -> bool {
+    let mut i = 0;
+    while i < keys.len() {
+        let mut j = i + 1;
+        while j < keys.len() {
+            if keys[i].0 == keys[j].0 && keys[i].1 == keys[j].1 {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+const _: () = assert!(positions_distinct(&FUNCTION_KEYS));
+const _: () = assert!(!positions_distinct(&[
+    (1, 2, KeyAction::No),
+    (1, 2, KeyAction::Transparent),
+]));
+const _: () = assert!(matches!(function_layer()[0][3], KeyAction::Transparent));
+
+#[rustfmt::skip]
+const fn media_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
     [
         layer!([
-            [k!(A),                    k!(B),                      k!(C),                  lt!(1, AudioMute)],
+            [k!(A),                    k!(B),                      k!(C),                  MUTE_KEY],
             [k!(D),                    k!(E),                      k!(F),                  k!(G)],
             [k!(H),                    k!(I),                      k!(J),                  k!(K)],
             [k!(L),                    a!(No),                     k!(N),                  k!(O)]
         ]),
+        function_layer(),
+        // Letters with one-shot shift, see configure_one_shot
         layer!([
-            [KeyAction::Single(BLE1),  KeyAction::Single(BLE2),    KeyAction::Single(BLE3),   a!(Transparent)],
-            [td!(0),                   a!(No),                     a!(No),                    KeyAction::Single(BATT_CHECK)],
-            [td!(1),                   a!(No),                     a!(No),                    KeyAction::Single(USB_BLE_SW)],
-            [a!(No),                   a!(No),                     a!(No),                    a!(No)]
-        ]),
-        layer!([
-            [k!(J),                    k!(K),                      k!(L),                  a!(No)],
+            [k!(J),                    k!(K),                      k!(L),                  osm!(ModifierCombination::LSHIFT)],
             [k!(M),                    k!(N),                      k!(O),                  a!(No)],
             [k!(P),                    k!(Q),                      k!(R),                  a!(No)],
             [a!(No),                   a!(No),                     a!(No),                 a!(No)]
         ]),
+        // Tuning layer - toggled from layer 1, encoder adjusts the tapdance hold timeout.
+        // Battery calibration, the raw ADC view and the focus timer live here too, see
+        // battery_cal.rs, raw_adc.rs and led/effects.rs. BLE_NEXT cycles the BLE profiles,
+        // KEYMAP_DUMP logs the keymap in dev builds (keymap_dump.rs), RUNTIME_SHOW shows the
+        // battery runtime estimate (runtime_estimate.rs). tg!(6) brings up bond management,
+        // tg!(4) goes on to the scroll layer.
         layer!([
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)],
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)],
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)],
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)]
+            [usr!(LED_LOCK),           usr!(BATT_CAL),             usr!(BATT_CHECK),       usr!(RAW_ADC_SHOW)],
+            [usr!(FOCUS_TIMER),        usr!(BLE_NEXT),             usr!(KEYMAP_DUMP),      usr!(RUNTIME_SHOW)],
+            [tg!(6),                   tg!(4),                     a!(No),                 a!(No)],
+            [tg!(3),                   a!(No),                     a!(No),                 a!(No)]
         ]),
+        // Scroll layer - toggled from the tuning layer, tg!(4) goes back. Arrows repeat while
+        // held, see configure_hold_repeat
         layer!([
+            [a!(No),                   k!(Up),                     a!(No),                 a!(No)],
+            [k!(Left),                 k!(Down),                   k!(Right),              a!(No)],
             [a!(No),                   a!(No),                     a!(No),                 a!(No)],
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)],
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)],
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)]
+            [tg!(4),                   a!(No),                     a!(No),                 a!(No)]
         ]),
+        // Media layer example, reach it with tg!(5) or lt!(5, ...). RMK sends consumer keys
+        // as a press report on key down and an empty one on key up, so holding works without
+        // extra setup - the host decides what a held key does:
+        // - MediaNextTrack/MediaPrevTrack (Scan Next/Previous) are one-shot controls, most hosts
+        //   skip on the press and ignore the hold. macOS seeks in Music/TV while held, Linux
+        //   may skip several tracks once its key repeat kicks in (~500ms).
+        // - MediaFastForward/MediaRewind are on/off controls, seeking while held. Android and
+        //   most Linux players respect them, Windows and macOS mostly ignore them.
+        // Encoder actions are always a press and release back to back, so there's no hold there.
         layer!([
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)],
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)],
+            [k!(MediaPrevTrack),       k!(MediaPlayPause),         k!(MediaNextTrack),     a!(No)],
+            [k!(MediaRewind),          k!(MediaStop),              k!(MediaFastForward),   a!(No)],
             [a!(No),                   a!(No),                     a!(No),                 a!(No)],
             [a!(No),                   a!(No),                     a!(No),                 a!(No)]
         ]),
+        // Bond management - toggled from the tuning layer, see bond_key
         layer!([
+            [bond_key(BLE1, BOND_DEL_1), bond_key(BLE2, BOND_DEL_2), bond_key(BLE3, BOND_DEL_3), a!(No)],
             [a!(No),                   a!(No),                     a!(No),                 a!(No)],
             [a!(No),                   a!(No),                     a!(No),                 a!(No)],
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)],
-            [a!(No),                   a!(No),                     a!(No),                 a!(No)]
+            [tg!(6),                   a!(No),                     a!(No),                 a!(No)]
         ]),
         layer!([
             [a!(No),                   a!(No),                     a!(No),                 a!(No)],
@@ -82,48 +257,96 @@ pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
     ]
 }
 
-pub const fn get_default_encoder_map() -> [[EncoderAction; NUM_ENCODER]; NUM_LAYER] {
-    [
+// Scroll mode example on layer 4 - reach it with tg!(4) from the tuning layer, or lt!(4, ...).
+// RMK sends mouse keycodes as a separate HID mouse report, so this works without extra setup.
+// - Each detent (resolution 4 steps) is one wheel tick, the host applies its own scroll
+//   acceleration on top. Any encoder-side acceleration would stack with the host's and make
+//   fast spins jump, so keep one or the other.
+// - Horizontal scroll: MouseWheelLeft/MouseWheelRight are real HID wheel axes, use them
+//   directly. Shift+wheel also works on most hosts but needs a modifier held per step.
+const fn media_encoder_map() -> [[EncoderAction; NUM_ENCODER]; NUM_LAYER] {
+    encoder_map!(
         [encoder!(k!(AudioVolUp), k!(AudioVolDown))],
-        [encoder!(k!(No), k!(No))],
-        [encoder!(k!(No), k!(No))],
-        [encoder!(k!(No), k!(No))],
-        [encoder!(k!(No), k!(No))],
-        [encoder!(k!(No), k!(No))],
-        [encoder!(k!(No), k!(No))],
-        [encoder!(k!(No), k!(No))],
-    ]
+        enc_none!(),
+        enc_none!(),
+        [encoder!(usr!(HOLD_TIMEOUT_UP), usr!(HOLD_TIMEOUT_DOWN))],
+        [encoder!(k!(MouseWheelUp), k!(MouseWheelDown))],
+    )
+}
+
+#[rustfmt::skip]
+const fn numpad_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
+    let mut keymap = media_keymap();
+    keymap[0] = layer!([
+        [k!(Kp7),                  k!(Kp8),                    k!(Kp9),                lt!(1, KpSlash)],
+        [k!(Kp4),                  k!(Kp5),                    k!(Kp6),                k!(KpAsterisk)],
+        [k!(Kp1),                  k!(Kp2),                    k!(Kp3),                k!(KpMinus)],
+        [k!(Kp0),                  k!(KpDot),                  k!(KpEnter),            k!(KpPlus)]
+    ]);
+    keymap
+}
+
+const fn numpad_encoder_map() -> [[EncoderAction; NUM_ENCODER]; NUM_LAYER] {
+    let mut encoder_map = media_encoder_map();
+    // Scroll through spreadsheets instead of changing volume
+    encoder_map[0] = [encoder!(k!(MouseWheelUp), k!(MouseWheelDown))];
+    encoder_map
 }
 
 /// Configure tapdance behaviors
 /// This function sets up tapdance configurations that can be referenced in the keymap using td!(index)
-pub fn configure_tapdance(behavior_config: &mut rmk::config::BehaviorConfig) {
-    use rmk::morse::{HOLD, TAP};
+/// `hold_timeout_ms` is the saved value from the tuning layer, used by every tapdance except the bootloader one
+pub fn configure_tapdance(behavior_config: &mut rmk::config::BehaviorConfig, hold_timeout_ms: u16) {
+    use rmk::morse::{DOUBLE_TAP, HOLD, TAP};
 
-    // Tapdance 0 - Hold for BLE clear
+    // Tapdance 0 - Double tap for BLE clear
     let mut td0 = Morse::default();
     td0.profile = MorseProfile::new(
-        None,                    // Use default unilateral_tap
-        Some(MorseMode::Normal), // Normal mode
-        Some(200),               // 200ms hold timeout
-        Some(200),               // 200ms gap timeout
+        None,                                // Use default unilateral_tap
+        Some(MorseMode::Normal),             // Normal mode
+        Some(hold_timeout_ms),               // Tunable hold timeout (200ms default)
+        Some(DESTRUCTIVE_CONFIRM_WINDOW_MS), // Gap timeout = time to confirm with the second tap
     );
-    td0.put(HOLD, BLE_CLR);
+    td0.put(DOUBLE_TAP, BLE_CLR);
 
     //////////////////////////////////////////////////////////////////////////////
 
-    // Tapdance 1 - Hold for bootloader
+    // Tapdance 1 - Hold for bootloader (after LED confirmation, see StatusLedController)
     let mut td1 = Morse::default();
-    td1.profile = MorseProfile::new(None, Some(MorseMode::Normal), Some(200), Some(200));
-    td1.put(HOLD, Action::KeyboardControl(KeyboardAction::Bootloader));
+    td1.profile = MorseProfile::new(
+        None,
+        Some(MorseMode::Normal),
+        Some(BOOTLOADER_HOLD_TIMEOUT_MS),
+        Some(200),
+    );
+    td1.put(HOLD, BOOTLOADER_REQ);
 
     //////////////////////////////////////////////////////////////////////////////
 
-    // Tapdance 2 - Tap for BLE3, Hold for BLE clear
+    // Tapdance 2 - Tap for BLE3. BLE clear is on tapdance 0, behind the confirm window
     let mut td2 = Morse::default();
-    td2.profile = MorseProfile::new(None, Some(MorseMode::Normal), Some(200), Some(200));
-    td2.put(TAP, BLE3);  // User(2) = BLE3
-    td2.put(HOLD, BLE_CLR);  // User(5) = BLE_CLR
+    td2.profile = MorseProfile::new(
+        None,
+        Some(MorseMode::Normal),
+        Some(hold_timeout_ms),
+        Some(200),
+    );
+    td2.put(TAP, BLE3);
+
+    //////////////////////////////////////////////////////////////////////////////
+
+    // Tapdance 3 - Media multi-tap: 1 tap play/pause, 2 taps next track, 3 taps previous track
+    // (and so do more than 3, see MULTI_TAP_OVERFLOW)
+    let td3 = multi_tap(
+        &[
+            Action::Key(KeyCode::MediaPlayPause),
+            Action::Key(KeyCode::MediaNextTrack),
+            Action::Key(KeyCode::MediaPrevTrack),
+        ],
+        hold_timeout_ms,
+        MULTI_TAP_GAP_MS,
+        MULTI_TAP_OVERFLOW,
+    );
 
     //////////////////////////////////////////////////////////////////////////////
 
@@ -131,19 +354,319 @@ pub fn configure_tapdance(behavior_config: &mut rmk::config::BehaviorConfig) {
     let _ = behavior_config.morse.morses.push(td0);
     let _ = behavior_config.morse.morses.push(td1);
     let _ = behavior_config.morse.morses.push(td2);
+    let _ = behavior_config.morse.morses.push(td3);
+}
+
+// Time allowed between taps of a multi-tap key before the count so far fires
+const MULTI_TAP_GAP_MS: u16 = 250;
+// Longest tap sequence `multi_tap` fills in for an overflow policy. RMK keeps up to 8 patterns
+// per morse key (`max_patterns_per_key`), so nothing past that would fit anyway.
+const MULTI_TAP_MAX_TAPS: usize = 8;
+
+/// What a multi-tap key does when tapped more times than it has actions
+#[derive(Clone, Copy)]
+pub enum TapOverflow {
+    /// Nothing fires, the whole sequence is dropped. That's RMK's own behavior for a tap count
+    /// with no pattern.
+    Drop,
+    /// Extra taps are ignored, the last action fires (4 taps on a 3-action key = the third).
+    /// The default.
+    Clamp,
+    /// The count wraps around to the tap action (4 taps on a 3-action key = the first)
+    Wrap,
+}
+
+/// Overflow policy for the multi-tap keys in `configure_tapdance`
+const MULTI_TAP_OVERFLOW: TapOverflow = TapOverflow::Clamp;
+
+/// Index of the action `taps` taps fire on a key with `len` actions, None = nothing fires
+pub(crate) const fn multi_tap_index(
+    len: usize,
+    taps: usize,
+    overflow: TapOverflow,
+) -> Option<usize> {
+    if len == 0 || taps == 0 {
+        return None;
+    }
+    if taps <= len {
+        return Some(taps - 1);
+    }
+    match overflow {
+        TapOverflow::Drop => None,
+        TapOverflow::Clamp => Some(len - 1),
+        TapOverflow::Wrap => Some((taps - 1) % len),
+    }
+}
+
+const _: () = assert!(matches!(multi_tap_index(3, 1, TapOverflow::Drop), Some(0)));
+const _: () = assert!(matches!(multi_tap_index(3, 3, TapOverflow::Drop), Some(2)));
+const _: () = assert!(multi_tap_index(3, 4, TapOverflow::Drop).is_none());
+const _: () = assert!(matches!(multi_tap_index(3, 4, TapOverflow::Clamp), Some(2)));
+const _: () = assert!(matches!(multi_tap_index(3, 8, TapOverflow::Clamp), Some(2)));
+const _: () = assert!(matches!(multi_tap_index(3, 4, TapOverflow::Wrap), Some(0)));
+const _: () = assert!(matches!(multi_tap_index(3, 8, TapOverflow::Wrap), Some(1)));
+const _: () = assert!(multi_tap_index(0, 1, TapOverflow::Clamp).is_none());
+
+/// Builds a multi-tap morse: n taps fire `actions[n - 1]`.
+/// Every tap within `gap_timeout_ms` of the previous one bumps the count, so nothing fires until
+/// the taps stop. If the user stops mid-sequence (two taps on a three-action key), the last
+/// completed count (the second action) fires once the gap timeout passes.
+/// Tapping past the last action is up to `overflow`. In Normal mode RMK looks up the exact tap
+/// count once the gap timeout passes, so the overflow counts (up to MULTI_TAP_MAX_TAPS) are filled
+/// in as patterns of their own - one action per sequence, never one per extra tap. Past
+/// MULTI_TAP_MAX_TAPS nothing fires whatever the policy.
+/// Holding past `hold_timeout_ms` has no action here, so a held key does nothing.
+pub fn multi_tap(
+    actions: &[Action],
+    hold_timeout_ms: u16,
+    gap_timeout_ms: u16,
+    overflow: TapOverflow,
+) -> Morse {
+    use rmk::morse::TAP;
+
+    let mut morse = Morse::default();
+    morse.profile = MorseProfile::new(
+        None,
+        Some(MorseMode::Normal),
+        Some(hold_timeout_ms),
+        Some(gap_timeout_ms),
+    );
+    let mut pattern = TAP;
+    for taps in 1..=MULTI_TAP_MAX_TAPS.max(actions.len()) {
+        if let Some(i) = multi_tap_index(actions.len(), taps, overflow) {
+            morse.put(pattern, actions[i]);
+        }
+        pattern = pattern.followed_by_tap();
+    }
+    morse
+}
+
+/// Configure layer-tap timing
+/// `lt!` and `layer_tap` keys (the base layer's mute key) don't carry their own profile, RMK
+/// falls back to the default morse profile for them. Its hold timeout is the tapping term:
+/// released before it = tap (mute), held past it = the hold (layer 1). It shares the tuned tapdance hold timeout
+/// (200ms default, adjustable from the tuning layer and shown on its readout).
+/// Normal mode only decides on the timeout, so rolling into the next key while typing fast
+/// still counts as a tap. If one position needs its own term, give it a profile in the keymap:
+/// `KeyAction::TapHold(Action::Key(KeyCode::AudioMute), Action::LayerOn(1), MorseProfile::new(None, Some(MorseMode::Normal), Some(ms), None))`
+pub fn configure_layer_tap(
+    behavior_config: &mut rmk::config::BehaviorConfig,
+    hold_timeout_ms: u16,
+) {
+    behavior_config.morse.default_profile =
+        MorseProfile::new(None, Some(MorseMode::Normal), Some(hold_timeout_ms), None);
+}
+
+// An unused one-shot modifier is dropped after this long
+const ONE_SHOT_TIMEOUT_MS: u64 = 1000;
+
+/// Configure one-shot modifiers (`osm!` in the keymap)
+/// Tapping an `osm!` key applies its modifier to the next key press only, holding it works like
+/// a normal modifier. Nothing pressed within ONE_SHOT_TIMEOUT_MS and it's dropped unused.
+/// Modifiers held or one-shot before the target key combine: tap osm!(LSHIFT) then hold Ctrl,
+/// and the next key gets Ctrl+Shift. Tapping a second `osm!` stacks too, both apply once.
+pub fn configure_one_shot(behavior_config: &mut rmk::config::BehaviorConfig) {
+    behavior_config.one_shot.timeout = embassy_time::Duration::from_millis(ONE_SHOT_TIMEOUT_MS);
+}
+
+// Auto-shift: holding a letter, number or symbol past AUTO_SHIFT_TIMEOUT_MS sends it shifted.
+// Off by default.
+const AUTO_SHIFT: bool = false;
+const AUTO_SHIFT_TIMEOUT_MS: u16 = 200;
+
+/// Letters, the number row and the shiftable symbols (`-=[]\;'`,./` and the ISO extras).
+/// Enter, Space, Tab, Backspace etc. are left alone, as are modifiers and the keypad.
+pub(crate) const fn auto_shiftable(key: KeyCode) -> bool {
+    let code = key as u16;
+    (code >= KeyCode::A as u16 && code <= KeyCode::Kc0 as u16)
+        || (code >= KeyCode::Minus as u16 && code <= KeyCode::Slash as u16)
+        || code == KeyCode::NonusBackslash as u16
+}
+
+const _: () = assert!(auto_shiftable(KeyCode::A) && auto_shiftable(KeyCode::Kc0));
+const _: () = assert!(auto_shiftable(KeyCode::Slash));
+const _: () = assert!(!auto_shiftable(KeyCode::Enter) && !auto_shiftable(KeyCode::Space));
+const _: () = assert!(!auto_shiftable(KeyCode::Kp1) && !auto_shiftable(KeyCode::LShift));
+
+/// Configure auto-shift (when AUTO_SHIFT is on)
+/// Every plain `auto_shiftable` key in the keymap becomes a tap-hold: tap = the key, hold =
+/// Shift + the key. RMK sends a key report as soon as the matrix reports the press, so an input
+/// processor in the chain can't hold a key back to see how long it's held - morse is the one
+/// place RMK waits for a decision, so that's what does it here.
+/// Normal mode only decides on the timeout, so typing fast (even rolling into the next key
+/// before releasing this one) never shifts. The flip side: these keys only type on release or
+/// once the timeout passes, and the host's key repeat doesn't kick in for them.
+pub fn configure_auto_shift(keymap: &mut [[[KeyAction; COL]; ROW]; NUM_LAYER]) {
+    if !AUTO_SHIFT {
+        return;
+    }
+    let profile = MorseProfile::new(
+        None,
+        Some(MorseMode::Normal),
+        Some(AUTO_SHIFT_TIMEOUT_MS),
+        None,
+    );
+    for action in keymap.iter_mut().flatten().flatten() {
+        if let KeyAction::Single(Action::Key(key)) = *action
+            && auto_shiftable(key)
+        {
+            *action = KeyAction::TapHold(
+                Action::Key(key),
+                Action::KeyWithModifier(key, ModifierCombination::LSHIFT),
+                profile,
+            );
+        }
+    }
 }
 
+// Tap-hold decision modes (RMK's `MorseMode`), for keys that do one thing on tap and another
+// on hold:
+// - Normal: decided on the hold timeout only. Released before it = tap, held past it = hold,
+//   whatever else gets pressed meanwhile. Safe for fast typing, but a modifier only applies
+//   once the timeout has passed.
+// - PermissiveHold: another key pressed and released while this one is held = hold right
+//   then. Rolling off this key before releasing the other one still taps.
+// - HoldOnOtherPress: any other key pressed while this one is held = hold right away, no
+//   waiting for the timeout. Lowest latency for mods, but rolls while typing fast turn into
+//   mod+key.
+// Tapdances, `lt!`/`layer_tap` keys (the default profile) and auto-shift all use Normal. The
+// home-row mods below use HOME_ROW_MOD_MODE.
+
+// Home-row mods: tap = the letter, hold = the modifier. Off by default, like auto-shift.
+// Positions are (row, col) on the base layer, the middle row GUI/Alt/Ctrl/Shift from the left.
+const HOME_ROW_MODS: bool = false;
+const HOME_ROW_MOD_KEYS: [(usize, usize, ModifierCombination); 4] = [
+    (1, 0, ModifierCombination::LGUI),
+    (1, 1, ModifierCombination::LALT),
+    (1, 2, ModifierCombination::LCTRL),
+    (1, 3, ModifierCombination::LSHIFT),
+];
+pub(crate) const HOME_ROW_MOD_MODE: MorseMode = MorseMode::HoldOnOtherPress;
+// Only matters when nothing else is pressed, a lone hold becomes the modifier after this long
+const HOME_ROW_MOD_TIMEOUT_MS: u16 = 200;
+const HOME_ROW_MOD_PROFILE: MorseProfile = MorseProfile::new(
+    None,
+    Some(HOME_ROW_MOD_MODE),
+    Some(HOME_ROW_MOD_TIMEOUT_MS),
+    None,
+);
+
+/// Home-row mod key on HOME_ROW_MOD_PROFILE
+pub(crate) const fn home_row_mod(key: KeyCode, modifier: ModifierCombination) -> KeyAction {
+    KeyAction::TapHold(
+        Action::Key(key),
+        Action::Modifier(modifier),
+        HOME_ROW_MOD_PROFILE,
+    )
+}
+
+const _: () = assert!(matches!(
+    home_row_mod(KeyCode::F, ModifierCombination::LCTRL),
+    KeyAction::TapHold(_, _, profile)
+        if matches!(profile.mode(), Some(MorseMode::HoldOnOtherPress))
+));
+
+/// Configure home-row mods (when HOME_ROW_MODS is on)
+/// Every HOME_ROW_MOD_KEYS position holding a plain key on the base layer becomes a
+/// `home_row_mod`. Runs before auto-shift, which leaves them alone then.
+pub fn configure_home_row_mods(keymap: &mut [[[KeyAction; COL]; ROW]; NUM_LAYER]) {
+    if !HOME_ROW_MODS {
+        return;
+    }
+    for (row, col, modifier) in HOME_ROW_MOD_KEYS {
+        if let KeyAction::Single(Action::Key(key)) = keymap[0][row][col] {
+            keymap[0][row][col] = home_row_mod(key, modifier);
+        }
+    }
+}
+
+/// Configure hold-to-repeat
+/// Listed actions re-tap at their own rate while held, instead of host typematic (see repeat.rs).
+/// Keep tapdance/morse keys out of here, every repeat would count as another tap.
+pub(crate) fn configure_hold_repeat(config: &mut HoldRepeatConfig) {
+    // Arrows on the scroll layer - 240ms before the first repeat, then 30 per second
+    for arrow in [k!(Up), k!(Down), k!(Left), k!(Right)] {
+        config.add(arrow, 240, 33);
+    }
+}
+
+/// Text macros, in `Action::TriggerMacro(index)` order
+pub(crate) const MACRO_TEXTS: [&str; 1] = [
+    // Macro 0: Text macro example
+    "Ziddy Makes was here (:",
+];
+
+/// LED flash when a macro fires, per `MACRO_TEXTS` index: the first `count` LEDs in `color`,
+/// for MACRO_FLASH_MS. None = no flash for that one. See StatusLedController for what counts
+/// as firing.
+pub(crate) const MACRO_FLASHES: [Option<LedReadout>; MACRO_TEXTS.len()] = [
+    // Macro 0: one white LED
+    Some(LedReadout {
+        count: 1,
+        color: RGB8 {
+            r: 30,
+            g: 30,
+            b: 30,
+        },
+    }),
+];
+pub(crate) const MACRO_FLASH_MS: u64 = 300;
+
 /// Configure keyboard macros
 /// This function sets up macro sequences that can be triggered using Action::TriggerMacro(index)
 pub fn configure_macros(behavior_config: &mut rmk::config::BehaviorConfig) {
     // Use in Keymap array
     // KeyAction::Single(Action::TriggerMacro(0))
 
-    // Macro 0: Text macro example
-    let macro0 = to_macro_sequence("Ziddy Makes was here (:");
-
     // Create macro sequences array and define them
-    let macro_sequences = [macro0];
+    let macro_sequences = MACRO_TEXTS.map(to_macro_sequence);
     let binary_macros = define_macro_sequences(&macro_sequences);
+    if !text_macros_encoded(&binary_macros, &MACRO_TEXTS) {
+        error!("Macro encoding doesn't match MACRO_TEXTS - macros will type garbage");
+    }
     behavior_config.keyboard_macros.macro_sequences = binary_macros;
 }
+
+/// Whether `blob` is exactly `texts` in RMK's binary macro format: printable ASCII text is
+/// stored as-is, each macro ends with a 0, and the rest of the space is 0.
+///
+/// RMK's encoder itself can't be tested from here. There's no host test for it: this is a
+/// bin-only no_std crate for thumbv7em, and rmk only builds with its nRF features, so there's
+/// no lib or std target a test harness could link against. There's no compile-time check
+/// either, since `to_macro_sequence` isn't const. What's left: the const asserts below pin the
+/// format down, and `configure_macros` checks RMK's real output against it at boot, so an RMK
+/// bump that changes the encoding shows up as an error on the first boot in the log.
+pub(crate) const fn text_macros_encoded(blob: &[u8], texts: &[&str]) -> bool {
+    let mut pos = 0;
+    let mut m = 0;
+    while m < texts.len() {
+        let text = texts[m].as_bytes();
+        let mut i = 0;
+        while i < text.len() {
+            if pos >= blob.len() || blob[pos] != text[i] {
+                return false;
+            }
+            pos += 1;
+            i += 1;
+        }
+        if pos >= blob.len() || blob[pos] != 0 {
+            return false;
+        }
+        pos += 1;
+        m += 1;
+    }
+    while pos < blob.len() {
+        if blob[pos] != 0 {
+            return false;
+        }
+        pos += 1;
+    }
+    true
+}
+
+const _: () = assert!(text_macros_encoded(b"AB\0\0\0", &["AB"]));
+const _: () = assert!(text_macros_encoded(b"AB\0c\0\0", &["AB", "c"]));
+// Wrong text, missing terminator, leftovers after the last macro
+const _: () = assert!(!text_macros_encoded(b"AC\0\0\0", &["AB"]));
+const _: () = assert!(!text_macros_encoded(b"AB", &["AB"]));
+const _: () = assert!(!text_macros_encoded(b"AB\0B\0", &["AB"]));
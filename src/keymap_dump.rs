@@ -0,0 +1,89 @@
+use core::cell::RefCell;
+
+use defmt::info;
+use embassy_time::{Duration, Instant, Timer};
+use rmk::event::{KeyEvent, KeyboardEvent};
+use rmk::keymap::KeyMap;
+use rmk::macros::controller;
+use rmk::types::action::KeyAction;
+
+use crate::keymap::{COL, NUM_ENCODER, NUM_LAYER, ROW};
+use crate::user_actions::{BATT_CHECK, KEYMAP_DUMP};
+
+// Keymap dump for bug reports (`dev` feature): hold BATT_CHECK and tap KEYMAP_DUMP, and every
+// layer of the live keymap goes out over RTT - the same `KeyMap` the keyboard resolves keys
+// on, so Vial remaps loaded from storage are in it. One line per row:
+//   L1 R0: [Single(User(0)), Single(User(1)), Single(User(2)), Transparent]
+// A layer with nothing but No/Transparent on it is a single "L6 empty" line.
+//
+// Lines go out KEYMAP_DUMP_LINE_GAP apart so the 1KB RTT buffer drains in between, and a dump
+// within KEYMAP_DUMP_COOLDOWN of the last one is ignored.
+const KEYMAP_DUMP_LINE_GAP: Duration = Duration::from_millis(10);
+const KEYMAP_DUMP_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Logs the live keymap, see above
+#[controller(subscribe = [KeyEvent])]
+pub struct KeymapDumper<'a> {
+    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    check_held: bool,
+    last_dump: Option<Instant>,
+}
+
+impl<'a> KeymapDumper<'a> {
+    pub fn new(keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>) -> Self {
+        Self {
+            keymap,
+            check_held: false,
+            last_dump: None,
+        }
+    }
+
+    fn row(&self, layer: usize, row: u8) -> [KeyAction; COL] {
+        let keymap = self.keymap.borrow();
+        core::array::from_fn(|col| {
+            let pos = KeyboardEvent::key(row, col as u8, true).pos;
+            keymap.get_action_at(pos, layer)
+        })
+    }
+
+    async fn dump(&mut self) {
+        if self
+            .last_dump
+            .is_some_and(|at| at.elapsed() < KEYMAP_DUMP_COOLDOWN)
+        {
+            info!("Keymap dump cooling down");
+            return;
+        }
+        self.last_dump = Some(Instant::now());
+        info!("Keymap: {} layers, {}x{}", NUM_LAYER, ROW, COL);
+        for layer in 0..NUM_LAYER {
+            // Rows are read again when printed, a borrow can't be held across the gaps
+            let empty = (0..ROW as u8).all(|row| {
+                self.row(layer, row)
+                    .iter()
+                    .all(|action| matches!(action, KeyAction::No | KeyAction::Transparent))
+            });
+            if empty {
+                info!("L{} empty", layer);
+                Timer::after(KEYMAP_DUMP_LINE_GAP).await;
+                continue;
+            }
+            for row in 0..ROW as u8 {
+                info!("L{} R{}: {:?}", layer, row, self.row(layer, row));
+                Timer::after(KEYMAP_DUMP_LINE_GAP).await;
+            }
+        }
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        // Same combo as BatteryCalibrator, but off the pressed flag instead of the toggle
+        // trick - events keep coming in while a dump is running, a missed one can't leave
+        // either key stuck
+        let pressed = event.keyboard_event.pressed;
+        if event.key_action == KeyAction::Single(BATT_CHECK) {
+            self.check_held = pressed;
+        } else if event.key_action == KeyAction::Single(KEYMAP_DUMP) && pressed && self.check_held {
+            self.dump().await;
+        }
+    }
+}
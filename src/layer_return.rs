@@ -0,0 +1,90 @@
+use core::cell::RefCell;
+
+use defmt::info;
+use embassy_time::{Duration, Instant};
+use rmk::event::{KeyEvent, LayerChangeEvent};
+use rmk::keymap::KeyMap;
+use rmk::macros::controller;
+use smart_leds::RGB8;
+
+use crate::keymap::{COL, NUM_ENCODER, NUM_LAYER, ROW};
+use crate::led::{LED_READOUT, LedReadout};
+
+// Back to the base layer after LAYER_RETURN_TIMEOUT on any other layer without a key event,
+// for a layer toggled on and forgotten (off by default). Never while a key is down, so a held
+// momentary layer key stays in charge however long it's held. One return per layer change,
+// after that it waits for the next one.
+//
+// The request goes through the same shared KeyMap as ProfileLayerSwitcher: every layer but 0
+// is deactivated, exactly what releasing MO / tapping TG again would do. The default layer
+// isn't part of that state, so the next key resolves on it (layer 0, or the profile's, see
+// profile_layer.rs).
+// There's no layer indicator on the strip, so one LAYER_RETURN_COLOR LED shows as a readout
+// to confirm it.
+pub(crate) const LAYER_RETURN_ENABLED: bool = false;
+pub(crate) const LAYER_RETURN_TIMEOUT: Duration = Duration::from_secs(30);
+
+const LAYER_RETURN_COLOR: RGB8 = RGB8 { r: 40, g: 0, b: 40 };
+
+/// Returns to the base layer when idle on another one, see above
+#[controller(subscribe = [LayerChangeEvent, KeyEvent], poll_interval = 1000)]
+pub struct LayerReturn<'a> {
+    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    layer: u8,
+    last_activity: Instant,
+    keys_down: u8,
+    returned: bool,
+}
+
+impl<'a> LayerReturn<'a> {
+    pub fn new(keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>) -> Self {
+        Self {
+            keymap,
+            layer: 0,
+            last_activity: Instant::now(),
+            keys_down: 0,
+            returned: false,
+        }
+    }
+
+    async fn on_layer_change_event(&mut self, event: LayerChangeEvent) {
+        self.layer = event.layer;
+        self.last_activity = Instant::now();
+        self.returned = false;
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        self.last_activity = Instant::now();
+        // Off the pressed flag rather than the toggle trick, a count has to match up
+        if event.keyboard_event.pressed {
+            self.keys_down = self.keys_down.saturating_add(1);
+        } else {
+            self.keys_down = self.keys_down.saturating_sub(1);
+        }
+    }
+
+    async fn poll(&mut self) {
+        if !LAYER_RETURN_ENABLED
+            || self.layer == 0
+            || self.returned
+            || self.keys_down > 0
+            || self.last_activity.elapsed() < LAYER_RETURN_TIMEOUT
+        {
+            return;
+        }
+        info!(
+            "Layer {} idle for {}s - back to the base layer",
+            self.layer,
+            LAYER_RETURN_TIMEOUT.as_secs()
+        );
+        self.returned = true;
+        let mut keymap = self.keymap.borrow_mut();
+        for layer in 1..NUM_LAYER as u8 {
+            keymap.deactivate_layer(layer);
+        }
+        LED_READOUT.signal(LedReadout {
+            count: 1,
+            color: LAYER_RETURN_COLOR,
+        });
+    }
+}
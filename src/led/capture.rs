@@ -0,0 +1,46 @@
+use core::convert::Infallible;
+
+use smart_leds::{RGB8, SmartLedsWrite};
+
+// LED sink that records frames instead of driving a strip, for checking effect output (the
+// battery bar, readouts, breathing...) without hardware: hand it to `StatusLedController` in
+// place of the `Ws2812` and look at `last` after each call.
+//
+// Nothing in the firmware uses it. Driving the controller off target still needs a host build
+// of this crate - it's a thumbv7em bin, and the power pin is an `embassy_nrf` `Output` - so
+// this is the piece that's ready for when that exists.
+#[allow(dead_code)]
+pub struct FrameCapture<const N: usize> {
+    /// The most recent frame, LEDs past the end of a short write are dark
+    pub last: [RGB8; N],
+    /// How many frames were written so far
+    pub writes: usize,
+}
+
+#[allow(dead_code)]
+impl<const N: usize> FrameCapture<N> {
+    pub const fn new() -> Self {
+        Self {
+            last: [RGB8 { r: 0, g: 0, b: 0 }; N],
+            writes: 0,
+        }
+    }
+}
+
+impl<const N: usize> SmartLedsWrite for FrameCapture<N> {
+    type Error = Infallible;
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        self.last = [RGB8::default(); N];
+        for (led, color) in self.last.iter_mut().zip(iterator) {
+            *led = color.into();
+        }
+        self.writes += 1;
+        Ok(())
+    }
+}
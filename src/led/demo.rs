@@ -0,0 +1,152 @@
+use defmt::{Format, info};
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+use super::effects::{PartyEffect, ScannerEffect, scale};
+
+// Demo mode for shelf units (`--features demo`): from boot until a host connects over BLE or
+// a key is pressed, StatusLedController hands the strip to this and it loops through every
+// effect, DEMO_STAGE_DURATION each (~48s per loop), logging each stage's name over RTT as it
+// starts:
+//   wave (the startup animation, over and over) > rainbow > scanner > party (a fake press
+//   every DEMO_PARTY_PRESS_MS) > breathing > battery fill (the bar filling 0-100%)
+// Either exit is for the rest of the session, the next boot starts the demo again. Stays out
+// of the default build.
+const DEMO_STAGE_DURATION: Duration = Duration::from_secs(8);
+const DEMO_PARTY_PRESS_MS: u64 = 200;
+const DEMO_BREATH_MS: u64 = 3000;
+// Startup animation timing, see startup_animation.rs
+const WAVE_STEP_MS: u64 = 100;
+const WAVE_FLASH_MS: u64 = 300;
+const WAVE_COLOR: RGB8 = RGB8 { r: 60, g: 20, b: 0 };
+const WAVE_FLASH_COLOR: RGB8 = RGB8 { r: 0, g: 0, b: 50 };
+const BREATH_COLOR: RGB8 = RGB8 { r: 0, g: 20, b: 60 };
+const FILL_COLOR: RGB8 = RGB8 { r: 0, g: 60, b: 0 };
+
+#[derive(Clone, Copy, PartialEq, Format)]
+enum Stage {
+    Wave,
+    Rainbow,
+    Scanner,
+    Party,
+    Breathing,
+    BatteryFill,
+}
+
+const STAGES: [Stage; 6] = [
+    Stage::Wave,
+    Stage::Rainbow,
+    Stage::Scanner,
+    Stage::Party,
+    Stage::Breathing,
+    Stage::BatteryFill,
+];
+
+/// Color wheel, 0 = red, 85 = green, 170 = blue, dim enough for the power budget
+const fn wheel(hue: u8) -> RGB8 {
+    let (r, g, b) = match hue {
+        0..=84 => (255 - hue * 3, hue * 3, 0),
+        85..=169 => (0, 255 - (hue - 85) * 3, (hue - 85) * 3),
+        _ => ((hue - 170) * 3, 0, 255 - (hue - 170) * 3),
+    };
+    RGB8 {
+        r: r / 4,
+        g: g / 4,
+        b: b / 4,
+    }
+}
+
+const _: () = assert!(wheel(0).r == 63 && wheel(0).g == 0);
+const _: () = assert!(wheel(85).g == 63 && wheel(170).b == 63);
+const _: () = assert!(wheel(255).r == 63 && wheel(255).b == 0);
+
+/// 0 -> 255 -> 0 once per `period_ms`
+const fn triangle(ms: u64, period_ms: u64) -> u8 {
+    let half = period_ms / 2;
+    let phase = ms % period_ms;
+    let rising = if phase < half {
+        phase
+    } else {
+        period_ms - phase
+    };
+    (rising * 255 / half) as u8
+}
+
+/// Effect loop for demo mode, stepped from `StatusLedController::poll`
+pub struct DemoCycle<const N: usize> {
+    started: Instant,
+    stage: usize,
+    scanner: ScannerEffect<N>,
+    party: PartyEffect<N>,
+    last_press: Instant,
+}
+
+impl<const N: usize> DemoCycle<N> {
+    pub fn new() -> Self {
+        info!("Demo mode - press a key or connect to stop");
+        info!("Demo: {}", STAGES[0]);
+        Self {
+            started: Instant::now(),
+            stage: 0,
+            scanner: ScannerEffect::new(),
+            party: PartyEffect::new(),
+            last_press: Instant::now(),
+        }
+    }
+
+    /// Moves the animations on, call it once per poll (50ms)
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let stage = ((now - self.started).as_millis() / DEMO_STAGE_DURATION.as_millis()) as usize
+            % STAGES.len();
+        if stage != self.stage {
+            self.stage = stage;
+            info!("Demo: {}", STAGES[stage]);
+        }
+        match STAGES[stage] {
+            Stage::Scanner => self.scanner.tick(),
+            Stage::Party => {
+                if (now - self.last_press).as_millis() >= DEMO_PARTY_PRESS_MS {
+                    self.last_press = now;
+                    self.party.on_press();
+                }
+                self.party.tick();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn frame(&self) -> [RGB8; N] {
+        let ms = (Instant::now() - self.started).as_millis() % DEMO_STAGE_DURATION.as_millis();
+        let mut data = [RGB8::default(); N];
+        match STAGES[self.stage] {
+            Stage::Wave => {
+                let wave_ms = N as u64 * WAVE_STEP_MS;
+                let ms = ms % (wave_ms + WAVE_FLASH_MS);
+                if ms < wave_ms {
+                    let lit = (ms / WAVE_STEP_MS) as usize + 1;
+                    data[..lit].fill(WAVE_COLOR);
+                } else {
+                    data.fill(WAVE_FLASH_COLOR);
+                }
+            }
+            Stage::Rainbow => {
+                // The whole wheel across the strip, turning once every 2.5s
+                let offset = (ms * 256 / 2560) as usize;
+                for (i, led) in data.iter_mut().enumerate() {
+                    *led = wheel(((i * 256 / N + offset) % 256) as u8);
+                }
+            }
+            Stage::Scanner => data = self.scanner.frame(),
+            Stage::Party => data = self.party.frame(),
+            Stage::Breathing => data.fill(scale(BREATH_COLOR, triangle(ms, DEMO_BREATH_MS))),
+            Stage::BatteryFill => {
+                // Empty to full over the stage, full for the last second
+                let fill_ms = DEMO_STAGE_DURATION.as_millis() - 1000;
+                let lit = (ms.min(fill_ms) * N as u64 / fill_ms) as usize;
+                data[..lit.max(1)].fill(FILL_COLOR);
+            }
+        }
+        data
+    }
+}
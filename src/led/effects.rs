@@ -0,0 +1,307 @@
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use smart_leds::RGB8;
+
+/// Effects cycled by the EFFECT_NEXT key. Status is the plain status display.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum LedEffect {
+    Status,
+    Party,
+    Scanner,
+}
+
+impl LedEffect {
+    pub fn next(self) -> Self {
+        match self {
+            LedEffect::Status => LedEffect::Party,
+            LedEffect::Party => LedEffect::Scanner,
+            LedEffect::Scanner => LedEffect::Status,
+        }
+    }
+
+    /// Stored form, see `ProfileLedsBytes`. Never renumber, old values stay in flash.
+    pub const fn to_byte(self) -> u8 {
+        match self {
+            LedEffect::Status => 0,
+            LedEffect::Party => 1,
+            LedEffect::Scanner => 2,
+        }
+    }
+
+    /// Unknown bytes are the status display
+    pub const fn from_byte(byte: u8) -> Self {
+        match byte {
+            1 => LedEffect::Party,
+            2 => LedEffect::Scanner,
+            _ => LedEffect::Status,
+        }
+    }
+}
+
+// Party mode: every press lights a random LED at full intensity, which then fades out.
+// Intensity drops by PARTY_DECAY_PER_TICK every tick (50ms), so 255 -> 0 takes ~0.65s.
+// Presses landing in the same tick just light their own LEDs - they all share one decay
+// buffer, and two presses picking the same LED simply restart its fade.
+const PARTY_DECAY_PER_TICK: u8 = 20;
+
+// Typing heat shifts the palette from cool to warm. Each press adds some, and it cools off
+// a little every tick, so only sustained fast typing (~6+ presses/s) keeps it warm.
+const PARTY_HEAT_PER_PRESS: u8 = 24;
+const PARTY_COOL_PER_TICK: u8 = 6;
+const PARTY_COOL_COLOR: RGB8 = RGB8 { r: 0, g: 30, b: 70 };
+const PARTY_WARM_COLOR: RGB8 = RGB8 { r: 70, g: 20, b: 0 };
+
+pub struct PartyEffect<const N: usize> {
+    decay: [u8; N],
+    heat: u8,
+    rng: u32,
+}
+
+impl<const N: usize> PartyEffect<N> {
+    // random_index() picks with % N
+    const HAS_LEDS: () = assert!(N >= 1, "PartyEffect needs at least one LED");
+
+    pub fn new() -> Self {
+        let () = Self::HAS_LEDS;
+        Self {
+            decay: [0; N],
+            heat: 0,
+            rng: 0,
+        }
+    }
+
+    /// xorshift32 - only needs to look random, seeded from the first press time
+    fn random_index(&mut self) -> usize {
+        if self.rng == 0 {
+            self.rng = Instant::now().as_ticks() as u32 | 1;
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as usize % N
+    }
+
+    pub fn on_press(&mut self) {
+        let index = self.random_index();
+        self.decay[index] = u8::MAX;
+        self.heat = self.heat.saturating_add(PARTY_HEAT_PER_PRESS);
+    }
+
+    /// Fades everything one step. Once fully dark the frame stops changing, so render() skips it.
+    pub fn tick(&mut self) {
+        for value in self.decay.iter_mut() {
+            *value = value.saturating_sub(PARTY_DECAY_PER_TICK);
+        }
+        self.heat = self.heat.saturating_sub(PARTY_COOL_PER_TICK);
+    }
+
+    pub fn frame(&self) -> [RGB8; N] {
+        let color = lerp(PARTY_COOL_COLOR, PARTY_WARM_COLOR, self.heat);
+        let mut data = [RGB8::default(); N];
+        for (led, &intensity) in data.iter_mut().zip(self.decay.iter()) {
+            *led = scale(color, intensity);
+        }
+        data
+    }
+}
+
+// Scanner: one bright LED sweeping back and forth, with a fading trail behind it.
+// It moves one LED every SCANNER_TICKS_PER_STEP ticks (50ms each), so 100ms per LED and ~1.3s
+// from end to end on 14 LEDs. The trail is roughly SCANNER_TRAIL_LEN LEDs long.
+const SCANNER_TICKS_PER_STEP: u8 = 2;
+const SCANNER_TRAIL_LEN: u8 = 4;
+const SCANNER_COLOR: RGB8 = RGB8 { r: 70, g: 0, b: 0 };
+
+pub struct ScannerEffect<const N: usize> {
+    trail: [u8; N],
+    position: usize,
+    forward: bool,
+    ticks: u8,
+}
+
+impl<const N: usize> ScannerEffect<N> {
+    const HAS_LEDS: () = assert!(N >= 1, "ScannerEffect needs at least one LED");
+
+    pub fn new() -> Self {
+        let () = Self::HAS_LEDS;
+        let mut trail = [0; N];
+        trail[0] = u8::MAX;
+        Self {
+            trail,
+            position: 0,
+            forward: true,
+            ticks: 0,
+        }
+    }
+
+    /// Moves the head once every SCANNER_TICKS_PER_STEP calls, fading the trail as it goes
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+        if self.ticks < SCANNER_TICKS_PER_STEP {
+            return;
+        }
+        self.ticks = 0;
+        for value in self.trail.iter_mut() {
+            *value = value.saturating_sub(u8::MAX / (SCANNER_TRAIL_LEN + 1));
+        }
+        // Bounce off either end
+        if self.forward && self.position + 1 >= N {
+            self.forward = false;
+        } else if !self.forward && self.position == 0 {
+            self.forward = true;
+        }
+        self.position = if self.forward {
+            (self.position + 1).min(N - 1)
+        } else {
+            self.position.saturating_sub(1)
+        };
+        self.trail[self.position] = u8::MAX;
+    }
+
+    pub fn frame(&self) -> [RGB8; N] {
+        let mut data = [RGB8::default(); N];
+        for (led, &intensity) in data.iter_mut().zip(self.trail.iter()) {
+            *led = scale(SCANNER_COLOR, intensity);
+        }
+        data
+    }
+}
+
+// Focus timer (FOCUS_TIMER key): the bar slowly fills over FOCUS_TIMER_DURATION, flashes when
+// it's full and then goes back to idle. It's drawn over the status display (or the underglow
+// region when the strip is split) and only lights the LEDs it fills, so one step is
+// 25min / 14 ~= 1.8min. Tap the key to start, tap again to pause and once more to resume.
+// Holding it for FOCUS_TIMER_RESET_HOLD cancels it, running or paused.
+pub const FOCUS_TIMER_DURATION: Duration = Duration::from_secs(25 * 60);
+pub const FOCUS_TIMER_RESET_HOLD: Duration = Duration::from_secs(1);
+const FOCUS_TIMER_COLOR: RGB8 = RGB8 { r: 12, g: 6, b: 0 };
+// Paused keeps the fill, just dimmer
+const FOCUS_TIMER_PAUSED_COLOR: RGB8 = RGB8 { r: 4, g: 2, b: 0 };
+const FOCUS_TIMER_DONE_COLOR: RGB8 = RGB8 { r: 0, g: 40, b: 10 };
+const FOCUS_TIMER_FLASH_MS: u64 = 400;
+const FOCUS_TIMER_FLASH_COUNT: u64 = 5;
+const FOCUS_TIMER_FLASH_TOTAL: Duration =
+    Duration::from_millis(FOCUS_TIMER_FLASH_MS * 2 * FOCUS_TIMER_FLASH_COUNT);
+
+#[derive(Clone, Copy)]
+enum FocusTimerState {
+    Idle,
+    Running { since: Instant },
+    Paused,
+    Done { at: Instant },
+}
+
+pub struct FocusTimer {
+    state: FocusTimerState,
+    // Time run before the current start, all of it while paused
+    banked: Duration,
+}
+
+impl FocusTimer {
+    pub fn new() -> Self {
+        Self {
+            state: FocusTimerState::Idle,
+            banked: Duration::from_ticks(0),
+        }
+    }
+
+    /// Running or flashing, something on the bar keeps changing
+    pub fn is_running(&self) -> bool {
+        matches!(
+            self.state,
+            FocusTimerState::Running { .. } | FocusTimerState::Done { .. }
+        )
+    }
+
+    /// The tap: start, pause or resume
+    pub fn toggle(&mut self, now: Instant) {
+        self.state = match self.state {
+            FocusTimerState::Idle | FocusTimerState::Done { .. } => {
+                self.banked = Duration::from_ticks(0);
+                FocusTimerState::Running { since: now }
+            }
+            FocusTimerState::Running { since } => {
+                self.banked += now - since;
+                FocusTimerState::Paused
+            }
+            FocusTimerState::Paused => FocusTimerState::Running { since: now },
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.state = FocusTimerState::Idle;
+        self.banked = Duration::from_ticks(0);
+    }
+
+    fn elapsed(&self, now: Instant) -> Duration {
+        match self.state {
+            FocusTimerState::Running { since } => self.banked + (now - since),
+            _ => self.banked,
+        }
+    }
+
+    /// Moves on to the flash once the time is up and back to idle after it.
+    /// Returns true on the tick the timer completes.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        match self.state {
+            FocusTimerState::Running { .. } if self.elapsed(now) >= FOCUS_TIMER_DURATION => {
+                self.state = FocusTimerState::Done { at: now };
+                true
+            }
+            FocusTimerState::Done { at } if now - at >= FOCUS_TIMER_FLASH_TOTAL => {
+                self.reset();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Draws the fill (or the flash) over `data`, LEDs it doesn't light are left alone
+    pub fn draw(&self, data: &mut [RGB8], now: Instant) {
+        let color = match self.state {
+            FocusTimerState::Idle => return,
+            FocusTimerState::Done { at } => {
+                if ((now - at).as_millis() / FOCUS_TIMER_FLASH_MS).is_multiple_of(2) {
+                    data.fill(FOCUS_TIMER_DONE_COLOR);
+                }
+                return;
+            }
+            FocusTimerState::Running { .. } => FOCUS_TIMER_COLOR,
+            FocusTimerState::Paused => FOCUS_TIMER_PAUSED_COLOR,
+        };
+        // At least one LED as soon as it's started, all of them right at the end
+        let elapsed_ms = self.elapsed(now).as_millis();
+        let lit = (elapsed_ms * data.len() as u64 / FOCUS_TIMER_DURATION.as_millis() + 1)
+            .min(data.len() as u64) as usize;
+        data[..lit].fill(color);
+    }
+}
+
+/// Scales a color by `amount` / 255
+pub fn scale(color: RGB8, amount: u8) -> RGB8 {
+    let channel = |c: u8| (c as u16 * amount as u16 / 255) as u8;
+    RGB8 {
+        r: channel(color.r),
+        g: channel(color.g),
+        b: channel(color.b),
+    }
+}
+
+// Typing heatmap palette, see `KeyOdometer`
+const HEATMAP_COLD_COLOR: RGB8 = RGB8 { r: 0, g: 0, b: 60 };
+const HEATMAP_HOT_COLOR: RGB8 = RGB8 { r: 70, g: 0, b: 0 };
+
+/// Blue for rarely used keys through to red for the most used one (`heat` 255)
+pub fn heat_color(heat: u8) -> RGB8 {
+    lerp(HEATMAP_COLD_COLOR, HEATMAP_HOT_COLOR, heat)
+}
+
+/// Blends from `from` (t = 0) to `to` (t = 255)
+fn lerp(from: RGB8, to: RGB8, t: u8) -> RGB8 {
+    let channel = |a: u8, b: u8| ((a as u16 * (255 - t as u16) + b as u16 * t as u16) / 255) as u8;
+    RGB8 {
+        r: channel(from.r, to.r),
+        g: channel(from.g, to.g),
+        b: channel(from.b, to.b),
+    }
+}
@@ -1,5 +1,309 @@
+pub mod capture;
+mod demo;
+pub mod effects;
 pub mod startup_animation;
 pub mod status_controller;
+pub mod timing;
 
-pub use startup_animation::StartupAnimator;
+use core::ops::Range;
+use core::sync::atomic::AtomicBool;
+
+use defmt::Format;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Duration;
+use smart_leds::RGB8;
+
+use crate::keymap::SIZE;
+use effects::LedEffect;
+
+pub use startup_animation::{StartupAnimation, StartupAnimator};
 pub use status_controller::StatusLedController;
+
+/// Below this battery percentage the LED MOSFET is never switched on: no startup animation,
+/// no battery check, no readouts. Powering all 14 LEDs on a nearly flat cell can brown out the
+/// nRF and cause a reset loop.
+pub const LED_MIN_BATTERY_PERCENT: u8 = 10;
+
+/// Once under the floor, LEDs only come back at floor + this.
+/// Plugging in a charger doesn't lift the floor by itself - the cell voltage jumps as soon as
+/// charge current flows, so a reading right at the floor would flap. Normal behavior resumes
+/// once a reading (charging or not) reaches floor + hysteresis, or on `Charged`.
+pub const LED_FLOOR_HYSTERESIS_PERCENT: u8 = 5;
+
+/// Whether LEDs are held off, given the previous state and a new reading
+pub const fn below_battery_floor(was_below: bool, percentage: u8) -> bool {
+    if percentage == BATTERY_UNKNOWN {
+        return false;
+    }
+    if was_below {
+        percentage < LED_MIN_BATTERY_PERCENT + LED_FLOOR_HYSTERESIS_PERCENT
+    } else {
+        percentage < LED_MIN_BATTERY_PERCENT
+    }
+}
+
+const _: () = assert!(below_battery_floor(false, LED_MIN_BATTERY_PERCENT - 1));
+const _: () = assert!(!below_battery_floor(false, LED_MIN_BATTERY_PERCENT));
+// Climbing back past the floor isn't enough, it takes the hysteresis on top
+const _: () = assert!(below_battery_floor(true, LED_MIN_BATTERY_PERCENT + 1));
+const _: () = assert!(!below_battery_floor(
+    true,
+    LED_MIN_BATTERY_PERCENT + LED_FLOOR_HYSTERESIS_PERCENT
+));
+const _: () = assert!(!below_battery_floor(true, BATTERY_UNKNOWN));
+
+/// The battery bar only gains or loses an LED once the percentage is this far past the band
+/// edge, so a reading hovering at e.g. 88/89% doesn't flicker the last LED
+pub const BATTERY_BAR_HYSTERESIS_PERCENT: u8 = 3;
+
+/// LEDs lit for `percentage` on an `n`-LED bar, with at least 1 LED even at 0%.
+/// Never more than `n`, whatever the thresholds below turn into - callers index the frame
+/// with it.
+pub const fn battery_bar_leds(percentage: u8, n: usize) -> usize {
+    let leds = if percentage == 0 {
+        1
+    } else if percentage >= 89 {
+        n // 89-100% = all n LEDs
+    } else {
+        // 1-88% maps to 1-(n-1) LEDs: scale proportionally
+        ((percentage as usize - 1) * (n - 1) / 88) + 1
+    };
+    if leds > n { n } else { leds }
+}
+
+/// Like `battery_bar_leds`, but sticks to `last` (what's shown now) until the percentage is
+/// `BATTERY_BAR_HYSTERESIS_PERCENT` past the next band edge in either direction
+pub const fn battery_bar_leds_hysteresis(last: usize, percentage: u8, n: usize) -> usize {
+    let leds = battery_bar_leds(percentage, n);
+    if leds > last {
+        let held = battery_bar_leds(percentage.saturating_sub(BATTERY_BAR_HYSTERESIS_PERCENT), n);
+        if held > last { held } else { last }
+    } else if leds < last {
+        let held = battery_bar_leds(
+            if percentage > 100 - BATTERY_BAR_HYSTERESIS_PERCENT {
+                100
+            } else {
+                percentage + BATTERY_BAR_HYSTERESIS_PERCENT
+            },
+            n,
+        );
+        if held < last { held } else { last }
+    } else {
+        last
+    }
+}
+
+// 14 LEDs, the last one comes on at 89%. Stepping up: 89 isn't enough, 92 is.
+const _: () = assert!(battery_bar_leds(88, 14) == 13 && battery_bar_leds(89, 14) == 14);
+const _: () = assert!(battery_bar_leds_hysteresis(13, 89, 14) == 13);
+const _: () = assert!(battery_bar_leds_hysteresis(13, 91, 14) == 13);
+const _: () = assert!(battery_bar_leds_hysteresis(13, 92, 14) == 14);
+// Stepping back down from there: 88 and 86 hold, 85 drops
+const _: () = assert!(battery_bar_leds_hysteresis(14, 88, 14) == 14);
+const _: () = assert!(battery_bar_leds_hysteresis(14, 86, 14) == 14);
+const _: () = assert!(battery_bar_leds_hysteresis(14, 85, 14) == 13);
+// A big jump still lands where it should
+const _: () = assert!(battery_bar_leds_hysteresis(14, 5, 14) == battery_bar_leds(8, 14));
+const _: () = assert!(battery_bar_leds_hysteresis(1, 100, 14) == 14);
+// A single-LED strip just keeps its one LED lit
+const _: () = assert!(battery_bar_leds(0, 1) == 1 && battery_bar_leds(50, 1) == 1);
+const _: () = assert!(battery_bar_leds(100, 1) == 1);
+
+// Every percentage (BATTERY_UNKNOWN included) from every starting point stays on the bar
+const fn battery_bar_fits(n: usize) -> bool {
+    let mut percentage = 0;
+    while percentage <= u8::MAX as usize {
+        if battery_bar_leds(percentage as u8, n) > n {
+            return false;
+        }
+        let mut last = 1;
+        while last <= n {
+            if battery_bar_leds_hysteresis(last, percentage as u8, n) > n {
+                return false;
+            }
+            last += 1;
+        }
+        percentage += 1;
+    }
+    true
+}
+
+const _: () = assert!(battery_bar_fits(1));
+const _: () = assert!(battery_bar_fits(2));
+const _: () = assert!(battery_bar_fits(14));
+const _: () = assert!(battery_bar_fits(60));
+
+/// Rough WS2812 draw per color channel at full value, in mA. The ~1mA each LED draws just
+/// sitting there comes on top whenever the rail is on, it doesn't depend on the frame.
+pub const LED_CHANNEL_MA: u32 = 20;
+
+/// Most the lit LEDs may draw together, in mA. Every frame above this is scaled down evenly
+/// (colors keep their hue) until it fits. It adds to the nRF's own draw and the radio peaks,
+/// so tune it to what the cell delivers without sagging - 300mA sits well inside a typical
+/// 1000mAh LiPo and its protection circuit. All 14 LEDs at full white would be ~840mA.
+/// The startup animation peaks at ~90mA, so it never gets near this.
+pub const LED_CURRENT_BUDGET_MA: u32 = 300;
+
+/// Estimated channel current of a frame, in mA
+pub const fn frame_current_ma(data: &[RGB8]) -> u32 {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < data.len() {
+        sum += data[i].r as u32 + data[i].g as u32 + data[i].b as u32;
+        i += 1;
+    }
+    sum * LED_CHANNEL_MA / 255
+}
+
+/// Amount for `scale` that brings a frame drawing `current_ma` within `budget_ma`,
+/// 255 if it already fits. `scale` rounds down, so the result never ends up above the budget.
+pub const fn power_budget_scale(current_ma: u32, budget_ma: u32) -> u8 {
+    if current_ma <= budget_ma {
+        255
+    } else {
+        (budget_ma * 255 / current_ma) as u8
+    }
+}
+
+const WHITE: RGB8 = RGB8 {
+    r: 255,
+    g: 255,
+    b: 255,
+};
+// Full white scaled down by 91, lands just under the budget
+const WHITE_LIMITED: RGB8 = RGB8 {
+    r: 91,
+    g: 91,
+    b: 91,
+};
+const _: () = assert!(frame_current_ma(&[WHITE; 14]) == 840);
+const _: () = assert!(power_budget_scale(840, LED_CURRENT_BUDGET_MA) == 91);
+const _: () = assert!(frame_current_ma(&[WHITE_LIMITED; 14]) <= LED_CURRENT_BUDGET_MA);
+const _: () = assert!(power_budget_scale(LED_CURRENT_BUDGET_MA, LED_CURRENT_BUDGET_MA) == 255);
+
+/// Battery percentage when the ADC failed to come up. Sticks for the whole session, so the
+/// battery display shows dim white instead of a number, and LED power isn't gated on it.
+pub const BATTERY_UNKNOWN: u8 = 255;
+
+/// Always-on underglow: a color and the LEDs it covers
+pub type Underglow = Option<(RGB8, Range<usize>)>;
+
+/// Underglow as it's stored and sent around: r, g, b, first LED, end LED (exclusive).
+/// An empty range means off.
+pub type UnderglowBytes = [u8; 5];
+
+pub fn underglow_from_bytes(bytes: UnderglowBytes) -> Underglow {
+    let [r, g, b, start, end] = bytes;
+    (start < end).then(|| (RGB8 { r, g, b }, start as usize..end as usize))
+}
+
+pub fn underglow_to_bytes(underglow: &Underglow) -> UnderglowBytes {
+    match underglow {
+        Some((color, range)) => [
+            color.r,
+            color.g,
+            color.b,
+            range.start.min(u8::MAX as usize) as u8,
+            range.end.min(u8::MAX as usize) as u8,
+        ],
+        None => [0; 5],
+    }
+}
+
+/// Brightness and effect of one BLE profile, so each one can look different
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct ProfileLeds {
+    /// Global frame scale like the console's `brightness`, 255 = colors as written
+    pub brightness: u8,
+    pub effect: LedEffect,
+}
+
+/// Every profile starts out at full brightness on the plain status display
+pub const PROFILE_LEDS_DEFAULT: ProfileLeds = ProfileLeds {
+    brightness: 255,
+    effect: LedEffect::Status,
+};
+
+/// One entry per BLE profile (BLE1-BLE3)
+pub const PROFILE_LEDS_COUNT: usize = 3;
+
+/// Stored as brightness, effect for each profile in order: 3 profiles x 2 bytes
+pub type ProfileLedsBytes = [u8; PROFILE_LEDS_COUNT * 2];
+
+pub const fn profile_leds_from_bytes(bytes: ProfileLedsBytes) -> [ProfileLeds; PROFILE_LEDS_COUNT] {
+    let mut profiles = [PROFILE_LEDS_DEFAULT; PROFILE_LEDS_COUNT];
+    let mut i = 0;
+    while i < PROFILE_LEDS_COUNT {
+        profiles[i] = ProfileLeds {
+            brightness: bytes[i * 2],
+            effect: LedEffect::from_byte(bytes[i * 2 + 1]),
+        };
+        i += 1;
+    }
+    profiles
+}
+
+pub const fn profile_leds_to_bytes(
+    profiles: &[ProfileLeds; PROFILE_LEDS_COUNT],
+) -> ProfileLedsBytes {
+    let mut bytes = [0; PROFILE_LEDS_COUNT * 2];
+    let mut i = 0;
+    while i < PROFILE_LEDS_COUNT {
+        bytes[i * 2] = profiles[i].brightness;
+        bytes[i * 2 + 1] = profiles[i].effect.to_byte();
+        i += 1;
+    }
+    bytes
+}
+
+// A dim party profile, a scanner profile and one with an effect byte from a newer firmware
+const PROFILE_LEDS_STORED: [ProfileLeds; PROFILE_LEDS_COUNT] =
+    profile_leds_from_bytes([40, 1, 255, 2, 255, 9]);
+const _: () = assert!(PROFILE_LEDS_STORED[0].brightness == 40);
+const _: () = assert!(matches!(PROFILE_LEDS_STORED[0].effect, LedEffect::Party));
+const _: () = assert!(matches!(PROFILE_LEDS_STORED[1].effect, LedEffect::Scanner));
+const _: () = assert!(matches!(PROFILE_LEDS_STORED[2].effect, LedEffect::Status));
+const _: () = assert!(profile_leds_to_bytes(&PROFILE_LEDS_STORED)[3] == 2);
+const _: () = assert!(profile_leds_to_bytes(&PROFILE_LEDS_STORED)[5] == 0);
+
+/// A value shown briefly as a count of lit LEDs, for other modules to report something on the bar
+#[derive(Clone, Copy)]
+pub struct LedReadout {
+    pub count: u8,
+    pub color: RGB8,
+}
+
+/// Picked up by `StatusLedController` on its next poll
+pub static LED_READOUT: Signal<CriticalSectionRawMutex, LedReadout> = Signal::new();
+
+/// Relative usage per matrix key (row by row), 255 = the most used key.
+/// Picked up by `StatusLedController` on its next poll and shown like a readout.
+pub static LED_HEATMAP: Signal<CriticalSectionRawMutex, [u8; SIZE]> = Signal::new();
+
+/// The strip goes dark after this long without key activity
+pub const LED_SLEEP_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// When true, the key that wakes the LEDs is only a wake-up tap and never reaches the host
+pub const TAP_TO_WAKE_SWALLOWS_KEY: bool = false;
+
+/// Set by `StatusLedController` while the LEDs sleep, if going to sleep turned lit LEDs off.
+///
+/// Key events are only published after the keyboard has already processed them, so the
+/// LED controller can't hold a key back itself. Instead the matrix debouncer reads this flag
+/// and drops the waking press (and its release) before the matrix ever reports it, then raises
+/// `LED_WAKE` so the controller wakes up without seeing a key event.
+pub static LEDS_ASLEEP: AtomicBool = AtomicBool::new(false);
+
+/// Whether the LED MOSFET is on, kept by `StatusLedController`. thermal_task reads it.
+pub static LEDS_POWERED: AtomicBool = AtomicBool::new(false);
+
+/// Raised by the debouncer when it swallowed a waking press, picked up on the next poll
+pub static LED_WAKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Raised when one of our settings writes (or the dev console erase) fails,
+/// `StatusLedController` flashes alternating red LEDs.
+///
+/// Only covers this crate's settings region. RMK handles its own storage (keymap, bonds) and
+/// only logs its write errors, so a failed RMK write won't show here.
+pub static STORAGE_ERROR: Signal<CriticalSectionRawMutex, ()> = Signal::new();
@@ -1,49 +1,94 @@
+use defmt::{Format, warn};
 use embassy_nrf::gpio::Output;
-use embassy_nrf::spim::Spim;
 use embassy_time::Timer;
 use smart_leds::{RGB8, SmartLedsWrite};
-use ws2812_spi::Ws2812;
 
-pub struct StartupAnimator<'d, const N: usize> {
-    ws2812: Ws2812<Spim<'d>>,
+use super::LED_MIN_BATTERY_PERCENT;
+use super::timing::{LED_COLOR_ORDER, LED_REVERSED, to_physical_order, to_wire_order};
+
+/// What runs at boot, picked per power source in main
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum StartupAnimation {
+    /// Wave across the strip, then a blue flash, ~1.8s
+    Full,
+    /// Just a short dim blue flash, so there's still a sign of life
+    Minimal,
+    /// Strip stays off, the LED rail isn't even switched on
+    Off,
+}
+
+/// Same LED sink as `StatusLedController`, it's handed over with `take()` afterwards
+pub struct StartupAnimator<'d, W: SmartLedsWrite<Color = RGB8>, const N: usize> {
+    leds: W,
     power_pin: Output<'d>,
 }
 
-impl<'d, const N: usize> StartupAnimator<'d, N> {
-    pub fn new(ws2812: Ws2812<Spim<'d>>, power_pin: Output<'d>) -> Self {
-        Self { ws2812, power_pin }
+impl<'d, W: SmartLedsWrite<Color = RGB8>, const N: usize> StartupAnimator<'d, W, N> {
+    // Fails the build for an empty strip, same check as StatusLedController
+    const HAS_LEDS: () = assert!(N >= 1, "StartupAnimator needs at least one LED");
+
+    pub fn new(leds: W, power_pin: Output<'d>) -> Self {
+        let () = Self::HAS_LEDS;
+        Self { leds, power_pin }
     }
 
-    /// Bootup animation: wave effect from start to end
-    pub async fn bootup_animation(&mut self) {
+    /// Bootup animation: wave effect from start to end, or less depending on `animation`
+    /// Skipped entirely if `battery_percentage` is below `LED_MIN_BATTERY_PERCENT`
+    pub async fn bootup_animation(&mut self, animation: StartupAnimation, battery_percentage: u8) {
+        if animation == StartupAnimation::Off {
+            return;
+        }
+        if battery_percentage < LED_MIN_BATTERY_PERCENT {
+            warn!(
+                "Battery at {}% - skipping startup animation (min {}%)",
+                battery_percentage, LED_MIN_BATTERY_PERCENT
+            );
+            return;
+        }
+
         // Turn on LED power
         self.power_pin.set_high();
+        if animation == StartupAnimation::Minimal {
+            self.write([RGB8 { r: 0, g: 0, b: 15 }; N]);
+            Timer::after_millis(150).await;
+            self.write([RGB8::default(); N]);
+            Timer::after_millis(50).await;
+            self.power_pin.set_low();
+            return;
+        }
         // Wave effect - light up each LED in sequence
         for i in 0..N {
             let mut data = [RGB8::default(); N];
             for j in 0..=i {
                 data[j] = RGB8 { r: 60, g: 20, b: 0 }; // Maybe Orange color
             }
-            let _ = self.ws2812.write(data.iter().cloned());
+            self.write(data);
             Timer::after_millis(100).await;
         }
 
         // Flash all LEDs white
         let data = [RGB8 { r: 0, g: 0, b: 50 }; N];
-        let _ = self.ws2812.write(data.iter().cloned());
+        self.write(data);
         Timer::after_millis(300).await;
 
         // Turn off all LEDs
         let data = [RGB8::default(); N];
-        let _ = self.ws2812.write(data.iter().cloned());
+        self.write(data);
         Timer::after_millis(50).await;
 
         // Turn off LED power to save power
         self.power_pin.set_low();
     }
 
-    /// Return the ws2812 controller and power pin for use elsewhere
-    pub fn take(self) -> (Ws2812<Spim<'d>>, Output<'d>) {
-        (self.ws2812, self.power_pin)
+    fn write(&mut self, data: [RGB8; N]) {
+        let data = to_physical_order(data, LED_REVERSED);
+        let _ = self
+            .leds
+            .write(data.iter().map(|led| to_wire_order(*led, LED_COLOR_ORDER)));
+    }
+
+    /// Return the LED sink and power pin for use elsewhere
+    pub fn take(self) -> (W, Output<'d>) {
+        (self.leds, self.power_pin)
     }
 }
@@ -1,80 +1,770 @@
-use defmt::info;
+use core::sync::atomic::Ordering;
+
+use defmt::{Format, info, warn};
 use embassy_nrf::gpio::Output;
-use embassy_nrf::spim::Spim;
+use embassy_time::{Duration, Instant, Timer};
+#[cfg(feature = "ble")]
 use rmk::ble::BleState;
-use rmk::event::{
-    BatteryStateEvent, BleProfileChangeEvent, BleStateChangeEvent, ConnectionChangeEvent,
-    ConnectionType, KeyEvent,
-};
+use rmk::event::{BatteryStateEvent, ConnectionChangeEvent, ConnectionType, KeyEvent};
+#[cfg(feature = "ble")]
+use rmk::event::{BleProfileChangeEvent, BleStateChangeEvent};
 use rmk::macros::controller;
-use rmk::types::action::Action;
+use rmk::td;
+use rmk::types::action::{Action, KeyAction};
 use smart_leds::{RGB8, SmartLedsWrite};
-use ws2812_spi::Ws2812;
 
-#[controller(subscribe = [ConnectionChangeEvent, BleStateChangeEvent, BatteryStateEvent, BleProfileChangeEvent, KeyEvent], poll_interval = 700)]
-pub struct StatusLedController<'d, const N: usize> {
-    ws2812: Ws2812<Spim<'d>>,
+use super::demo::DemoCycle;
+use super::effects::{
+    FOCUS_TIMER_RESET_HOLD, FocusTimer, LedEffect, PartyEffect, ScannerEffect, heat_color, scale,
+};
+use super::timing::{LED_COLOR_ORDER, LED_REVERSED, to_physical_order, to_wire_order};
+#[cfg(feature = "dev-console")]
+use super::underglow_from_bytes;
+use super::{
+    BATTERY_UNKNOWN, LED_CURRENT_BUDGET_MA, LED_HEATMAP, LED_MIN_BATTERY_PERCENT, LED_READOUT,
+    LED_SLEEP_TIMEOUT, LED_WAKE, LEDS_ASLEEP, LEDS_POWERED, LedReadout, PROFILE_LEDS_COUNT,
+    PROFILE_LEDS_DEFAULT, ProfileLeds, STORAGE_ERROR, Underglow, battery_bar_leds,
+    battery_bar_leds_hysteresis, below_battery_floor, frame_current_ma, power_budget_scale,
+    profile_leds_to_bytes,
+};
+use crate::advertising::AdvertisingBackoff;
+#[cfg(feature = "ambient-light")]
+use crate::ambient_light::AMBIENT_BRIGHTNESS;
+#[cfg(feature = "dev-console")]
+use crate::debug_console::{CONSOLE_CHANNEL, ConsoleCommand};
+use crate::keyboard_lock::KEYBOARD_LOCKED;
+use crate::keymap::{
+    BOOTLOADER_HOLD_TIMEOUT_MS, BOOTLOADER_TAPDANCE, CONFIRM_TAPDANCES,
+    DESTRUCTIVE_CONFIRM_WINDOW_MS, MACRO_FLASH_MS, MACRO_FLASHES, SIZE,
+};
+use crate::output::{EFFECTIVE_OUTPUT, EffectiveOutput};
+use crate::settings::{SETTINGS_CHANNEL, SettingsUpdate};
+use crate::thermal::THERMAL_OVERHEAT;
+use crate::user_actions::{BATT_CHECK, EFFECT_NEXT, FOCUS_TIMER, LED_LOCK, LEDS_TOGGLE};
+use crate::vbus::VBUS_PRESENT;
+
+/// Everything that can own the strip. See `LED_PRIORITY` for the order.
+#[derive(Clone, Copy)]
+enum LedSource {
+    LedsOff,
+    LockedColor,
+    KeyboardLock,
+    BootloaderArmed,
+    Confirm,
+    StorageError,
+    BatteryCheck,
+    Readout,
+    Heatmap,
+    Demo,
+    Base,
+}
+
+// Which source owns the strip, highest priority first. frame() asks each one in this order and
+// the first one with something to show gets the whole strip, nothing below it is drawn:
+// LEDs-off flash > LED_LOCK color > keyboard lock > bootloader armed > confirm prompt >
+// storage error > battery check > readout > heatmap > demo mode > base.
+// Base is always there, and is itself layered (top first): connect / advertising blink on the
+// profile LED > focus timer > effect or status display with the profile indicator.
+// The underglow goes under all of it - it only fills LEDs the winning frame left dark (and
+// never the status region), so a battery check or readout only covers the LEDs it lights.
+// A new indicator gets a variant and a place in this list, nothing else decides the order.
+const LED_PRIORITY: [LedSource; 11] = [
+    LedSource::LedsOff,
+    LedSource::LockedColor,
+    LedSource::KeyboardLock,
+    LedSource::BootloaderArmed,
+    LedSource::Confirm,
+    LedSource::StorageError,
+    LedSource::BatteryCheck,
+    LedSource::Readout,
+    LedSource::Heatmap,
+    LedSource::Demo,
+    LedSource::Base,
+];
+
+// Every source exactly once, with the always-on base last so nothing ends up under it
+const fn priority_complete(order: &[LedSource]) -> bool {
+    let mut seen = 0u32;
+    let mut i = 0;
+    while i < order.len() {
+        let bit = 1 << order[i] as u32;
+        if seen & bit != 0 {
+            return false;
+        }
+        seen |= bit;
+        i += 1;
+    }
+    seen == (1 << (LedSource::Base as u32 + 1)) - 1
+        && matches!(order[order.len() - 1], LedSource::Base)
+}
+
+const _: () = assert!(priority_complete(&LED_PRIORITY));
+const _: () = assert!(!priority_complete(&[LedSource::Base, LedSource::Base]));
+
+// Bootloader entry confirmation - fast purple flash right before jumping to DFU
+const BOOTLOADER_COLOR: RGB8 = RGB8 { r: 50, g: 0, b: 50 };
+const BOOTLOADER_FLASH_COUNT: u8 = 3;
+const BOOTLOADER_FLASH_MS: u64 = 80;
+// When true, the hold only arms bootloader entry and a second tap within the window confirms it
+const BOOTLOADER_REQUIRE_CONFIRM_TAP: bool = true;
+const BOOTLOADER_CONFIRM_WINDOW_MS: u64 = 2000;
+
+// Whole bar, shown by the battery check when the ADC failed at boot
+const BATTERY_UNKNOWN_COLOR: RGB8 = RGB8 { r: 8, g: 8, b: 8 };
+
+// Shown between the first and second tap of a destructive action
+const CONFIRM_COLOR: RGB8 = RGB8 { r: 40, g: 20, b: 0 };
+
+// How long an `LED_READOUT` value stays on the bar
+const READOUT_DURATION_MS: u64 = 1500;
+
+// The heatmap has more to look at than a readout
+const HEATMAP_DURATION_MS: u64 = 4000;
+
+// Single flash right before the LEDs turn off for good, the last thing they show
+const LEDS_OFF_CONFIRM_COLOR: RGB8 = RGB8 { r: 30, g: 30, b: 30 };
+const LEDS_OFF_CONFIRM_MS: u64 = 150;
+
+// What an LED_TOGGLE press does, from whether the off flash is showing and whether the LEDs
+// are already off
+#[derive(Clone, Copy)]
+enum LedsToggle {
+    // Pressed again during the flash
+    StayOn,
+    Enable,
+    StartOff,
+}
+
+const fn leds_toggle(flashing: bool, disabled: bool) -> LedsToggle {
+    if flashing {
+        LedsToggle::StayOn
+    } else if disabled {
+        LedsToggle::Enable
+    } else {
+        LedsToggle::StartOff
+    }
+}
+
+/// Whether poll at `now_ms` ends the flash, `off_at_ms` is set while it's showing
+const fn leds_off_due(off_at_ms: Option<u64>, now_ms: u64) -> bool {
+    match off_at_ms {
+        Some(off_at) => now_ms >= off_at,
+        None => false,
+    }
+}
+
+// The sequence as poll() steps it, pressed at 0: flash until LEDS_OFF_CONFIRM_MS, then off
+// leds_off_at of that press
+const PRESSED_AT_0: Option<u64> = Some(LEDS_OFF_CONFIRM_MS);
+const _: () = assert!(matches!(leds_toggle(false, false), LedsToggle::StartOff));
+const _: () = assert!(!leds_off_due(PRESSED_AT_0, LEDS_OFF_CONFIRM_MS - 1));
+const _: () = assert!(leds_off_due(PRESSED_AT_0, LEDS_OFF_CONFIRM_MS));
+// A late poll still finishes it, and nothing is due without a flash
+const _: () = assert!(leds_off_due(PRESSED_AT_0, 10 * LEDS_OFF_CONFIRM_MS));
+const _: () = assert!(!leds_off_due(None, LEDS_OFF_CONFIRM_MS));
+// A press during the flash cancels it, the next one once they're off turns them back on
+const _: () = assert!(matches!(leds_toggle(true, false), LedsToggle::StayOn));
+const _: () = assert!(matches!(leds_toggle(false, true), LedsToggle::Enable));
+
+// Event delivery: the controller gets each event type through its own subscriber queue on
+// RMK's pubsub channels. While a handler is busy, whatever comes in meanwhile waits in there
+// and is handled in order once it returns, so a slow handler only delays events. Once a queue
+// is full though, RMK publishes without waiting for slow subscribers and the oldest event is
+// dropped. For the toggle trick a lost key event is a key stuck "held" (BATT_CHECK showing the
+// battery until the next press), and a lost BleState is a stale profile LED.
+// So no handler here awaits a timer: every timed sequence (connect blink, LEDs-off flash,
+// readouts, confirm windows) is a deadline that poll() steps. The only waits left are
+// SETTINGS_CHANNEL sends, which only wait while the settings store is that many writes
+// behind, and enter_bootloader, which never returns.
+//
+// The queue depths are RMK's (ca38784), fixed per event type where its events are declared,
+// nothing in this tree can make them deeper. A handler that has to block would need its own
+// task draining the subscriber into a bounded queue of ours.
+
+// Profile LED while the keyboard is locked, see keyboard_lock.rs
+const KEYBOARD_LOCKED_COLOR: RGB8 = RGB8 { r: 40, g: 0, b: 0 };
+
+// LED_LOCK steps through these, then back to normal. Full value and never dimmed by the
+// brightness settings, only the LED power budget caps them (white on all 14 LEDs is over it).
+const LOCK_COLORS: [RGB8; 4] = [
+    RGB8 { r: 255, g: 0, b: 0 },
+    RGB8 { r: 0, g: 255, b: 0 },
+    RGB8 { r: 0, g: 0, b: 255 },
+    RGB8 {
+        r: 255,
+        g: 255,
+        b: 255,
+    },
+];
+
+// Advertising blink on the active profile's LED
+const ADVERTISING_COLOR: RGB8 = RGB8 { r: 0, g: 0, b: 70 };
+
+// First pairing (no bonds at boot, see main.rs): instead of the advertising blink, the profile
+// LEDs take turns pulsing slowly in their own color - press one of the profile keys to pair.
+// Ends for good with the first connection.
+const FIRST_PAIRING_COLORS: [RGB8; 3] = [
+    RGB8 { r: 0, g: 0, b: 70 },
+    RGB8 { r: 0, g: 70, b: 0 },
+    RGB8 { r: 50, g: 0, b: 50 },
+];
+const FIRST_PAIRING_PULSE_MS: u64 = 2000;
+
+/// Brightness `ms` into the pulses, 0 -> 255 -> 0 once per FIRST_PAIRING_PULSE_MS
+const fn pulse_level(ms: u64) -> u8 {
+    let half = FIRST_PAIRING_PULSE_MS / 2;
+    let phase = ms % FIRST_PAIRING_PULSE_MS;
+    let rising = if phase < half {
+        phase
+    } else {
+        FIRST_PAIRING_PULSE_MS - phase
+    };
+    (rising * 255 / half) as u8
+}
+
+const _: () = assert!(pulse_level(0) == 0 && pulse_level(FIRST_PAIRING_PULSE_MS) == 0);
+const _: () = assert!(pulse_level(FIRST_PAIRING_PULSE_MS / 2) == 255);
+const _: () = assert!(pulse_level(FIRST_PAIRING_PULSE_MS / 4) == 127);
+
+// Blinks on the active profile's LED once a BLE connection comes up, 0 blinks skips it.
+// Override per board with `with_connect_blink`.
+const CONNECT_BLINK_COUNT_DEFAULT: u8 = 4;
+const CONNECT_BLINK_COLOR_DEFAULT: RGB8 = RGB8 { r: 0, g: 70, b: 0 };
+const CONNECT_BLINK_MS: u64 = 500;
+// Per-profile blink counts stop here, whatever the profile index says
+const CONNECT_BLINK_MAX: u8 = 8;
+
+// At most one strip write per this from the event handlers. Fast typing with the heatmap or a
+// key-driven effect would otherwise write the strip on every press and release, and each write
+// is a blocking SPI transfer plus LED current. A render that comes in too soon is skipped, the
+// state change stays in place and the next poll (at most 50ms later) draws it together with
+// whatever else changed in between. Everything timed - the connect blink, advertising blink,
+// readout expiry, animations - is stepped in poll(), whose draw always goes through, so their
+// timing doesn't move. Only how fast a keypress shows up on the strip is capped.
+const MIN_RENDER_INTERVAL: Duration = Duration::from_millis(20);
+
+// Dim marker on the active profile's LED while connected over BLE and nothing else is showing
+const PROFILE_INDICATOR_COLOR: RGB8 = RGB8 { r: 0, g: 6, b: 0 };
+// Same spot, while USB has the reports and the BLE link is only standing by
+const BLE_STANDBY_COLOR: RGB8 = RGB8 { r: 0, g: 0, b: 6 };
+
+// Failed settings write - alternating red LEDs that swap every STORAGE_ERROR_SWAP_MS.
+// A dying flash fails every write, so after one showing further errors are only logged
+// until the cooldown has passed.
+const STORAGE_ERROR_COLOR: RGB8 = RGB8 { r: 70, g: 0, b: 0 };
+const STORAGE_ERROR_SWAP_MS: u64 = 250;
+const STORAGE_ERROR_DURATION_MS: u64 = 2000;
+const STORAGE_ERROR_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+// On battery (no VBUS, see vbus.rs) the strip is dimmed on top of the brightness above,
+// and party fades run twice as fast
+const ON_BATTERY_BRIGHTNESS: u8 = 128;
+
+// poll() runs every 50ms for effect animation, the advertising blink keeps its own slower pace
+const BLINK_INTERVAL_MS: u64 = 700;
+// Once advertising backs off (see advertising.rs) the blink does too, down to a short flash
+// every few seconds
+const SLOW_BLINK_ON_MS: u64 = 150;
+const SLOW_BLINK_OFF_MS: u64 = 3000;
+
+/// Read-only copy of what `StatusLedController` currently knows, see `snapshot()`.
+/// Picked for tests and telemetry, anything purely internal to an animation stays out.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+#[cfg_attr(not(feature = "dev-console"), allow(dead_code))]
+pub struct StatusSnapshot {
+    /// BLE profile the indicator shows (0-2)
+    pub ble_profile: u8,
+    pub ble_connected: bool,
+    /// Advertising blink is on
+    pub advertising: bool,
+    pub usb_mode: bool,
+    pub output: Option<EffectiveOutput>,
+    /// Last reading, `BATTERY_UNKNOWN` if the ADC never came up
+    pub battery_percentage: u8,
+    /// Held off by the battery floor
+    pub below_floor: bool,
+    /// BATT_CHECK is being held
+    pub showing_battery: bool,
+    pub effect: LedEffect,
+    pub asleep: bool,
+    pub leds_disabled: bool,
+    pub keyboard_locked: bool,
+    /// The strip is powered and was last written with something lit
+    pub leds_on: bool,
+    /// Something other than the base layer owns the strip (readout, confirm prompt, ...)
+    pub transient: bool,
+}
+
+// USB-only builds (no `ble` feature) never see BLE events: no advertising blink, no connect
+// blink and no profile LED, the strip stays dark apart from battery checks, readouts and effects
+#[cfg_attr(
+    feature = "ble",
+    controller(subscribe = [ConnectionChangeEvent, BleStateChangeEvent, BatteryStateEvent, BleProfileChangeEvent, KeyEvent], poll_interval = 50)
+)]
+#[cfg_attr(
+    not(feature = "ble"),
+    controller(subscribe = [ConnectionChangeEvent, BatteryStateEvent, KeyEvent], poll_interval = 50)
+)]
+/// Generic over the LED sink: anything that takes a frame of `RGB8` through `SmartLedsWrite`.
+/// The firmware passes the `Ws2812` on SPIM3 from main, `capture::FrameCapture` records frames
+/// instead, for checking what the controller draws without a strip attached.
+pub struct StatusLedController<'d, W: SmartLedsWrite<Color = RGB8>, const N: usize> {
+    leds: W,
     power_pin: Output<'d>,
     should_blink: bool,
+    blink_on: bool,
     leds_on: bool,
+    ble_connected: bool,
+    usb_mode: bool,
+    // From OutputMonitor, None until its first report
+    output: Option<EffectiveOutput>,
     current_ble_profile: u8,
+    // What's in flash, only written when the profile actually changes
+    #[cfg_attr(not(feature = "ble"), allow(dead_code))]
+    saved_ble_profile: u8,
     battery_percentage: u8,
+    // LEDs the battery bar shows right now, only moves with hysteresis
+    last_battery_leds: usize,
+    // Under the battery floor (with hysteresis), see `below_battery_floor`
+    below_floor: bool,
     is_showing_battery: bool,
-    user7_held: bool,
+    batt_check_held: bool,
+    bootloader_key_pressed_at: Option<Instant>,
+    bootloader_armed_until: Option<Instant>,
+    readout: Option<(LedReadout, Instant)>,
+    heatmap: Option<([u8; SIZE], Instant)>,
+    confirm_until: Option<Instant>,
+    last_activity: Instant,
+    asleep: bool,
+    leds_disabled: bool,
+    // LEDs-off flash showing, the strip goes dark at this point
+    leds_off_at: Option<Instant>,
+    leds_toggle_held: bool,
+    brightness: u8,
+    on_battery: bool,
+    effect: LedEffect,
+    effect_key_held: bool,
+    // Brightness and effect per BLE profile, the active one is in brightness/effect
+    profile_leds: [ProfileLeds; PROFILE_LEDS_COUNT],
+    // Index into LOCK_COLORS while LED_LOCK has the strip
+    locked_color: Option<usize>,
+    lock_key_held: bool,
+    // Keyboard lock (keyboard_lock.rs), not to be confused with LED_LOCK
+    keyboard_locked: bool,
+    // Last flushed frame was scaled down to LED_CURRENT_BUDGET_MA, only for the log
+    power_limited: bool,
+    focus_timer: FocusTimer,
+    // Set while FOCUS_TIMER is held, a long hold resets instead of toggling
+    focus_timer_pressed_at: Option<Instant>,
+    party: PartyEffect<N>,
+    scanner: ScannerEffect<N>,
+    // Demo mode (`demo` feature), until the first key press or BLE connection
+    demo: Option<DemoCycle<N>>,
+    last_blink: Instant,
+    advertising: AdvertisingBackoff,
+    connect_blink_enabled: bool,
+    connect_blink_count: u8,
+    connect_blink_color: RGB8,
+    // Blink profile + 1 times instead of connect_blink_count
+    connect_blink_per_profile: bool,
+    // Steady per-profile color while connected, replaces the connect blink when set
+    connected_colors: Option<[RGB8; 3]>,
+    // Nothing was ever paired, advertising shows the first pairing pulse
+    first_pairing: bool,
+    // First LEDs that show status, the rest is underglow. None = no split.
+    status_leds: Option<usize>,
+    // On and off phases still to show, each one CONNECT_BLINK_MS long
+    connect_blink_phases_left: u16,
+    connect_blink_next: Option<Instant>,
+    // Last frame handed to flush(), render() skips the write when nothing changed
+    last_frame: Option<[RGB8; N]>,
+    // When render() last wrote the strip, for MIN_RENDER_INTERVAL
+    last_render: Option<Instant>,
+    overheated: bool,
+    // When the error pattern last started, it runs for STORAGE_ERROR_DURATION_MS from there
+    storage_error_at: Option<Instant>,
+    underglow: Underglow,
 }
 
-impl<'d, const N: usize> StatusLedController<'d, N> {
-    pub fn new(ws2812: Ws2812<Spim<'d>>, power_pin: Output<'d>) -> Self {
+impl<'d, W: SmartLedsWrite<Color = RGB8>, const N: usize> StatusLedController<'d, W, N> {
+    // profile_index() and the battery bar take N - 1. A single LED works (everything lands on
+    // it), an empty strip fails the build here instead of underflowing at runtime.
+    const HAS_LEDS: () = assert!(N >= 1, "StatusLedController needs at least one LED");
+
+    /// `leds_disabled` is the saved preference, read from settings at boot.
+    /// `battery_percentage` is the boot reading, `BATTERY_UNKNOWN` if the ADC didn't come up.
+    /// `active_ble_profile` is the saved profile, the indicator starts there.
+    pub fn new(
+        leds: W,
+        power_pin: Output<'d>,
+        leds_disabled: bool,
+        battery_percentage: u8,
+        active_ble_profile: u8,
+    ) -> Self {
+        let () = Self::HAS_LEDS;
         Self {
-            ws2812,
+            leds,
             power_pin,
-            should_blink: true, // Start true - we're advertising on boot, event may be missed due to race
+            // Start true - we're advertising on boot, event may be missed due to race
+            should_blink: cfg!(feature = "ble"),
+            blink_on: false,
             leds_on: false,
-            current_ble_profile: 0,
-            battery_percentage: 100,
+            ble_connected: false,
+            usb_mode: false,
+            output: None,
+            current_ble_profile: active_ble_profile,
+            saved_ble_profile: active_ble_profile,
+            battery_percentage,
+            last_battery_leds: battery_bar_leds(battery_percentage, N),
+            below_floor: below_battery_floor(false, battery_percentage),
             is_showing_battery: false,
-            user7_held: false,
+            batt_check_held: false,
+            bootloader_key_pressed_at: None,
+            bootloader_armed_until: None,
+            readout: None,
+            heatmap: None,
+            confirm_until: None,
+            last_activity: Instant::now(),
+            asleep: false,
+            leds_disabled,
+            leds_off_at: None,
+            leds_toggle_held: false,
+            brightness: PROFILE_LEDS_DEFAULT.brightness,
+            // vbus_task reports the real state right after boot
+            on_battery: false,
+            effect: LedEffect::Status,
+            effect_key_held: false,
+            profile_leds: [PROFILE_LEDS_DEFAULT; PROFILE_LEDS_COUNT],
+            locked_color: None,
+            lock_key_held: false,
+            keyboard_locked: false,
+            power_limited: false,
+            focus_timer: FocusTimer::new(),
+            focus_timer_pressed_at: None,
+            party: PartyEffect::new(),
+            scanner: ScannerEffect::new(),
+            demo: cfg!(feature = "demo").then(DemoCycle::new),
+            last_blink: Instant::now(),
+            advertising: AdvertisingBackoff::new(cfg!(feature = "ble")),
+            connect_blink_enabled: true,
+            connect_blink_count: CONNECT_BLINK_COUNT_DEFAULT,
+            connect_blink_color: CONNECT_BLINK_COLOR_DEFAULT,
+            connect_blink_per_profile: false,
+            connected_colors: None,
+            first_pairing: false,
+            status_leds: None,
+            connect_blink_phases_left: 0,
+            connect_blink_next: None,
+            last_frame: None,
+            last_render: None,
+            overheated: false,
+            storage_error_at: None,
+            underglow: None,
+        }
+    }
+
+    /// How often and in which color the profile LED blinks when a connection comes up.
+    /// `count` 0 means no blink, the LEDs settle straight to the profile indicator.
+    pub fn with_connect_blink(mut self, count: u8, color: RGB8) -> Self {
+        self.connect_blink_count = count;
+        self.connect_blink_color = color;
+        self
+    }
+
+    /// `false` skips the connect blink altogether, like a count of 0 but whatever the count
+    /// and per-profile settings say. The advertising blink still stops on connect, so the
+    /// profile LED goes straight to the connected indicator.
+    pub fn with_connect_blink_enabled(mut self, enabled: bool) -> Self {
+        self.connect_blink_enabled = enabled;
+        self
+    }
+
+    /// Blink profile index + 1 times on connect (profile 0 = 1 blink, profile 2 = 3) instead of
+    /// the fixed count, so the blink itself says which profile connected. Capped at
+    /// `CONNECT_BLINK_MAX`. A count of 0 in `with_connect_blink` still turns the blink off.
+    pub fn with_connect_blink_per_profile(mut self, per_profile: bool) -> Self {
+        self.connect_blink_per_profile = per_profile;
+        self
+    }
+
+    /// Steady color on the profile LED while connected over BLE, one per profile, instead of the
+    /// connect blink and `PROFILE_INDICATOR_COLOR`. `None` keeps the blink.
+    /// It's part of the base layer, so USB mode, battery checks and readouts still cover it.
+    pub fn with_connected_colors(mut self, colors: Option<[RGB8; 3]>) -> Self {
+        self.connected_colors = colors;
+        self
+    }
+
+    /// No host was ever paired: advertising shows the first pairing pulse instead of the blink,
+    /// until the first connection. See main.rs for where it comes from.
+    pub fn with_first_pairing(mut self, first_pairing: bool) -> Self {
+        self.first_pairing = first_pairing;
+        self
+    }
+
+    /// Splits the strip into a status region (the first `status_leds` LEDs) and an underglow
+    /// region (the rest). The status region shows the BLE profile, advertising and connect blinks,
+    /// the underglow region runs the current effect or the static underglow, each on its own.
+    /// Battery checks, readouts, the heatmap, confirm prompts and errors still take the whole
+    /// bar while they're up, then both regions come back. `None` keeps the strip in one piece.
+    pub fn with_status_region(mut self, status_leds: Option<usize>) -> Self {
+        self.status_leds = status_leds.map(|n| n.clamp(1, N));
+        self
+    }
+
+    /// LEDs in `range` stay lit in `color` while awake, under everything else - a battery check,
+    /// blink or readout only covers the LEDs it actually lights.
+    /// Brightness and the LEDs-off preference apply to it like to everything else.
+    pub fn with_underglow(mut self, underglow: Underglow) -> Self {
+        self.underglow = underglow;
+        self
+    }
+
+    /// LED brightness and effect for each BLE profile. The active profile's apply right away,
+    /// and every profile switch loads that profile's. EFFECT_NEXT and the console's
+    /// `brightness` / `effect` change the active profile's entry and save it. With
+    /// `ambient-light` the sensor owns the brightness, so only the effect follows the profile.
+    pub fn with_profile_leds(mut self, profile_leds: [ProfileLeds; PROFILE_LEDS_COUNT]) -> Self {
+        self.profile_leds = profile_leds;
+        self.apply_profile_leds();
+        self
+    }
+
+    fn apply_profile_leds(&mut self) {
+        let Some(profile) = self.profile_leds.get(self.current_ble_profile as usize) else {
+            return;
+        };
+        info!("Profile {} LEDs: {:?}", self.current_ble_profile, profile);
+        if !cfg!(feature = "ambient-light") {
+            self.brightness = profile.brightness;
         }
+        self.effect = profile.effect;
+        // Brightness may have changed, same frame or not
+        self.last_frame = None;
     }
 
-    fn blink_ble_profile_led_blue(&mut self) {
+    /// Stores the current brightness and effect as the active profile's, if they changed
+    async fn save_profile_leds(&mut self) {
+        let brightness = self.brightness;
+        let effect = self.effect;
+        let Some(profile) = self.profile_leds.get_mut(self.current_ble_profile as usize) else {
+            return;
+        };
+        let updated = ProfileLeds {
+            // A sensor reading isn't a preference
+            brightness: if cfg!(feature = "ambient-light") {
+                profile.brightness
+            } else {
+                brightness
+            },
+            effect,
+        };
+        if *profile == updated {
+            return;
+        }
+        *profile = updated;
+        SETTINGS_CHANNEL
+            .send(SettingsUpdate::ProfileLeds(profile_leds_to_bytes(
+                &self.profile_leds,
+            )))
+            .await;
+    }
+
+    /// Turns on the LED MOSFET, unless the battery is too low to handle the LED inrush
+    /// or the chip is overheating. Returns whether the strip is powered.
+    fn power_on(&mut self) -> bool {
+        if THERMAL_OVERHEAT.load(Ordering::Relaxed) {
+            warn!("Overheating - refusing to power LEDs");
+            self.power_off();
+            return false;
+        }
+        if self.below_floor {
+            warn!(
+                "Battery at {}% - refusing to power LEDs (min {}%)",
+                self.battery_percentage, LED_MIN_BATTERY_PERCENT
+            );
+            self.power_off();
+            return false;
+        }
         self.power_pin.set_high();
-        info!(
-            "Blinking blue LED: {} (max: {})",
-            self.current_ble_profile, N
-        );
-        let mut data = [RGB8 { r: 0, g: 0, b: 0 }; N];
+        LEDS_POWERED.store(true, Ordering::Relaxed);
+        true
+    }
 
-        // Bounds check to prevent panic
-        let profile_index = (self.current_ble_profile as usize).min(N - 1);
-        data[profile_index] = RGB8 { r: 0, g: 0, b: 70 };
+    /// Cuts the LED MOSFET, whatever was on the strip goes dark with it
+    fn power_off(&mut self) {
+        self.power_pin.set_low();
+        self.leds_on = false;
+        LEDS_POWERED.store(false, Ordering::Relaxed);
+    }
 
-        match self.ws2812.write(data.iter().cloned()) {
-            Ok(_) => {
-                info!("Successfully wrote LED data");
-                self.leds_on = true;
-            }
-            Err(_) => {
-                info!("Failed to write LED data");
+    /// Persistent layer that transient effects are drawn over and fall back to
+    fn base_frame(&self) -> [RGB8; N] {
+        if self.asleep {
+            return [RGB8::default(); N];
+        }
+        let mut data = match self.effect {
+            LedEffect::Party => self.party.frame(),
+            LedEffect::Scanner => self.scanner.frame(),
+            LedEffect::Status => [RGB8::default(); N],
+        };
+        match self.status_leds {
+            // Without a split an effect takes the whole strip
+            None if self.effect != LedEffect::Status => return data,
+            None => {}
+            // Effects still run over the whole strip, only the underglow part of it is shown
+            Some(status_leds) => data[..status_leds].fill(RGB8::default()),
+        }
+        if self.output == Some(EffectiveOutput::UsbBleStandby) {
+            data[self.profile_index()] = BLE_STANDBY_COLOR;
+        } else if self.ble_connected && !self.usb_mode {
+            data[self.profile_index()] = self
+                .connected_colors
+                .and_then(|colors| colors.get(self.current_ble_profile as usize).copied())
+                .unwrap_or(PROFILE_INDICATOR_COLOR);
+        }
+        data
+    }
+
+    // Bounds check to prevent panic, and it stays inside the status region
+    fn profile_index(&self) -> usize {
+        (self.current_ble_profile as usize).min(self.status_leds.unwrap_or(N) - 1)
+    }
+
+    /// Global scale for every frame, from the dev console or the ambient light sensor,
+    /// whichever set it last
+    #[cfg(any(feature = "dev-console", feature = "ambient-light"))]
+    pub fn set_brightness(&mut self, value: u8) {
+        self.brightness = value;
+        // Same frame, different scale - has to be written again
+        self.last_frame = None;
+    }
+
+    #[cfg(feature = "dev-console")]
+    async fn handle_console(&mut self) {
+        let mut changed = false;
+        while let Ok(command) = CONSOLE_CHANNEL.try_receive() {
+            changed = true;
+            match command {
+                ConsoleCommand::Brightness(value) => {
+                    info!("Brightness: {}", value);
+                    self.set_brightness(value);
+                    self.save_profile_leds().await;
+                }
+                ConsoleCommand::Effect(effect) => {
+                    info!("LED effect: {:?}", effect);
+                    self.effect = effect;
+                    self.save_profile_leds().await;
+                }
+                ConsoleCommand::Underglow(bytes) => {
+                    self.underglow = underglow_from_bytes(bytes);
+                    info!("Underglow: {:?}", bytes);
+                    SETTINGS_CHANNEL
+                        .send(SettingsUpdate::Underglow(bytes))
+                        .await;
+                }
+                ConsoleCommand::Battery => {
+                    info!("Battery: {}%", self.battery_percentage);
+                }
+                ConsoleCommand::Status => {
+                    info!("{:?}", self.snapshot());
+                }
             }
         }
+        if changed {
+            self.force_refresh();
+        }
     }
 
-    fn blink_ble_profile_led_green(&mut self) {
-        self.power_pin.set_high();
-        info!(
-            "Blinking green LED: {} (max: {})",
-            self.current_ble_profile, N
-        );
-        let mut data = [RGB8 { r: 0, g: 0, b: 0 }; N];
+    /// Current state for tests and telemetry, the fields themselves stay private
+    #[cfg_attr(not(feature = "dev-console"), allow(dead_code))]
+    pub fn snapshot(&self) -> StatusSnapshot {
+        StatusSnapshot {
+            ble_profile: self.current_ble_profile,
+            ble_connected: self.ble_connected,
+            advertising: self.should_blink,
+            usb_mode: self.usb_mode,
+            output: self.output,
+            battery_percentage: self.battery_percentage,
+            below_floor: self.below_floor,
+            showing_battery: self.is_showing_battery,
+            effect: self.effect,
+            asleep: self.asleep,
+            leds_disabled: self.leds_disabled,
+            keyboard_locked: self.keyboard_locked,
+            leds_on: self.leds_on,
+            transient: self.transient_active(),
+        }
+    }
+
+    fn storage_error_showing(&self) -> bool {
+        self.storage_error_at
+            .is_some_and(|at| at.elapsed() < Duration::from_millis(STORAGE_ERROR_DURATION_MS))
+    }
 
-        // Bounds check to prevent panic
-        let profile_index = (self.current_ble_profile as usize).min(N - 1);
-        data[profile_index] = RGB8 { r: 0, g: 70, b: 0 };
+    /// Something other than the base layer currently owns the strip
+    fn transient_active(&self) -> bool {
+        self.locked_color.is_some()
+            || self.leds_off_at.is_some()
+            || self.demo.is_some()
+            || self.storage_error_showing()
+            || self.is_showing_battery
+            || self.readout.is_some()
+            || self.heatmap.is_some()
+            || self.bootloader_armed_until.is_some()
+            || self.confirm_until.is_some()
+            || self.connect_blink_next.is_some()
+            || self.focus_timer.is_running()
+    }
 
-        match self.ws2812.write(data.iter().cloned()) {
+    /// Every strip write goes through here. An all-black frame also cuts LED power.
+    fn flush(&mut self, mut data: [RGB8; N]) {
+        // Disabled means the MOSFET stays low and nothing is ever written
+        if self.leds_disabled {
+            return;
+        }
+        // A locked color is shown exactly as is
+        for led in data.iter_mut().filter(|_| self.locked_color.is_none()) {
+            *led = scale(*led, self.brightness);
+            if self.on_battery {
+                *led = scale(*led, ON_BATTERY_BRIGHTNESS);
+            }
+        }
+        // Everything, locked colors included, stays within the current budget
+        let current_ma = frame_current_ma(&data);
+        let budget_scale = power_budget_scale(current_ma, LED_CURRENT_BUDGET_MA);
+        if budget_scale < 255 {
+            for led in data.iter_mut() {
+                *led = scale(*led, budget_scale);
+            }
+        }
+        if (budget_scale < 255) != self.power_limited {
+            self.power_limited = budget_scale < 255;
+            if self.power_limited {
+                info!(
+                    "LED frame at ~{}mA, scaled to the {}mA budget",
+                    current_ma, LED_CURRENT_BUDGET_MA
+                );
+            } else {
+                info!("LED frame back within the power budget");
+            }
+        }
+        // Last steps before the strip, everything above works in plain RGB and logical order
+        let mut data = to_physical_order(data, LED_REVERSED);
+        for led in data.iter_mut() {
+            *led = to_wire_order(*led, LED_COLOR_ORDER);
+        }
+        if data.iter().all(|led| *led == RGB8::default()) {
+            let _ = self.leds.write(data.iter().cloned());
+            self.power_off();
+            return;
+        }
+        if !self.power_on() {
+            return;
+        }
+        // No success log here, effects write every tick
+        match self.leds.write(data.iter().cloned()) {
             Ok(_) => {
-                info!("Successfully wrote LED data");
                 self.leds_on = true;
             }
             Err(_) => {
@@ -83,47 +773,216 @@ impl<'d, const N: usize> StatusLedController<'d, N> {
         }
     }
 
-    fn clear_all_leds(&mut self) {
-        let data = [RGB8::default(); N];
-        let _ = self.ws2812.write(data.iter().cloned());
-        self.power_pin.set_low();
-        self.leds_on = false;
+    /// Draws whatever the current state says should be on the strip.
+    /// Handlers and poll() only change state and call this once at the end, so every change
+    /// shows up right away instead of waiting for the next poll.
+    /// Rate limited by MIN_RENDER_INTERVAL, a skipped render is drawn by the next poll.
+    fn render(&mut self) {
+        if self
+            .last_render
+            .is_some_and(|at| at.elapsed() < MIN_RENDER_INTERVAL)
+        {
+            return;
+        }
+        self.draw();
     }
 
-    fn show_battery_level(&mut self) {
-        self.power_pin.set_high();
+    /// Writes the current frame right now, even if it's the same as the last one and whatever
+    /// the rate limit says. For state changed from outside the event handlers (the console),
+    /// or a strip that may not show what `last_frame` says. Goes through flush() like every
+    /// write, so disabled LEDs stay dark and the power budget still applies.
+    pub fn force_refresh(&mut self) {
+        self.last_frame = None;
+        self.draw();
+    }
+
+    /// render() without the rate limit, poll() ends with this
+    fn draw(&mut self) {
+        let frame = self.frame();
+        if self.last_frame == Some(frame) {
+            return;
+        }
+        self.last_frame = Some(frame);
+        self.last_render = Some(Instant::now());
+        self.flush(frame);
+    }
+
+    /// The top frame with the underglow filled in wherever it left an LED dark
+    fn frame(&self) -> [RGB8; N] {
+        let mut data = self.top_frame();
+        if let Some((color, range)) = &self.underglow
+            && !self.asleep
+        {
+            // Never over the status region
+            let start = range.start.max(self.status_leds.unwrap_or(0));
+            for led in data.iter_mut().take(range.end).skip(start) {
+                if *led == RGB8::default() {
+                    *led = *color;
+                }
+            }
+        }
+        data
+    }
+
+    /// The frame of the highest priority source that has something to show, see `LED_PRIORITY`
+    fn top_frame(&self) -> [RGB8; N] {
+        LED_PRIORITY
+            .iter()
+            .find_map(|&source| self.source_frame(source))
+            .unwrap_or([RGB8::default(); N])
+    }
 
-        // Calculate how many LEDs to light up based on battery percentage
-        // Map 0-100% to 0-N LEDs (with at least 1 LED if battery > 0%)
-        let num_leds = if self.battery_percentage == 0 {
-            1
-        } else if self.battery_percentage >= 89 {
-            N // 89-100% = all N LEDs
+    /// What `source` shows right now, None while it's inactive
+    fn source_frame(&self, source: LedSource) -> Option<[RGB8; N]> {
+        match source {
+            LedSource::LedsOff => self.leds_off_at.map(|_| [LEDS_OFF_CONFIRM_COLOR; N]),
+            LedSource::LockedColor => self.locked_color.map(|index| [LOCK_COLORS[index]; N]),
+            // Nothing else can be triggered while locked anyway. Still sleeps, any press wakes it.
+            LedSource::KeyboardLock => (self.keyboard_locked && !self.asleep).then(|| {
+                let mut data = [RGB8::default(); N];
+                data[self.profile_index()] = KEYBOARD_LOCKED_COLOR;
+                data
+            }),
+            // Dim purple, a quarter of the flash right before the jump
+            LedSource::BootloaderArmed => self
+                .bootloader_armed_until
+                .map(|_| [scale(BOOTLOADER_COLOR, 64); N]),
+            LedSource::Confirm => self.confirm_until.map(|_| [CONFIRM_COLOR; N]),
+            LedSource::StorageError => {
+                let at = self
+                    .storage_error_at
+                    .filter(|_| self.storage_error_showing())?;
+                let phase = (at.elapsed().as_millis() / STORAGE_ERROR_SWAP_MS) as usize;
+                let mut data = [RGB8::default(); N];
+                for (i, led) in data.iter_mut().enumerate() {
+                    if (i + phase).is_multiple_of(2) {
+                        *led = STORAGE_ERROR_COLOR;
+                    }
+                }
+                Some(data)
+            }
+            LedSource::BatteryCheck => self.is_showing_battery.then(|| self.battery_frame()),
+            LedSource::Readout => self
+                .readout
+                .map(|(readout, _)| Self::count_frame(readout.count, readout.color)),
+            LedSource::Heatmap => self.heatmap.map(|(heat, _)| Self::heatmap_frame(&heat)),
+            LedSource::Demo => self.demo.as_ref().map(|demo| demo.frame()),
+            LedSource::Base => Some(self.status_frame()),
+        }
+    }
+
+    /// Base layer with the focus timer and the profile LED blinks on top
+    fn status_frame(&self) -> [RGB8; N] {
+        let mut data = self.base_frame();
+        if self.asleep {
+            return data;
+        }
+        // Over the base layer, in the underglow region if the strip is split
+        self.focus_timer
+            .draw(&mut data[self.status_leds.unwrap_or(0)..], Instant::now());
+        if self.connect_blink_next.is_some() {
+            // Even phases left = lit, so the sequence always starts lit and ends dark
+            if self.connect_blink_phases_left.is_multiple_of(2) {
+                data[self.profile_index()] = self.connect_blink_color;
+            }
+        } else if self.should_blink && self.first_pairing {
+            let ms = Instant::now().as_millis();
+            let profile = (ms / FIRST_PAIRING_PULSE_MS % 3) as usize;
+            let led = profile.min(self.status_leds.unwrap_or(N) - 1);
+            data[led] = scale(FIRST_PAIRING_COLORS[profile], pulse_level(ms));
+        } else if self.should_blink && self.blink_on {
+            data[self.profile_index()] = ADVERTISING_COLOR;
+        }
+        data
+    }
+
+    // Only BLE connections start it
+    #[cfg_attr(not(feature = "ble"), allow(dead_code))]
+    fn start_connect_blink(&mut self) {
+        // The steady connected color says it on its own
+        if !self.connect_blink_enabled
+            || self.connect_blink_count == 0
+            || self.connected_colors.is_some()
+        {
+            self.connect_blink_next = None;
+            return;
+        }
+        let count = if self.connect_blink_per_profile {
+            self.current_ble_profile
+                .saturating_add(1)
+                .min(CONNECT_BLINK_MAX)
         } else {
-            // 1-88% maps to 1-(N-1) LEDs: scale proportionally
-            ((self.battery_percentage as usize - 1) * (N - 1) / 88) + 1
+            self.connect_blink_count
         };
+        info!(
+            "Connect blink on LED: {} (max: {}), {} blinks",
+            self.current_ble_profile, N, count
+        );
+        self.connect_blink_phases_left = count as u16 * 2;
+        self.connect_blink_next = Some(Instant::now() + Duration::from_millis(CONNECT_BLINK_MS));
+    }
 
-        // Choose color based on battery level: red if under 30%, green otherwise
-        let led_color = if self.battery_percentage < 30 {
-            RGB8 { r: 70, g: 0, b: 0 } // Red for low battery
+    /// Moves to the next on/off phase, done once every phase was shown
+    fn step_connect_blink(&mut self) {
+        self.connect_blink_phases_left = self.connect_blink_phases_left.saturating_sub(1);
+        self.connect_blink_next = if self.connect_blink_phases_left == 0 {
+            None
         } else {
-            RGB8 { r: 0, g: 70, b: 0 } // Green for normal battery
+            Some(Instant::now() + Duration::from_millis(CONNECT_BLINK_MS))
         };
+    }
 
-        // Create LED array and light up the first num_leds
-        let mut data = [RGB8::default(); N];
-        for i in 0..num_leds {
-            data[i] = led_color;
+    fn sleep(&mut self) {
+        info!("No key activity - LEDs going to sleep");
+        self.asleep = true;
+        // Only a strip that actually goes dark needs a wake-up tap. Already dark (disabled,
+        // below the battery floor, USB without an indicator) the next key just goes through.
+        LEDS_ASLEEP.store(self.leds_on, Ordering::Relaxed);
+    }
+
+    /// Any activity counts, waking up is only visible if the LEDs were asleep
+    fn wake(&mut self) {
+        self.last_activity = Instant::now();
+        if !self.asleep {
+            return;
+        }
+        info!("LEDs waking up");
+        self.asleep = false;
+        LEDS_ASLEEP.store(false, Ordering::Relaxed);
+    }
+
+    fn battery_frame(&self) -> [RGB8; N] {
+        if self.battery_percentage == BATTERY_UNKNOWN {
+            return [BATTERY_UNKNOWN_COLOR; N];
         }
 
-        let _ = self.ws2812.write(data.iter().cloned());
-        self.leds_on = true;
+        // battery_bar_leds never goes past N, count_frame stops at the end of the bar anyway
+        debug_assert!(self.last_battery_leds <= N);
+        // Light up the first few LEDs, tracked in on_battery_state_event - battery covers the
+        // profile indicator
+        Self::count_frame(
+            self.last_battery_leds as u8,
+            Self::battery_color(self.battery_percentage),
+        )
+    }
+
+    // Red if under 30%, green otherwise
+    fn battery_color(percentage: u8) -> RGB8 {
+        if percentage < 30 {
+            RGB8 { r: 70, g: 0, b: 0 } // Red for low battery
+        } else {
+            RGB8 { r: 0, g: 70, b: 0 } // Green for normal battery
+        }
+    }
 
+    fn log_battery_level(&self) {
+        if self.battery_percentage == BATTERY_UNKNOWN {
+            info!("Battery level unknown");
+            return;
+        }
         info!(
-            "Battery level: {}% ({} LEDs, {})",
+            "Battery level: {}% ({})",
             self.battery_percentage,
-            num_leds,
             if self.battery_percentage < 30 {
                 "RED"
             } else {
@@ -132,7 +991,242 @@ impl<'d, const N: usize> StatusLedController<'d, N> {
         );
     }
 
-    // Event handlers for #[controller] macro
+    /// Keys are spread evenly along the bar in matrix order. With more keys than LEDs some
+    /// LEDs cover two neighbouring keys and show the hotter one.
+    fn heatmap_frame(heat: &[u8; SIZE]) -> [RGB8; N] {
+        let mut led_heat = [0u8; N];
+        for (key, &h) in heat.iter().enumerate() {
+            let led = key * N / SIZE;
+            led_heat[led] = led_heat[led].max(h);
+        }
+        let mut data = [RGB8::default(); N];
+        for (led, &h) in data.iter_mut().zip(led_heat.iter()) {
+            *led = heat_color(h);
+        }
+        data
+    }
+
+    /// The first `count` LEDs in `color`
+    fn count_frame(count: u8, color: RGB8) -> [RGB8; N] {
+        let mut data = [RGB8::default(); N];
+        for led in data.iter_mut().take(count as usize) {
+            *led = color;
+        }
+        data
+    }
+
+    /// Flashes purple, cuts the LED rail and resets into the bootloader.
+    /// Blocking is fine here, we're about to reset.
+    ///
+    /// The purple flash is the last frame: nothing else draws the strip, effects only run from
+    /// poll, which never comes back. The jump is a system reset: `jump_to_bootloader` tags
+    /// GPREGRET for the Adafruit bootloader and resets the chip, and the bootloader starts DFU
+    /// from that reset. The reset also resets the RADIO, TIMERs and RTCs MPSL and the SDC run on,
+    /// so the radio can't stay powered into DFU, and nothing needs shutting down before it.
+    /// There's no BLE disconnect first, RMK (ca38784) keeps the connection inside its BLE task -
+    /// the host sees the link drop and times it out like any other lost connection.
+    async fn enter_bootloader(&mut self) {
+        info!("Entering bootloader");
+        #[cfg(feature = "buzzer")]
+        crate::audio::play(crate::audio::Tune::Bootloader);
+        for _ in 0..BOOTLOADER_FLASH_COUNT {
+            self.flush([BOOTLOADER_COLOR; N]);
+            Timer::after_millis(BOOTLOADER_FLASH_MS).await;
+            self.flush([RGB8::default(); N]);
+            Timer::after_millis(BOOTLOADER_FLASH_MS).await;
+        }
+        // The black flush already cut it, unless the LEDs were disabled and never flushed
+        self.power_off();
+        rmk::boot::jump_to_bootloader();
+    }
+
+    /// First tap shows amber, the second one within the window is the confirmation.
+    /// RMK performs the action itself on the double tap, this only tracks it for the LEDs.
+    fn on_confirm_tap(&mut self) {
+        if let Some(confirm_until) = self.confirm_until.take()
+            && Instant::now() <= confirm_until
+        {
+            info!("Destructive action confirmed");
+            return;
+        }
+        info!("Destructive action - tap again to confirm");
+        self.confirm_until =
+            Some(Instant::now() + Duration::from_millis(DESTRUCTIVE_CONFIRM_WINDOW_MS as u64));
+    }
+
+    async fn toggle_leds_disabled(&mut self) {
+        match leds_toggle(self.leds_off_at.is_some(), self.leds_disabled) {
+            LedsToggle::StayOn => {
+                // They never went off
+                info!("LEDs staying on");
+                self.leds_off_at = None;
+                self.force_refresh();
+            }
+            LedsToggle::Enable => {
+                info!("LEDs enabled");
+                self.leds_disabled = false;
+                // Nothing was written while disabled, so start from scratch
+                self.force_refresh();
+                SETTINGS_CHANNEL
+                    .send(SettingsUpdate::LedsDisabled(false))
+                    .await;
+            }
+            LedsToggle::StartOff => {
+                info!("LEDs disabled");
+                // Last thing shown before going dark, poll() turns them off and saves it
+                self.leds_off_at =
+                    Some(Instant::now() + Duration::from_millis(LEDS_OFF_CONFIRM_MS));
+                self.force_refresh();
+            }
+        }
+    }
+
+    async fn finish_leds_off(&mut self) {
+        self.leds_off_at = None;
+        self.flush([RGB8::default(); N]);
+        self.leds_disabled = true;
+        SETTINGS_CHANNEL
+            .send(SettingsUpdate::LedsDisabled(true))
+            .await;
+    }
+
+    async fn on_bootloader_key(&mut self) {
+        // Same toggle trick as BATT_CHECK - press and release always arrive in pairs
+        let Some(pressed_at) = self.bootloader_key_pressed_at.take() else {
+            self.bootloader_key_pressed_at = Some(Instant::now());
+            return;
+        };
+
+        // Released - a second tap while armed confirms
+        if let Some(armed_until) = self.bootloader_armed_until
+            && Instant::now() <= armed_until
+        {
+            self.enter_bootloader().await;
+            return;
+        }
+
+        // Only a hold (not a tap) arms/triggers bootloader entry
+        if pressed_at.elapsed() < Duration::from_millis(BOOTLOADER_HOLD_TIMEOUT_MS as u64) {
+            return;
+        }
+        if BOOTLOADER_REQUIRE_CONFIRM_TAP {
+            // Solid dim purple until the confirm tap or the window runs out
+            info!("Bootloader armed - tap again to confirm");
+            self.bootloader_armed_until =
+                Some(Instant::now() + Duration::from_millis(BOOTLOADER_CONFIRM_WINDOW_MS));
+        } else {
+            self.enter_bootloader().await;
+        }
+    }
+
+    async fn handle_key(&mut self, event: KeyEvent) {
+        self.wake();
+        if self.effect == LedEffect::Party && event.keyboard_event.pressed {
+            self.party.on_press();
+        }
+
+        // RMK doesn't report macros it runs, but the key event carries the keymap action at the
+        // pressed position - a plain TriggerMacro key is a macro that fires on the press.
+        // A macro behind a tap-hold or tapdance can't be told apart from its other action
+        // here, so those don't flash.
+        if let KeyAction::Single(Action::TriggerMacro(index)) = event.key_action {
+            if event.keyboard_event.pressed
+                && let Some(Some(flash)) = MACRO_FLASHES.get(index as usize)
+            {
+                info!("Macro {} fired", index);
+                self.readout = Some((
+                    *flash,
+                    Instant::now() + Duration::from_millis(MACRO_FLASH_MS),
+                ));
+            }
+            return;
+        }
+
+        if event.key_action == KeyAction::Single(EFFECT_NEXT) {
+            // Same toggle trick as BATT_CHECK, only act on the press
+            self.effect_key_held = !self.effect_key_held;
+            if self.effect_key_held {
+                self.effect = self.effect.next();
+                info!("LED effect: {:?}", self.effect);
+                self.save_profile_leds().await;
+            }
+            return;
+        }
+
+        if event.key_action == KeyAction::Single(LED_LOCK) {
+            // Same toggle trick as BATT_CHECK, only act on the press
+            self.lock_key_held = !self.lock_key_held;
+            if self.lock_key_held {
+                self.locked_color = match self.locked_color {
+                    None => Some(0),
+                    Some(index) if index + 1 < LOCK_COLORS.len() => Some(index + 1),
+                    Some(_) => None,
+                };
+                info!("LED lock: {:?}", self.locked_color);
+            }
+            return;
+        }
+
+        if event.key_action == KeyAction::Single(FOCUS_TIMER) {
+            // Same toggle trick, the press time doubles as the held state
+            let now = Instant::now();
+            match self.focus_timer_pressed_at.take() {
+                None => self.focus_timer_pressed_at = Some(now),
+                Some(pressed_at) if now - pressed_at >= FOCUS_TIMER_RESET_HOLD => {
+                    info!("Focus timer reset");
+                    self.focus_timer.reset();
+                }
+                Some(_) => {
+                    self.focus_timer.toggle(now);
+                    info!("Focus timer running: {}", self.focus_timer.is_running());
+                }
+            }
+            return;
+        }
+
+        if event.key_action == td!(BOOTLOADER_TAPDANCE) {
+            self.on_bootloader_key().await;
+            return;
+        }
+
+        let is_confirm_tapdance = CONFIRM_TAPDANCES
+            .iter()
+            .any(|&i| event.key_action == td!(i));
+        if is_confirm_tapdance {
+            if event.keyboard_event.pressed {
+                self.on_confirm_tap();
+            }
+            return;
+        }
+
+        if event.key_action == KeyAction::Single(LEDS_TOGGLE) {
+            // Same toggle trick as BATT_CHECK, only act on the press
+            self.leds_toggle_held = !self.leds_toggle_held;
+            if self.leds_toggle_held {
+                self.toggle_leds_disabled().await;
+            }
+            return;
+        }
+
+        // BAT_CHK in Vial
+        if event.key_action == KeyAction::Single(BATT_CHECK) {
+            // Toggle the state - if not currently held, it's a press; otherwise it's a release
+            if !self.batt_check_held {
+                // Pressed - show battery level
+                info!("BAT_CHK pressed - showing battery level");
+                self.batt_check_held = true;
+                self.is_showing_battery = true;
+                self.log_battery_level();
+            } else {
+                // Released - clear LEDs
+                info!("BAT_CHK released - clearing battery display");
+                self.batt_check_held = false;
+                self.is_showing_battery = false;
+            }
+        }
+    }
+
+    // Event handlers for #[controller] macro - each one updates state, then renders
 
     async fn on_connection_change_event(&mut self, event: ConnectionChangeEvent) {
         info!("ConnectionType changed: {:?}", event.connection_type);
@@ -140,53 +1234,75 @@ impl<'d, const N: usize> StatusLedController<'d, N> {
             ConnectionType::Ble => {
                 // BLE mode - start advertising indicator
                 info!("BLE mode activated - starting advertising indicator");
+                self.usb_mode = false;
                 self.should_blink = true;
+                self.advertising.start();
             }
             ConnectionType::Usb => {
                 // USB mode - turn off BLE indicators
                 info!("USB mode - stopping BLE indicators");
+                self.usb_mode = true;
                 self.should_blink = false;
-                if !self.is_showing_battery {
-                    self.clear_all_leds();
-                }
+                self.advertising.stop();
             }
         }
+        self.render();
     }
 
+    #[cfg(feature = "ble")]
     async fn on_ble_state_change_event(&mut self, event: BleStateChangeEvent) {
+        // Connection changes are worth seeing, even if the LEDs were asleep
+        self.wake();
         match event.state {
             BleState::Advertising => {
                 // Start blinking blue when advertising
                 info!("Advertising - Custom Controller - Profile: {}", event.profile);
                 self.current_ble_profile = event.profile;
+                self.ble_connected = false;
                 self.should_blink = true;
+                self.connect_blink_next = None;
+                self.advertising.start();
             }
             BleState::Connected => {
-                // Stop blinking and blink green 4 times
+                // Stop the advertising blink, poll() steps the connect blink from here
                 self.should_blink = false;
+                self.advertising.stop();
+                self.ble_connected = true;
                 self.current_ble_profile = event.profile;
                 info!("Connected - Custom Controller - Profile: {}", event.profile);
-
-                // Blink green 4 times
-                for _ in 0..4 {
-                    self.blink_ble_profile_led_green();
-                    embassy_time::Timer::after(embassy_time::Duration::from_millis(500)).await;
-                    self.clear_all_leds();
-                    embassy_time::Timer::after(embassy_time::Duration::from_millis(500)).await;
+                self.stop_demo("host connected");
+                if self.first_pairing {
+                    info!("First host paired - regular advertising blink from now on");
+                    self.first_pairing = false;
+                }
+                self.start_connect_blink();
+                if self.connect_blink_next.is_none() {
+                    // Nothing blinks over the profile LED, so a lit advertising phase must not
+                    // stay up behind the rate limit until the next poll
+                    self.force_refresh();
                 }
             }
             BleState::None => {
                 // Turn off LEDs when not in BLE mode
                 self.should_blink = false;
+                self.advertising.stop();
+                self.ble_connected = false;
+                self.connect_blink_next = None;
                 info!("None - Custom Controller");
-                self.clear_all_leds();
             }
         }
+        self.render();
     }
 
     async fn on_battery_state_event(&mut self, event: BatteryStateEvent) {
-        // Update battery percentage when received from BatteryProcessor
+        // Update battery percentage when received from CurveBatteryProcessor
         match event {
+            // After a failed ADC init, whatever the ADC reads later is garbage too
+            BatteryStateEvent::Normal(_) if self.battery_percentage == BATTERY_UNKNOWN => {}
+            // With the joystick NrfAdc samples every few ms, nothing to redraw for the same value
+            BatteryStateEvent::Normal(percentage) if percentage == self.battery_percentage => {
+                return;
+            }
             BatteryStateEvent::Normal(percentage) => {
                 self.battery_percentage = percentage;
                 info!("Battery updated: {}%", percentage);
@@ -202,52 +1318,213 @@ impl<'d, const N: usize> StatusLedController<'d, N> {
                 info!("Battery not available");
             }
         }
+        if self.battery_percentage != BATTERY_UNKNOWN {
+            self.last_battery_leds =
+                battery_bar_leds_hysteresis(self.last_battery_leds, self.battery_percentage, N);
+        }
+        let below_floor = below_battery_floor(self.below_floor, self.battery_percentage);
+        if below_floor != self.below_floor {
+            if below_floor {
+                warn!("Battery under the LED floor - LEDs off");
+                // Right away, not whenever the next frame gets written
+                self.power_off();
+            } else {
+                info!("Battery back above the LED floor");
+            }
+            self.below_floor = below_floor;
+        }
+        // The battery floor in power_on() may have flipped, so write the frame again
+        self.last_frame = None;
+        self.render();
     }
 
+    #[cfg(feature = "ble")]
     async fn on_ble_profile_change_event(&mut self, event: BleProfileChangeEvent) {
         info!("BLE Profile changed to: {}", event.profile);
+        // BLE1-3 and BLE_NEXT both end up here. A switch shows profile + 1 LEDs for a moment,
+        // in that profile's connected color if there is one.
+        if event.profile != self.current_ble_profile {
+            let color = self
+                .connected_colors
+                .and_then(|colors| colors.get(event.profile as usize).copied())
+                .unwrap_or(self.connect_blink_color);
+            let readout = LedReadout {
+                count: event.profile.saturating_add(1),
+                color,
+            };
+            self.readout = Some((
+                readout,
+                Instant::now() + Duration::from_millis(READOUT_DURATION_MS),
+            ));
+        }
+        // Moves the indicator right away, unless something else owns the strip
         self.current_ble_profile = event.profile;
+        self.apply_profile_leds();
+        self.render();
+        if event.profile != self.saved_ble_profile {
+            self.saved_ble_profile = event.profile;
+            SETTINGS_CHANNEL
+                .send(SettingsUpdate::ActiveBleProfile(event.profile))
+                .await;
+        }
+    }
+
+    fn stop_demo(&mut self, why: &str) {
+        if self.demo.take().is_some() {
+            info!("Demo mode off - {}", why);
+        }
     }
 
     async fn on_key_event(&mut self, event: KeyEvent) {
-        // Check if it's User7 key (BAT_CHK in Vial)
-        if let rmk::types::action::KeyAction::Single(Action::User(7)) = event.key_action {
-            // Toggle the state - if not currently held, it's a press; otherwise it's a release
-            if !self.user7_held {
-                // User7 pressed - show battery level
-                info!("User7 (BAT_CHK) pressed - showing battery level");
-                self.user7_held = true;
-                self.is_showing_battery = true;
-                self.show_battery_level();
-            } else {
-                // User7 released - clear LEDs
-                info!("User7 (BAT_CHK) released - clearing battery display");
-                self.user7_held = false;
-                self.is_showing_battery = false;
-                self.clear_all_leds();
-            }
+        self.stop_demo("key pressed");
+        // Someone's typing, so advertise fast again and show it with the fast blink
+        if self.should_blink && self.advertising.is_slow() {
+            self.blink_on = true;
+            self.last_blink = Instant::now();
         }
+        self.advertising.on_keypress();
+        self.handle_key(event).await;
+        self.render();
     }
 
-    /// Called by PollingController::update() every 700ms (poll_interval)
+    /// Called by PollingController::update() every 50ms (poll_interval).
+    /// Only advances timers and animations, drawing is left to draw() at the end.
     async fn poll(&mut self) {
-        // Debug: always log poll calls to verify polling is working
-        info!(
-            "poll() called: should_blink={}, is_showing_battery={}, leds_on={}",
-            self.should_blink, self.is_showing_battery, self.leds_on
-        );
+        let now = Instant::now();
+
+        // Write the frame again on any change, cutting or restoring the rail right away
+        let overheated = THERMAL_OVERHEAT.load(Ordering::Relaxed);
+        if overheated != self.overheated {
+            self.overheated = overheated;
+            self.last_frame = None;
+        }
 
-        // Only blink for BLE if we're not currently showing battery level
-        if self.should_blink && !self.is_showing_battery {
+        if let Some(armed_until) = self.bootloader_armed_until
+            && now > armed_until
+        {
+            info!("Bootloader confirm window expired - cancelled");
+            self.bootloader_armed_until = None;
+        }
+
+        if let Some(confirm_until) = self.confirm_until
+            && now > confirm_until
+        {
+            info!("Confirm window expired - cancelled");
+            self.confirm_until = None;
+        }
+
+        #[cfg(feature = "dev-console")]
+        self.handle_console().await;
+
+        if let Some(output) = EFFECTIVE_OUTPUT.try_take() {
+            self.output = Some(output);
+        }
+
+        if let Some(vbus) = VBUS_PRESENT.try_take()
+            && vbus == self.on_battery
+        {
             info!(
-                "Blinking: leds_on={}, profile={}",
-                self.leds_on, self.current_ble_profile
+                "{} - LED brightness adjusted",
+                if vbus { "On USB power" } else { "On battery" }
             );
-            if self.leds_on {
-                self.clear_all_leds();
+            self.on_battery = !vbus;
+            self.last_frame = None;
+        }
+
+        #[cfg(feature = "ambient-light")]
+        if let Some(brightness) = AMBIENT_BRIGHTNESS.try_take() {
+            self.set_brightness(brightness);
+        }
+
+        if STORAGE_ERROR.try_take().is_some() {
+            let cooling_down = self
+                .storage_error_at
+                .is_some_and(|at| at.elapsed() < STORAGE_ERROR_COOLDOWN);
+            if cooling_down {
+                warn!("Flash write failed again - error pattern is cooling down");
             } else {
-                self.blink_ble_profile_led_blue();
+                warn!("Flash write failed - showing storage error");
+                self.storage_error_at = Some(now);
+            }
+        }
+
+        // The lock combo is handled in the debouncer too, no key event for it either
+        let keyboard_locked = KEYBOARD_LOCKED.load(Ordering::Relaxed);
+        if keyboard_locked != self.keyboard_locked {
+            self.keyboard_locked = keyboard_locked;
+            self.wake();
+        }
+
+        // The debouncer ate a waking press, so no key event is coming for it
+        if LED_WAKE.try_take().is_some() {
+            self.wake();
+        }
+        if !self.asleep
+            && self.last_activity.elapsed() >= LED_SLEEP_TIMEOUT
+            && !self.transient_active()
+        {
+            self.sleep();
+        }
+
+        if let Some(readout) = LED_READOUT.try_take() {
+            info!("Showing readout: {} LEDs", readout.count);
+            self.readout = Some((readout, now + Duration::from_millis(READOUT_DURATION_MS)));
+        }
+        if let Some((_, readout_until)) = self.readout
+            && now > readout_until
+        {
+            self.readout = None;
+        }
+        if let Some(heat) = LED_HEATMAP.try_take() {
+            self.heatmap = Some((heat, now + Duration::from_millis(HEATMAP_DURATION_MS)));
+        }
+        if let Some((_, heatmap_until)) = self.heatmap
+            && now > heatmap_until
+        {
+            self.heatmap = None;
+        }
+
+        if let Some(next) = self.connect_blink_next
+            && now >= next
+        {
+            self.step_connect_blink();
+        }
+
+        if leds_off_due(self.leds_off_at.map(|at| at.as_millis()), now.as_millis()) {
+            self.finish_leds_off().await;
+        }
+
+        if self.effect == LedEffect::Party {
+            self.party.tick();
+            if self.on_battery {
+                self.party.tick();
             }
         }
+        // Frozen while asleep, it picks up where it was on wake
+        if self.effect == LedEffect::Scanner && !self.asleep {
+            self.scanner.tick();
+        }
+        if let Some(demo) = &mut self.demo {
+            demo.tick();
+        }
+        if self.focus_timer.tick(now) {
+            info!("Focus timer done");
+            self.wake();
+        }
+
+        self.advertising.update();
+        let blink_interval_ms = match (self.advertising.is_slow(), self.blink_on) {
+            (false, _) => BLINK_INTERVAL_MS,
+            (true, true) => SLOW_BLINK_ON_MS,
+            (true, false) => SLOW_BLINK_OFF_MS,
+        };
+        if self.last_blink.elapsed() >= Duration::from_millis(blink_interval_ms) {
+            self.last_blink = now;
+            if self.should_blink {
+                self.blink_on = !self.blink_on;
+            }
+        }
+
+        self.draw();
     }
 }
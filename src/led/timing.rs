@@ -0,0 +1,174 @@
+use embassy_nrf::spim::Frequency;
+use smart_leds::RGB8;
+
+// WS2812 bit timing over SPI
+//
+// The strip isn't really SPI, we only use MOSI and shape each data bit out of SPI bits.
+// At 4MHz one SPI bit is 250ns, so one nibble (4 SPI bits, 1us) encodes one LED bit:
+// - 0 bit: 1000 -> 250ns high, 750ns low
+// - 1 bit: 1110 -> 750ns high, 250ns low (stock pattern) or 1100 -> 500ns high (CUSTOM_PATTERNS)
+// A pattern byte carries 2 LED bits, indexed by their value: [00, 01, 10, 11]
+// Both patterns assume M4 - changing the frequency means working out new nibbles too
+pub const LED_SPI_FREQUENCY: Frequency = Frequency::M4;
+
+/// Use `CUSTOM_PATTERNS` instead of the crate's stock pattern
+pub const LED_USE_CUSTOM_PATTERNS: bool = false;
+
+const NIBBLE_ZERO: u8 = 0b1000;
+const NIBBLE_ONE: u8 = 0b1100;
+
+/// Shorter 1-bit high time (500ns instead of 750ns) for clones with a tighter T1H window.
+///
+/// The stock pattern is fine on the WS2812B strip this board ships with (T1H 580-1000ns).
+/// It sits right at the edge of SK6812-style clones (T1H 600ns +-150ns), which show up as
+/// flicker or wrong colors on the far end of the strip, switch to these for those.
+/// Don't use these on a genuine WS2812B, 500ns is below its T1H minimum and reads as a 0.
+pub const CUSTOM_PATTERNS: [u8; 4] = [
+    (NIBBLE_ZERO << 4) | NIBBLE_ZERO,
+    (NIBBLE_ZERO << 4) | NIBBLE_ONE,
+    (NIBBLE_ONE << 4) | NIBBLE_ZERO,
+    (NIBBLE_ONE << 4) | NIBBLE_ONE,
+];
+
+/// Every entry is two valid nibbles, high nibble = first bit, and entry `i` encodes the value `i`
+const fn patterns_are_valid(patterns: [u8; 4], zero: u8, one: u8) -> bool {
+    let mut i = 0;
+    while i < 4 {
+        let first = if i & 0b10 != 0 { one } else { zero };
+        let second = if i & 0b01 != 0 { one } else { zero };
+        if patterns[i] != ((first << 4) | second) {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// High time in ns for a nibble at 4MHz, counting leading ones
+const fn high_time_ns(nibble: u8) -> u32 {
+    let mut ns = 0;
+    let mut bit = 0b1000;
+    while bit != 0 && nibble & bit != 0 {
+        ns += 250;
+        bit >>= 1;
+    }
+    ns
+}
+
+const _: () = assert!(patterns_are_valid(CUSTOM_PATTERNS, NIBBLE_ZERO, NIBBLE_ONE));
+// Every bit has to start high and end low, otherwise neighbouring bits run together
+const _: () = assert!(NIBBLE_ZERO & 0b1000 != 0 && NIBBLE_ZERO & 0b0001 == 0);
+const _: () = assert!(NIBBLE_ONE & 0b1000 != 0 && NIBBLE_ONE & 0b0001 == 0);
+// SK6812 windows: T0H 150-450ns, T1H 450-750ns
+const _: () = assert!(high_time_ns(NIBBLE_ZERO) >= 150 && high_time_ns(NIBBLE_ZERO) <= 450);
+const _: () = assert!(high_time_ns(NIBBLE_ONE) >= 450 && high_time_ns(NIBBLE_ONE) <= 750);
+
+/// Channel order the strip expects on the wire
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorOrder {
+    Grb,
+    Rgb,
+    Brg,
+    Bgr,
+    Rbg,
+    Gbr,
+}
+
+/// WS2812B (what this board ships with) is GRB, and that's what `ws2812-spi` always sends.
+/// Most SK6812 variants are GRB too, but some clones are RGB or BRG - if red and green come
+/// out swapped (the green connect blink shows up red), change this.
+pub const LED_COLOR_ORDER: ColorOrder = ColorOrder::Grb;
+
+/// Shuffles `color` so that once `ws2812-spi` sends it in GRB order, the strip sees its own
+/// order. Applied right before every strip write.
+pub const fn to_wire_order(color: RGB8, order: ColorOrder) -> RGB8 {
+    let RGB8 { r, g, b } = color;
+    // The first channel on the wire goes in g, the second in r, the third in b
+    let (first, second, third) = match order {
+        ColorOrder::Grb => (g, r, b),
+        ColorOrder::Rgb => (r, g, b),
+        ColorOrder::Brg => (b, r, g),
+        ColorOrder::Bgr => (b, g, r),
+        ColorOrder::Rbg => (r, b, g),
+        ColorOrder::Gbr => (g, b, r),
+    };
+    RGB8 {
+        r: second,
+        g: first,
+        b: third,
+    }
+}
+
+/// Bytes `ws2812-spi` puts on the wire for `color`, in order
+const fn wire_bytes(color: RGB8) -> [u8; 3] {
+    [color.g, color.r, color.b]
+}
+
+/// `order` puts r = 1, g = 2, b = 3 on the wire as `expected`
+const fn sends(order: ColorOrder, expected: [u8; 3]) -> bool {
+    let sent = wire_bytes(to_wire_order(RGB8 { r: 1, g: 2, b: 3 }, order));
+    sent[0] == expected[0] && sent[1] == expected[1] && sent[2] == expected[2]
+}
+
+const _: () = assert!(sends(ColorOrder::Grb, [2, 1, 3]));
+const _: () = assert!(sends(ColorOrder::Rgb, [1, 2, 3]));
+const _: () = assert!(sends(ColorOrder::Brg, [3, 1, 2]));
+const _: () = assert!(sends(ColorOrder::Bgr, [3, 2, 1]));
+const _: () = assert!(sends(ColorOrder::Rbg, [1, 3, 2]));
+const _: () = assert!(sends(ColorOrder::Gbr, [2, 3, 1]));
+
+/// Set when the strip is mounted the other way round, with LED 0 on the right. Everything
+/// draws in logical order (index 0 = left: the battery bar fills from there, profile 0's LED),
+/// and the frame is flipped right before the write so it comes out the same on the board.
+pub const LED_REVERSED: bool = false;
+
+/// Logical frame to strip order, applied right before every strip write along with
+/// `to_wire_order`
+pub const fn to_physical_order<const N: usize>(data: [RGB8; N], reversed: bool) -> [RGB8; N] {
+    if !reversed {
+        return data;
+    }
+    let mut out = [RGB8 { r: 0, g: 0, b: 0 }; N];
+    let mut i = 0;
+    while i < N {
+        out[N - 1 - i] = data[i];
+        i += 1;
+    }
+    out
+}
+
+const fn same_frame<const N: usize>(a: &[RGB8; N], b: &[RGB8; N]) -> bool {
+    let mut i = 0;
+    while i < N {
+        if a[i].r != b[i].r || a[i].g != b[i].g || a[i].b != b[i].b {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const LIT: RGB8 = RGB8 { r: 0, g: 9, b: 0 };
+const DARK: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
+const TEST_FRAME: [RGB8; 5] = [
+    RGB8 { r: 1, g: 0, b: 0 },
+    RGB8 { r: 2, g: 0, b: 0 },
+    RGB8 { r: 3, g: 0, b: 0 },
+    RGB8 { r: 4, g: 0, b: 0 },
+    RGB8 { r: 5, g: 0, b: 0 },
+];
+
+// Flipping twice gets the frame back, not flipping leaves it alone
+const _: () = assert!(same_frame(
+    &to_physical_order(to_physical_order(TEST_FRAME, true), true),
+    &TEST_FRAME
+));
+const _: () = assert!(same_frame(
+    &to_physical_order(TEST_FRAME, false),
+    &TEST_FRAME
+));
+// A 2 LED battery bar lights the first two logical LEDs, the last two on a reversed strip
+const _: () = assert!(same_frame(
+    &to_physical_order([LIT, LIT, DARK, DARK, DARK], true),
+    &[DARK, DARK, DARK, LIT, LIT]
+));
@@ -44,3 +44,46 @@ macro_rules! kc {
         rmk::types::action::KeyAction::Single(rmk::types::action::Action::Key($k))
     };
 }
+
+/// An encoder row with every encoder set to `No`, sized to `NUM_ENCODER`
+/// Usage: `enc_none!()` in place of `[encoder!(k!(No), k!(No))]`
+macro_rules! enc_none {
+    () => {
+        [::rmk::encoder!(::rmk::k!(No), ::rmk::k!(No)); $crate::keymap::NUM_ENCODER]
+    };
+}
+
+/// Builds a full encoder map from the first few layers, the remaining layers are `enc_none!()`
+/// Giving more layers than `NUM_LAYER` fails to compile.
+/// Usage: `encoder_map!([encoder!(k!(AudioVolUp), k!(AudioVolDown))], enc_none!())`
+macro_rules! encoder_map {
+    ($($layer:expr),* $(,)?) => {{
+        const {
+            assert!(
+                [$(encoder_map!(@unit $layer)),*].len() <= $crate::keymap::NUM_LAYER,
+                "more encoder layers than NUM_LAYER"
+            )
+        };
+        let layers = [$($layer),*];
+        let mut map = [enc_none!(); $crate::keymap::NUM_LAYER];
+        // No for loops in const fn
+        let mut i = 0;
+        while i < layers.len() {
+            map[i] = layers[i];
+            i += 1;
+        }
+        map
+    }};
+
+    (@unit $layer:expr) => {
+        ()
+    };
+}
+
+/// Wraps an `Action` constant into a key action, the `Action` counterpart to `kc!`
+/// Usage: `usr!(BLE1)`, `usr!(BATT_CHECK)`
+macro_rules! usr {
+    ($a:expr) => {
+        rmk::types::action::KeyAction::Single($a)
+    };
+}
@@ -1,48 +1,161 @@
 #![no_std]
 #![no_main]
 
+// The thumbstick sits on the e-ink SPI pins, see joystick.rs
+#[cfg(all(feature = "joystick", feature = "eink"))]
+compile_error!("features `joystick` and `eink` share P0_02/P0_03, enable only one");
+// With the joystick RMK's NrfAdc owns the SAADC and drops the light channel, see adc.rs
+#[cfg(all(feature = "joystick", feature = "ambient-light"))]
+compile_error!("features `joystick` and `ambient-light` can't share the SAADC, enable only one");
+
 mod vial;
 #[macro_use]
 mod macros;
+#[cfg(not(feature = "joystick"))]
+mod adc;
+mod advertising;
+#[cfg(feature = "ambient-light")]
+mod ambient_light;
+#[cfg(feature = "buzzer")]
+mod audio;
+mod battery_alert;
+mod battery_cal;
+mod battery_curve;
+mod bond_manager;
+mod boot_loop;
+mod charge_cycles;
+mod debounce;
+#[cfg(feature = "dev-console")]
+mod debug_console;
+mod display;
+mod encoder_filter;
+mod flash_layout;
+#[cfg(feature = "dev")]
+mod heartbeat;
+mod idle;
+mod init_error;
+#[cfg(feature = "joystick")]
+mod joystick;
+mod keyboard_lock;
 mod keymap;
+#[cfg(feature = "dev")]
+mod keymap_dump;
+mod layer_return;
 mod led;
-
-use defmt::{info, unwrap};
+mod odometer;
+mod output;
+mod panic;
+mod profile_layer;
+mod raw_adc;
+mod repeat;
+mod reset_reason;
+mod runtime_estimate;
+mod safe_mode;
+mod settings;
+mod thermal;
+mod tuning;
+mod user_actions;
+mod vbus;
+mod volume;
+
+use core::cell::RefCell;
+
+use defmt::{info, unwrap, warn};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
 use embassy_executor::Spawner;
 // use embassy_nrf::gpio::{Input, Output};
 use embassy_nrf::gpio::{Input, Level, Output, OutputDrive};
 use embassy_nrf::interrupt::{self, InterruptExt};
 use embassy_nrf::mode::Async;
-use embassy_nrf::peripherals::{RNG, SAADC, USBD};
+#[cfg(feature = "ble")]
+use embassy_nrf::peripherals::RNG;
+use embassy_nrf::peripherals::{SAADC, USBD};
 use embassy_nrf::saadc::{self, AnyInput, Input as _, Saadc};
+#[cfg(not(feature = "ble"))]
+use embassy_nrf::temp;
 use embassy_nrf::usb::Driver;
 use embassy_nrf::usb::vbus_detect::HardwareVbusDetect;
-use embassy_nrf::{Peri, bind_interrupts, pac, peripherals, rng, spim, usb};
-
-use keymap::{COL, ROW};
-use led::{StartupAnimator, StatusLedController};
+use embassy_nrf::{Peri, bind_interrupts, peripherals, spim, usb};
+#[cfg(feature = "ble")]
+use embassy_nrf::{pac, rng};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+#[cfg(not(feature = "joystick"))]
+use adc::BatteryAdc;
+use battery_alert::LowBatteryAlert;
+use battery_cal::{BatteryCalibration, BatteryCalibrator};
+use battery_curve::{BATTERY_CURVE, CurveBatteryProcessor};
+use bond_manager::BondManager;
+use charge_cycles::ChargeCycleCounter;
+use debounce::{MATRIX_DEBOUNCE_MS, TimedDebouncer};
+use encoder_filter::FilteredEncoder;
+use flash_layout::{FLASH_SIZE, RMK_STORAGE_NUM_SECTORS, RMK_STORAGE_START_ADDR};
+use idle::IdleMonitor;
+use init_error::InitError;
+use keymap::{COL, NUM_ENCODER, NUM_LAYER, ROW};
+use layer_return::LayerReturn;
+use led::timing::{CUSTOM_PATTERNS, LED_SPI_FREQUENCY, LED_USE_CUSTOM_PATTERNS};
+use led::{
+    BATTERY_UNKNOWN, StartupAnimation, StartupAnimator, StatusLedController, Underglow,
+    underglow_from_bytes,
+};
+// Without BLE there's no MPSL to share the NVMC with, so RMK gets the plain flash driver
+#[cfg(not(feature = "ble"))]
+use embassy_nrf::nvmc::Nvmc as Flash;
+#[cfg(feature = "ble")]
 use nrf_mpsl::Flash;
+#[cfg(feature = "ble")]
 use nrf_sdc::mpsl::MultiprotocolServiceLayer;
+#[cfg(feature = "ble")]
 use nrf_sdc::{self as sdc, mpsl};
+use odometer::KeyOdometer;
+use output::OutputMonitor;
+use profile_layer::ProfileLayerSwitcher;
+#[cfg(feature = "ble")]
 use rand_chacha::ChaCha12Rng;
+#[cfg(feature = "ble")]
 use rand_core::SeedableRng;
+use raw_adc::RawAdcView;
+use repeat::{HoldRepeatConfig, KeyRepeater};
+#[cfg(feature = "ble")]
+use rmk::HostResources;
+#[cfg(feature = "ble")]
 use rmk::ble::build_ble_stack;
+#[cfg(feature = "ble")]
+use rmk::config::BleBatteryConfig;
 use rmk::config::{
-    BehaviorConfig, BleBatteryConfig, DeviceConfig, PositionalConfig, RmkConfig, StorageConfig,
-    VialConfig,
+    BehaviorConfig, DeviceConfig, PositionalConfig, RmkConfig, StorageConfig, VialConfig,
 };
-use rmk::debounce::default_debouncer::DefaultDebouncer;
+use rmk::debounce::DebouncerTrait;
+#[cfg(any(
+    feature = "eink",
+    feature = "dev",
+    feature = "joystick",
+    all(feature = "buzzer", feature = "ble")
+))]
+use rmk::input_device::Runnable;
+#[cfg(feature = "joystick")]
 use rmk::input_device::adc::{AnalogEventType, NrfAdc};
-use rmk::input_device::battery::BatteryProcessor;
-use rmk::input_device::rotary_encoder::RotaryEncoder;
+#[cfg(feature = "joystick")]
+use rmk::input_device::joystick::JoystickProcessor;
 use rmk::keyboard::Keyboard;
-use rmk::{
-    HostResources, initialize_encoder_keymap_and_storage, run_all, run_rmk,
-};
+use rmk::keymap::KeyMap;
+use rmk::storage::Storage;
+use rmk::{initialize_encoder_keymap_and_storage, run_all, run_rmk};
+use runtime_estimate::RuntimeEstimator;
+use settings::{SETTINGS_NUM_SECTORS, SETTINGS_SECTOR_SIZE, SETTINGS_START_ADDR, SettingsStore};
+use smart_leds::RGB8;
 use static_cell::StaticCell;
+use thermal::thermal_task;
+use tuning::HoldTimeoutTuner;
 use vial::{VIAL_KEYBOARD_DEF, VIAL_KEYBOARD_ID};
+use volume::VolumeStepper;
 use ws2812_spi::Ws2812;
-use {defmt_rtt as _, panic_probe as _};
+// With the dev console, rtt-target provides the defmt logger instead (see debug_console.rs)
+#[cfg(not(feature = "dev-console"))]
+use defmt_rtt as _;
+#[cfg(feature = "ble")]
 bind_interrupts!(struct Irqs {
     USBD => usb::InterruptHandler<USBD>;
     SAADC => saadc::InterruptHandler;
@@ -52,65 +165,241 @@ bind_interrupts!(struct Irqs {
     RADIO => nrf_sdc::mpsl::HighPrioInterruptHandler;
     TIMER0 => nrf_sdc::mpsl::HighPrioInterruptHandler;
     RTC0 => nrf_sdc::mpsl::HighPrioInterruptHandler;
-    SPIM3 => spim::InterruptHandler<peripherals::SPI3>;
+    SPIM3 => spim::InterruptHandler<LedSpi>;
+    #[cfg(feature = "eink")]
+    SPIM2_SPIS2_SPI2 => spim::InterruptHandler<peripherals::SPI2>;
 });
 
+// USB-only: no MPSL, so CLOCK_POWER is only VBUS detection and TEMP is ours to read
+#[cfg(not(feature = "ble"))]
+bind_interrupts!(struct Irqs {
+    USBD => usb::InterruptHandler<USBD>;
+    SAADC => saadc::InterruptHandler;
+    CLOCK_POWER => usb::vbus_detect::InterruptHandler;
+    TEMP => temp::InterruptHandler;
+    SPIM3 => spim::InterruptHandler<LedSpi>;
+    #[cfg(feature = "eink")]
+    SPIM2_SPIS2_SPI2 => spim::InterruptHandler<peripherals::SPI2>;
+});
+
+#[cfg(feature = "ble")]
 #[embassy_executor::task]
 async fn mpsl_task(mpsl: &'static MultiprotocolServiceLayer<'static>) -> ! {
     mpsl.run().await
 }
 
 /// How many outgoing L2CAP buffers per link
+#[cfg(feature = "ble")]
 const L2CAP_TXQ: u8 = 3;
 
 /// How many incoming L2CAP buffers per link
+#[cfg(feature = "ble")]
 const L2CAP_RXQ: u8 = 3;
 
 /// Size of L2CAP packets
+#[cfg(feature = "ble")]
 const L2CAP_MTU: usize = 251;
 
+/// Radio TX power in dBm, for advertising and connections alike. 0 dBm is the SoftDevice
+/// Controller default, what the board always used before this was configurable.
+/// The nRF52840 supports +8, +7, +6, +5, +4, +3, +2, 0, -4, -8, -12, -16, -20 and -40 dBm,
+/// anything in between is rounded down to the next supported level.
+/// Higher = more range but more current on every TX (roughly 5mA at 0 dBm, 14mA at +8 dBm
+/// with the DC/DC on), lower = less battery drain if the host is always on the same desk.
+/// TX time is a small slice of the total while idle, so expect a few percent of battery life
+/// either way, more while typing or advertising fast.
+#[cfg(feature = "ble")]
+const BLE_TX_POWER_DBM: i8 = 0;
+
+/// BLE profiles (BLE1-3), RMK's default profile count
+#[cfg(feature = "ble")]
+const BLE_PROFILE_COUNT: u8 = 3;
+
 const UNLOCK_KEYS: &[(u8, u8)] = &[(0, 0), (0, 1)];
 
 const NUM_LEDS: usize = 14;
 
+/// SPIM instance driving the LED strip. Another instance also needs its interrupt swapped in
+/// both `Irqs` above (SPIM3 for SPI3, SPIM2_SPIS2_SPI2 for SPI2, ...).
+type LedSpi = peripherals::SPI3;
+
+/// The LED strip's SPIM instance and pins, taken out of `p` in one place so a board revision
+/// with the strip elsewhere only changes this (and `LedSpi`). Only MOSI carries anything: it's
+/// the WS2812 data line, first LED's DIN on P0_26. SPIM won't run without SCK and MISO, so
+/// those go to spare pins the strip doesn't touch - nothing else may claim them.
+macro_rules! led_spi {
+    ($p:ident) => {
+        // (instance, SCK, MISO, MOSI = data)
+        ($p.SPI3, $p.P0_21, $p.P0_28, $p.P0_26)
+    };
+}
+
+/// Startup animation per power source: the full wave on USB, just a short flash on battery to
+/// save the cell and the inrush (any of Full / Minimal / Off for either). If VBUS is still
+/// bouncing at boot (plugged in that very moment) the battery choice is used.
+const STARTUP_ANIMATION_USB: StartupAnimation = StartupAnimation::Full;
+const STARTUP_ANIMATION_BATTERY: StartupAnimation = StartupAnimation::Minimal;
+
+/// Encoder pulses per detent (`FilteredEncoder`'s resolution): one step every this many
+/// quadrature steps. To find it for a given encoder, check the datasheet for "pulses" vs
+/// "detents" (a 24-detent / 12-pulse part is 2), or set this to 1 and count events per click -
+/// that count is the value. Too high and it skips clicks, too low and every click sends several events.
+/// Anything that works per click (step acceleration, jitter filtering) sees what comes out of
+/// this, so get it right first.
+const ENCODER_RESOLUTION: u8 = 4;
+const _: () = assert!(
+    matches!(ENCODER_RESOLUTION, 1 | 2 | 4),
+    "ENCODER_RESOLUTION must be 1, 2 or 4"
+);
+
+/// Blink the profile LED at all when a BLE connection comes up, false goes straight to the
+/// connected indicator
+const CONNECT_BLINK_ENABLED: bool = true;
+/// Profile LED blinks when a BLE connection comes up, 0 to skip straight to the indicator
+const CONNECT_BLINK_COUNT: u8 = 4;
+const CONNECT_BLINK_COLOR: RGB8 = RGB8 { r: 0, g: 70, b: 0 };
+/// Blink profile + 1 times instead (BLE1 = 1 blink, BLE3 = 3), off keeps the fixed count
+const CONNECT_BLINK_PER_PROFILE: bool = false;
+
+/// Steady color per BLE profile on the profile LED while connected, replacing the connect blink
+/// and the dim green indicator. Off by default. Keep these dim and away from the advertising blue.
+const CONNECTED_COLORS: Option<[RGB8; 3]> = None;
+
+/// Splits the strip into a status region (this many LEDs from the start) and an underglow region
+/// (the rest), e.g. `Some(1)` for a single status LED. Off by default, the whole strip is one.
+const LED_STATUS_REGION: Option<usize> = None;
+
+/// Always-on underglow (color, LED range), off by default. Once set from the dev console,
+/// the saved value wins over this.
+const UNDERGLOW: Underglow = None;
+
+/// Battery voltage divider (1MΩ measured / 1.4MΩ total), see battery_curve.rs
+const BATTERY_DIVIDER_MEASURED: u32 = 1000;
+const BATTERY_DIVIDER_TOTAL: u32 = 1400;
+
+/// Boot reading outside this range means the pin or divider is wrong (or there's no battery),
+/// the reading is treated as unknown rather than shown as 0% or 100%
+const BATTERY_PLAUSIBLE_MV: core::ops::RangeInclusive<u32> = 2500..=4500;
+
+/// SAADC calibration and a single sample take well under a millisecond, anything longer means
+/// the peripheral isn't responding
+const ADC_INIT_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_millis(100);
+
+#[cfg(feature = "ble")]
 fn build_sdc<'d, const N: usize>(
     p: nrf_sdc::Peripherals<'d>,
     rng: &'d mut rng::Rng<Async>,
     mpsl: &'d MultiprotocolServiceLayer,
     mem: &'d mut sdc::Mem<N>,
 ) -> Result<nrf_sdc::SoftdeviceController<'d>, nrf_sdc::Error> {
-    sdc::Builder::new()?
+    let builder = sdc::Builder::new()?
         .support_adv()?
         .support_peripheral()?
         .support_dle_peripheral()?
         .support_phy_update_peripheral()?
         .support_le_2m_phy()?
         .peripheral_count(1)?
-        .buffer_cfg(L2CAP_MTU as u16, L2CAP_MTU as u16, L2CAP_TXQ, L2CAP_RXQ)?
-        .build(p, rng, mpsl, mem)
+        .buffer_cfg(L2CAP_MTU as u16, L2CAP_MTU as u16, L2CAP_TXQ, L2CAP_RXQ)?;
+    // After sdc_init (Builder::new) and before sdc_enable (build), so every role picks it up
+    set_tx_power(BLE_TX_POWER_DBM);
+    builder.build(p, rng, mpsl, mem)
+}
+
+/// Default TX power for every advertising set and connection created from here on
+#[cfg(feature = "ble")]
+fn set_tx_power(dbm: i8) {
+    // nrf-sdc's builder has no setting for it, the SDC C API does
+    let ret = unsafe { sdc::raw::sdc_default_tx_power_set(dbm) };
+    if ret == 0 {
+        info!("BLE TX power: {} dBm", dbm);
+    } else {
+        warn!("Failed to set BLE TX power to {} dBm: {}", dbm, ret);
+    }
 }
 
-/// Initializes the SAADC peripheral in single-ended mode on the given pin.
-fn init_adc(adc_pin: AnyInput, adc: Peri<'static, SAADC>) -> Saadc<'static, 1> {
-    // Then we initialize the ADC. We are only using one channel in this example.
+/// Battery on channel 0, then either the joystick X/Y with `joystick` (`NrfAdc` hands channels
+/// to event types in order) or the light sensor with `ambient-light` (read by `BatteryAdc`).
+const ADC_CHANNELS: usize =
+    1 + 2 * cfg!(feature = "joystick") as usize + cfg!(feature = "ambient-light") as usize;
+
+/// Initializes the SAADC peripheral in single-ended mode on the given pins.
+fn init_adc(
+    adc_pin: AnyInput,
+    #[cfg(feature = "joystick")] joystick_pins: [AnyInput; 2],
+    #[cfg(feature = "ambient-light")] light_pin: AnyInput,
+    adc: Peri<'static, SAADC>,
+) -> Saadc<'static, ADC_CHANNELS> {
     let config = saadc::Config::default();
-    let channel_cfg = saadc::ChannelConfig::single_ended(adc_pin.degrade_saadc());
+    #[cfg(feature = "joystick")]
+    let [joystick_x, joystick_y] = joystick_pins;
+    let channels = [
+        saadc::ChannelConfig::single_ended(adc_pin.degrade_saadc()),
+        #[cfg(feature = "joystick")]
+        saadc::ChannelConfig::single_ended(joystick_x.degrade_saadc()),
+        #[cfg(feature = "joystick")]
+        saadc::ChannelConfig::single_ended(joystick_y.degrade_saadc()),
+        #[cfg(feature = "ambient-light")]
+        saadc::ChannelConfig::single_ended(light_pin.degrade_saadc()),
+    ];
     interrupt::SAADC.set_priority(interrupt::Priority::P3);
-    let saadc = saadc::Saadc::new(adc, Irqs, config, [channel_cfg]);
+    let saadc = saadc::Saadc::new(adc, Irqs, config, channels);
     saadc
 }
 
+/// Takes a single raw battery reading, None if the ADC doesn't answer.
+/// Only used at boot, `BatteryAdc` (`NrfAdc` with `joystick`) owns the SAADC after that.
+async fn sample_battery_raw(saadc: &mut Saadc<'static, ADC_CHANNELS>) -> Option<u16> {
+    let mut buf = [0i16; ADC_CHANNELS];
+    if embassy_time::with_timeout(ADC_INIT_TIMEOUT, saadc.sample(&mut buf))
+        .await
+        .is_err()
+    {
+        warn!("SAADC sample timed out - battery level unknown");
+        return None;
+    }
+    Some(buf[0].max(0) as u16)
+}
+
+/// Maps the boot battery reading to a percentage on the same curve as `CurveBatteryProcessor`.
+/// Only used at boot to gate the startup animation, the processor takes over after that.
+/// Returns `BATTERY_UNKNOWN` if the reading makes no sense.
+fn battery_percentage(raw: u16, calibration: BatteryCalibration) -> u8 {
+    let battery_mv = battery_cal::battery_mv(
+        raw,
+        calibration,
+        BATTERY_DIVIDER_MEASURED,
+        BATTERY_DIVIDER_TOTAL,
+    );
+    if !BATTERY_PLAUSIBLE_MV.contains(&battery_mv) {
+        warn!(
+            "Battery reading {}mV is implausible, check the ADC pin and divider - battery level unknown",
+            battery_mv
+        );
+        return BATTERY_UNKNOWN;
+    }
+    let (empty, full) =
+        battery_curve::endpoints(calibration, BATTERY_DIVIDER_MEASURED, BATTERY_DIVIDER_TOTAL);
+    battery_curve::percentage(BATTERY_CURVE, raw, empty, full)
+}
+
+#[cfg(feature = "ble")]
 fn ble_addr() -> [u8; 6] {
     let ficr = pac::FICR;
     let high = u64::from(ficr.deviceid(1).read());
     let addr = high << 32 | u64::from(ficr.deviceid(0).read());
     let addr = addr | 0x0000_c000_0000_0000;
+    // Always 6 of the 8 bytes, can't fail
     unwrap!(addr.to_le_bytes()[..6].try_into())
 }
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
+    #[cfg(feature = "dev-console")]
+    let console_input = debug_console::init_rtt();
+    #[cfg(feature = "ble")]
     info!("Hello RMK BLE!");
+    #[cfg(not(feature = "ble"))]
+    info!("Hello RMK (USB only)!");
     // Initialize the peripherals and nrf-sdc controller
     let mut nrf_config = embassy_nrf::config::Config::default();
     nrf_config.dcdc.reg0_voltage = Some(embassy_nrf::config::Reg0Voltage::_3V3);
@@ -118,8 +407,12 @@ async fn main(spawner: Spawner) {
     nrf_config.dcdc.reg0 = false;
     nrf_config.dcdc.reg1 = false;
     let p = embassy_nrf::init(nrf_config);
+    reset_reason::log_and_clear();
+    let reset_loop = boot_loop::record_boot();
+    #[cfg(feature = "ble")]
     let mpsl_p =
         mpsl::Peripherals::new(p.RTC0, p.TIMER0, p.TEMP, p.PPI_CH19, p.PPI_CH30, p.PPI_CH31);
+    #[cfg(feature = "ble")]
     let lfclk_cfg = mpsl::raw::mpsl_clock_lfclk_cfg_t {
         source: mpsl::raw::MPSL_CLOCK_LF_SRC_RC as u8,
         rc_ctiv: mpsl::raw::MPSL_RECOMMENDED_RC_CTIV as u8,
@@ -127,39 +420,122 @@ async fn main(spawner: Spawner) {
         accuracy_ppm: mpsl::raw::MPSL_DEFAULT_CLOCK_ACCURACY_PPM as u16,
         skip_wait_lfclk_started: mpsl::raw::MPSL_DEFAULT_SKIP_WAIT_LFCLK_STARTED != 0,
     };
+    #[cfg(feature = "ble")]
     static MPSL: StaticCell<MultiprotocolServiceLayer> = StaticCell::new();
+    #[cfg(feature = "ble")]
     static SESSION_MEM: StaticCell<mpsl::SessionMem<1>> = StaticCell::new();
-    let mpsl = MPSL.init(unwrap!(mpsl::MultiprotocolServiceLayer::with_timeslots(
+    #[cfg(feature = "ble")]
+    let mpsl = match mpsl::MultiprotocolServiceLayer::with_timeslots(
         mpsl_p,
         Irqs,
         lfclk_cfg,
-        SESSION_MEM.init(mpsl::SessionMem::new())
-    )));
-    spawner.must_spawn(mpsl_task(&*mpsl));
+        SESSION_MEM.init(mpsl::SessionMem::new()),
+    ) {
+        Ok(mpsl) => MPSL.init(mpsl),
+        Err(e) => init_error::halt(InitError::Mpsl(e)),
+    };
+    #[cfg(feature = "ble")]
+    spawner
+        .spawn(mpsl_task(&*mpsl))
+        .unwrap_or_else(|_| init_error::halt(InitError::Spawn("mpsl_task")));
+    // Reads go through MPSL, so this has to start after it
+    #[cfg(feature = "ble")]
+    spawner
+        .spawn(thermal_task())
+        .unwrap_or_else(|_| init_error::halt(InitError::Spawn("thermal_task")));
+    #[cfg(feature = "ble")]
     let sdc_p = sdc::Peripherals::new(
         p.PPI_CH17, p.PPI_CH18, p.PPI_CH20, p.PPI_CH21, p.PPI_CH22, p.PPI_CH23, p.PPI_CH24,
         p.PPI_CH25, p.PPI_CH26, p.PPI_CH27, p.PPI_CH28, p.PPI_CH29,
     );
+    #[cfg(feature = "ble")]
     let mut rng = rng::Rng::new(p.RNG, Irqs);
-    let mut rng_gen = ChaCha12Rng::from_rng(&mut rng).unwrap();
+    #[cfg(feature = "ble")]
+    let mut rng_gen =
+        ChaCha12Rng::from_rng(&mut rng).unwrap_or_else(|_| init_error::halt(InitError::RngSeed));
+    #[cfg(feature = "ble")]
     let mut sdc_mem = sdc::Mem::<4096>::new();
-    let sdc = unwrap!(build_sdc(sdc_p, &mut rng, mpsl, &mut sdc_mem));
+    #[cfg(feature = "ble")]
+    let sdc = build_sdc(sdc_p, &mut rng, mpsl, &mut sdc_mem)
+        .unwrap_or_else(|e| init_error::halt(InitError::Sdc(e)));
+    #[cfg(feature = "ble")]
     let mut host_resources = HostResources::new();
+    #[cfg(feature = "ble")]
     let stack = build_ble_stack(sdc, ble_addr(), &mut rng_gen, &mut host_resources).await;
+    // Nothing else uses TEMP without MPSL, so the thermal task reads it directly
+    #[cfg(not(feature = "ble"))]
+    spawner
+        .spawn(thermal_task(temp::Temp::new(p.TEMP, Irqs)))
+        .unwrap_or_else(|_| init_error::halt(InitError::Spawn("thermal_task")));
 
     // Initialize usb driver
     let driver = Driver::new(p.USBD, Irqs, HardwareVbusDetect::new(Irqs));
 
-    // Initialize flash
-    let flash = Flash::take(mpsl, p.NVMC);
+    // Initialize flash - check the storage regions first, a bad layout would eat code or the bootloader
+    flash_layout::validate();
+    // Shared between RMK's storage (partition over the whole flash, so its addresses are unchanged)
+    // and our own settings region. Everything runs on one executor, so no real locking is needed.
+    static FLASH: StaticCell<Mutex<NoopRawMutex, RefCell<Flash>>> = StaticCell::new();
+    #[cfg(feature = "ble")]
+    let nvmc = Flash::take(mpsl, p.NVMC);
+    #[cfg(not(feature = "ble"))]
+    let nvmc = Flash::new(p.NVMC);
+    let shared_flash = FLASH.init(Mutex::new(RefCell::new(nvmc)));
+    let flash = BlockingPartition::new(shared_flash, 0, FLASH_SIZE);
+    let mut settings_store = SettingsStore::new(BlockingPartition::new(
+        shared_flash,
+        SETTINGS_START_ADDR,
+        SETTINGS_NUM_SECTORS * SETTINGS_SECTOR_SIZE,
+    ));
+    let settings = settings_store.load().await;
 
     // Initialize the ADC.
-    // We are only using one channel for detecting battery level
+    // Channel 0 reads the battery level, then the optional joystick and light sensor
     let adc_pin = p.P0_04.degrade_saadc();
     // let is_charging_pin = Input::new(p.P1_09, embassy_nrf::gpio::Pull::Up);
-    let saadc = init_adc(adc_pin, p.SAADC);
-    // Wait for ADC calibration.
-    saadc.calibrate().await;
+    let mut saadc = init_adc(
+        adc_pin,
+        #[cfg(feature = "joystick")]
+        [p.P0_02.degrade_saadc(), p.P0_03.degrade_saadc()],
+        #[cfg(feature = "ambient-light")]
+        p.P0_31.degrade_saadc(),
+        p.SAADC,
+    );
+    // Wait for ADC calibration. On a misconfigured board this may never finish, so give up
+    // after a while and keep going without battery data rather than hanging here.
+    // One reading up front, so the startup animation can be skipped on a flat battery
+    let boot_battery_raw =
+        match embassy_time::with_timeout(ADC_INIT_TIMEOUT, saadc.calibrate()).await {
+            Ok(()) => sample_battery_raw(&mut saadc).await,
+            Err(_) => {
+                warn!("SAADC calibration timed out - battery level unknown");
+                None
+            }
+        };
+    let boot_battery_percentage = boot_battery_raw.map_or(BATTERY_UNKNOWN, |raw| {
+        battery_percentage(raw, settings.battery_calibration)
+    });
+    info!("Boot battery reading: {}%", boot_battery_percentage);
+    if let Some(raw) = boot_battery_raw {
+        raw_adc::BATTERY_RAW.store(raw, core::sync::atomic::Ordering::Relaxed);
+    }
+    // USB power state for the LED dimming on battery
+    spawner
+        .spawn(vbus::vbus_task())
+        .unwrap_or_else(|_| init_error::halt(InitError::Spawn("vbus_task")));
+    // Clears the reset loop counter once we've been up a while
+    spawner
+        .spawn(boot_loop::stable_uptime_task())
+        .unwrap_or_else(|_| init_error::halt(InitError::Spawn("stable_uptime_task")));
+    // Smooths the light readings from BatteryAdc into a brightness, see ambient_light.rs
+    #[cfg(feature = "ambient-light")]
+    spawner
+        .spawn(ambient_light::ambient_light_task())
+        .unwrap_or_else(|_| init_error::halt(InitError::Spawn("ambient_light_task")));
+    #[cfg(feature = "dev-console")]
+    spawner
+        .spawn(debug_console::debug_console_task(console_input))
+        .unwrap_or_else(|_| init_error::halt(InitError::Spawn("debug_console_task")));
 
     // Keyboard config
     let keyboard_device_config = DeviceConfig {
@@ -171,45 +547,25 @@ async fn main(spawner: Spawner) {
     };
     let vial_config = VialConfig::new(VIAL_KEYBOARD_ID, VIAL_KEYBOARD_DEF, UNLOCK_KEYS);
     // let ble_battery_config = BleBatteryConfig::new(Some(is_charging_pin), true, None, false);
+    #[cfg(feature = "ble")]
     let ble_battery_config = BleBatteryConfig::new(None, true, None, false);
     let storage_config = StorageConfig {
-        start_addr: 0xA0000, // FIXME: use 0x70000 after we can build without softdevice controller
-        num_sectors: 12,     // Sectors are 4KB each on nRF52840 -- 24 sectors = 96KB
+        // Geometry and checks live in flash_layout.rs
+        start_addr: RMK_STORAGE_START_ADDR as usize,
+        num_sectors: RMK_STORAGE_NUM_SECTORS as u8,
         clear_storage: false,
         clear_layout: false,
     };
     let rmk_config = RmkConfig {
         device_config: keyboard_device_config,
         vial_config,
+        #[cfg(feature = "ble")]
         ble_battery_config,
         storage_config,
         ..Default::default()
     };
 
-    // Initialze keyboard stuffs
-    // Initialize the storage and keymap
-    let mut default_keymap = keymap::get_default_keymap();
-    let mut key_config = PositionalConfig::default();
-    let mut behavior_config = BehaviorConfig::default();
-
-    // Configure tapdance behaviors
-    keymap::configure_tapdance(&mut behavior_config);
-
-    // Configure macros
-    keymap::configure_macros(&mut behavior_config);
-
-    let mut encoder_map = keymap::get_default_encoder_map();
-    let (keymap, mut storage) = initialize_encoder_keymap_and_storage(
-        &mut default_keymap,
-        &mut encoder_map,
-        flash,
-        &storage_config,
-        &mut behavior_config,
-        &mut key_config,
-    )
-    .await;
-
-    // Initialize the matrix and keyboard
+    // Matrix pins, set up ahead of the keymap for the safe mode check
     // Column to Row (Diodes pointing from Column to Row)
     // Columns:
     //   Column 3: P1_09 (SW1 Net on Schematic)
@@ -222,13 +578,98 @@ async fn main(spawner: Spawner) {
     //   Row 2: P0_22
     //   Row 3: P0_24
     #[rustfmt::skip]
-    let (input_pins, output_pins) = config_matrix_pins_nrf! {
+    let (mut input_pins, mut output_pins) = config_matrix_pins_nrf! {
         peripherals: p,
         input: [P0_17, P0_20, P0_22, P0_24], // Rows
         output: [P0_15, P0_11, P0_12, P1_09] // Columns
     };
 
-    let debouncer = DefaultDebouncer::new();
+    // Held at boot: firmware default keymap for this session, see safe_mode.rs
+    let safe_mode = safe_mode::key_held_at_boot(&mut input_pins, &mut output_pins);
+
+    // Initialze keyboard stuffs
+    // Initialize the storage and keymap
+    let mut default_keymap = keymap::get_default_keymap();
+    keymap::configure_home_row_mods(&mut default_keymap);
+    keymap::configure_auto_shift(&mut default_keymap);
+    let mut key_config = PositionalConfig::default();
+    let mut behavior_config = BehaviorConfig::default();
+
+    // Configure tapdance behaviors
+    keymap::configure_tapdance(&mut behavior_config, settings.hold_timeout_ms);
+    // Tapping term for lt! keys, tuned together with the tapdances
+    keymap::configure_layer_tap(&mut behavior_config, settings.hold_timeout_ms);
+
+    // Configure one-shot modifiers
+    keymap::configure_one_shot(&mut behavior_config);
+
+    // Configure macros
+    keymap::configure_macros(&mut behavior_config);
+
+    // Only the first boot (or a storage reset) uses this map, after that RMK loads the encoder
+    // actions from storage, so remaps made in Vial survive reboots. Encoder ids here must match
+    // the "<id>,<dir>" keys in vial.json.
+    let mut encoder_map = keymap::get_default_encoder_map();
+    let (keymap, mut storage) = if safe_mode {
+        // Same as initialize_encoder_keymap_and_storage (rmk/src/lib.rs), except the keymap
+        // doesn't get the storage to load the stored layout from. Storage itself is opened as
+        // usual, so Vial edits and every other setting are still saved.
+        let mut storage = Storage::new(
+            flash,
+            &default_keymap,
+            &Some(encoder_map),
+            &storage_config,
+            &behavior_config,
+        )
+        .await;
+        let keymap = RefCell::new(
+            KeyMap::new_from_storage(
+                &mut default_keymap,
+                Some(&mut encoder_map),
+                None::<&mut Storage<_, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+                &mut behavior_config,
+                &mut key_config,
+            )
+            .await,
+        );
+        safe_mode::confirm();
+        (keymap, storage)
+    } else {
+        initialize_encoder_keymap_and_storage(
+            &mut default_keymap,
+            &mut encoder_map,
+            flash,
+            &storage_config,
+            &mut behavior_config,
+            &mut key_config,
+        )
+        .await
+    };
+
+    // First pairing guidance on the LEDs until a host connects. RMK keeps each profile's bond
+    // in its storage, one slot per profile - all empty means nothing was ever paired (or every
+    // bond was cleared). A slot that can't be read counts as bonded, so a flaky read never
+    // shows the first pairing pulse on a paired board.
+    #[cfg(feature = "ble")]
+    let first_pairing = {
+        let mut first_pairing = true;
+        for slot in 0..BLE_PROFILE_COUNT {
+            if !matches!(storage.read_trouble_bond_info(slot).await, Ok(None)) {
+                first_pairing = false;
+                break;
+            }
+        }
+        first_pairing
+    };
+    #[cfg(not(feature = "ble"))]
+    let first_pairing = false;
+    if first_pairing {
+        info!("No bonds stored - first pairing");
+    }
+
+    // Initialize the matrix and keyboard
+    // Each input device gets its own debouncer so thresholds can differ (see debounce.rs)
+    let debouncer = TimedDebouncer::<ROW, COL, MATRIX_DEBOUNCE_MS>::new();
     // Matrix type: <Input, Output, Debouncer, ROW, COL, COL2ROW>
     // COL2ROW = true means column-to-row (diodes pointing from column to row)
     let mut matrix =
@@ -239,39 +680,204 @@ async fn main(spawner: Spawner) {
     // Encoder Pin A: P0_08, Pin B: P0_06
     let pin_a = Input::new(p.P0_08, embassy_nrf::gpio::Pull::Up);
     let pin_b = Input::new(p.P0_06, embassy_nrf::gpio::Pull::Up);
-    let mut encoder = RotaryEncoder::with_resolution(pin_a, pin_b, 4, false, 0);
-
+    // Idle jitter between detents is filtered out, see encoder_filter.rs
+    let mut encoder = FilteredEncoder::new(pin_a, pin_b, ENCODER_RESOLUTION, false);
+    // Encoder push-button: P0_04 is used for the battery ADC on this revision. Once it has its own
+    // pin, read it as a 1x1 direct pin matrix with the (longer) encoder button debounce:
+    // let encoder_button_pins = config_matrix_pins_nrf!(peripherals: p, direct_pins: [[P0_04]]);
+    // let encoder_button_debouncer = TimedDebouncer::<1, 1, ENCODER_BUTTON_DEBOUNCE_MS>::new();
+
+    // Battery and light sensor samples plus periodic recalibration, see adc.rs
+    #[cfg(not(feature = "joystick"))]
+    let mut adc_device = BatteryAdc::new(saadc);
+    // The stick needs fast sampling while it's in use, see joystick.rs
+    #[cfg(feature = "joystick")]
     let mut adc_device = NrfAdc::new(
         saadc,
-        [AnalogEventType::Battery],
-        embassy_time::Duration::from_secs(12),
-        None,
+        [AnalogEventType::Battery, AnalogEventType::Joystick(2)],
+        joystick::JOYSTICK_POLL_INTERVAL,
+        Some(joystick::JOYSTICK_IDLE_POLL_INTERVAL),
+    );
+    // A stored calibration replaces the nominal divider, see battery_cal.rs
+    let mut batt_proc = CurveBatteryProcessor::new(
+        settings.battery_calibration,
+        BATTERY_DIVIDER_MEASURED,
+        BATTERY_DIVIDER_TOTAL,
     );
-    let mut batt_proc = BatteryProcessor::new(1000, 1400);
 
+    // The panic handler switches this off by register, see panic.rs
     let mosfet_sk_pwr_ctrl = Output::new(p.P0_29, Level::Low, OutputDrive::Standard);
 
     let mut spim_config = spim::Config::default();
-    spim_config.frequency = spim::Frequency::M4;
-
-    let spim = spim::Spim::new(p.SPI3, Irqs, p.P0_21, p.P0_28, p.P0_26, spim_config);
-    // Bit pattern that works for nRF52840 at 4MHz
-
-    // let ws2812 = Ws2812::new_with_custom_patterns(spim, CUSTOM_PATTERNS);
-    let ws2812 = Ws2812::new(spim);
+    spim_config.frequency = LED_SPI_FREQUENCY;
+
+    let (led_spi, led_sck, led_miso, led_data) = led_spi!(p);
+    let spim = spim::Spim::new(led_spi, Irqs, led_sck, led_miso, led_data, spim_config);
+    // Bit patterns (stock and custom) are both for nRF52840 at 4MHz, see led/timing.rs
+    let ws2812 = if LED_USE_CUSTOM_PATTERNS {
+        Ws2812::new_with_custom_patterns(spim, CUSTOM_PATTERNS)
+    } else {
+        Ws2812::new(spim)
+    };
 
     // Run bootup animation
-    let mut startup_animator = StartupAnimator::<NUM_LEDS>::new(ws2812, mosfet_sk_pwr_ctrl);
-    startup_animator.bootup_animation().await;
+    let mut startup_animator = StartupAnimator::<_, NUM_LEDS>::new(ws2812, mosfet_sk_pwr_ctrl);
+    // Settings are already loaded, so a disabled strip never lights up, not even at boot.
+    // In a reset loop it's skipped too, see boot_loop.rs
+    if !settings.leds_disabled && !reset_loop {
+        let animation = match vbus::vbus_at_boot().await {
+            Some(true) => STARTUP_ANIMATION_USB,
+            Some(false) => STARTUP_ANIMATION_BATTERY,
+            None => {
+                warn!("VBUS unsettled at boot, using the battery startup animation");
+                STARTUP_ANIMATION_BATTERY
+            }
+        };
+        info!("Startup animation: {}", animation);
+        startup_animator
+            .bootup_animation(animation, boot_battery_percentage)
+            .await;
+    }
     let (ws2812, mosfet_sk_pwr_ctrl) = startup_animator.take();
 
-    let mut status_led: StatusLedController<'_, NUM_LEDS> =
-        StatusLedController::<NUM_LEDS>::new(ws2812, mosfet_sk_pwr_ctrl);
-
-    // Run all devices, processors, keyboard, controller, and RMK concurrently
-    rmk::embassy_futures::join::join(
-        run_all!(matrix, encoder, adc_device, batt_proc, keyboard, status_led),
-        run_rmk(&keymap, driver, &stack, &mut storage, rmk_config),
+    // The controllers only see the strip as a `SmartLedsWrite` sink, this is the real one
+    let mut status_led: StatusLedController<'_, _, NUM_LEDS> =
+        StatusLedController::<_, NUM_LEDS>::new(
+            ws2812,
+            mosfet_sk_pwr_ctrl,
+            settings.leds_disabled,
+            boot_battery_percentage,
+            settings.active_ble_profile,
+        )
+        .with_connect_blink_enabled(CONNECT_BLINK_ENABLED)
+        .with_connect_blink(CONNECT_BLINK_COUNT, CONNECT_BLINK_COLOR)
+        .with_connect_blink_per_profile(CONNECT_BLINK_PER_PROFILE)
+        .with_connected_colors(CONNECTED_COLORS)
+        .with_first_pairing(first_pairing)
+        .with_status_region(LED_STATUS_REGION)
+        .with_underglow(settings.underglow.map_or(UNDERGLOW, underglow_from_bytes))
+        .with_profile_leds(settings.profile_leds);
+
+    let mut hold_timeout_tuner = HoldTimeoutTuner::new(&keymap, settings.hold_timeout_ms);
+    let mut idle_monitor = IdleMonitor::new();
+    let mut profile_layer_switcher =
+        ProfileLayerSwitcher::new(&keymap, settings.active_ble_profile);
+    let mut layer_return = LayerReturn::new(&keymap);
+    let mut bond_manager = BondManager::new();
+    let mut output_monitor = OutputMonitor::new();
+    let mut battery_calibrator = BatteryCalibrator::new(
+        boot_battery_raw,
+        settings.battery_calibration,
+        BATTERY_DIVIDER_MEASURED,
+        BATTERY_DIVIDER_TOTAL,
+    );
+    let mut key_odometer = KeyOdometer::new(settings.odometer_total, settings.odometer_per_key);
+    let mut charge_cycle_counter =
+        ChargeCycleCounter::new(settings.charge_cycles, settings.charge_partial_percent);
+    let mut raw_adc_view = RawAdcView::new();
+    let mut runtime_estimator = RuntimeEstimator::new();
+    let mut low_battery_alert = LowBatteryAlert::new();
+    let mut hold_repeat_config = HoldRepeatConfig::new();
+    keymap::configure_hold_repeat(&mut hold_repeat_config);
+    let mut key_repeater = KeyRepeater::new(hold_repeat_config);
+    let mut volume_stepper = VolumeStepper::new();
+
+    // E-ink status display on its own SPIM, so it never waits behind (or garbles) an LED write.
+    // SCK P0_02, MOSI P0_03, CS P0_05, DC P0_30, RST P1_11, BUSY P1_13 - TX only, the panel
+    // never talks back over SPI.
+    #[cfg(feature = "eink")]
+    let mut eink_status = {
+        let mut eink_spim_config = spim::Config::default();
+        eink_spim_config.frequency = spim::Frequency::M4;
+        let eink_spim = spim::Spim::new_txonly(p.SPI2, Irqs, p.P0_02, p.P0_03, eink_spim_config);
+        let panel = display::eink::Ssd1680::new(
+            eink_spim,
+            Output::new(p.P0_05, Level::High, OutputDrive::Standard),
+            Output::new(p.P0_30, Level::Low, OutputDrive::Standard),
+            Output::new(p.P1_11, Level::High, OutputDrive::Standard),
+            Input::new(p.P1_13, embassy_nrf::gpio::Pull::None),
+        );
+        display::eink::EinkStatusController::new(panel, boot_battery_percentage)
+    };
+    #[cfg(feature = "eink")]
+    let eink = eink_status.run();
+    #[cfg(not(feature = "eink"))]
+    let eink = core::future::ready(());
+
+    // Mouse reports from the thumbstick, see joystick.rs
+    #[cfg(feature = "joystick")]
+    let mut joystick_proc = JoystickProcessor::new(
+        joystick::JOYSTICK_TRANSFORM,
+        joystick::JOYSTICK_BIAS,
+        joystick::JOYSTICK_DEAD_ZONE,
+        &keymap,
+    );
+    #[cfg(feature = "joystick")]
+    let joystick = joystick_proc.run();
+    #[cfg(not(feature = "joystick"))]
+    let joystick = core::future::ready(());
+
+    // Piezo buzzer, see audio.rs. P0_09 is one of the NFC pins, free as GPIO here
+    // (nfc-pins-as-gpio), and nothing else on the board uses PWM0. Buzzer + to the pin,
+    // - to GND - a bare piezo draws little enough for the pin to drive it, a magnetic one
+    // needs a transistor.
+    #[cfg(feature = "buzzer")]
+    let buzzer_player = audio::run_player(embassy_nrf::pwm::SimplePwm::new_1ch(p.PWM0, p.P0_09));
+    // The connect chime is the only tune driven by events here, USB-only builds have no use
+    // for it
+    #[cfg(all(feature = "buzzer", feature = "ble"))]
+    let mut buzzer_controller = audio::BuzzerController::new();
+    #[cfg(all(feature = "buzzer", feature = "ble"))]
+    let buzzer = rmk::embassy_futures::join::join(buzzer_controller.run(), buzzer_player);
+    #[cfg(all(feature = "buzzer", not(feature = "ble")))]
+    let buzzer = buzzer_player;
+    #[cfg(not(feature = "buzzer"))]
+    let buzzer = core::future::ready(());
+
+    // Dev-only diagnostics: the heartbeat log and the keymap dump
+    #[cfg(feature = "dev")]
+    let mut heartbeat = heartbeat::Heartbeat::new();
+    #[cfg(feature = "dev")]
+    let mut keymap_dumper = keymap_dump::KeymapDumper::new(&keymap);
+    #[cfg(feature = "dev")]
+    let dev_tools = rmk::embassy_futures::join::join(heartbeat.run(), keymap_dumper.run());
+    #[cfg(not(feature = "dev"))]
+    let dev_tools = core::future::ready(());
+
+    #[cfg(feature = "ble")]
+    let rmk = run_rmk(&keymap, driver, &stack, &mut storage, rmk_config);
+    #[cfg(not(feature = "ble"))]
+    let rmk = run_rmk(&keymap, driver, &mut storage, rmk_config);
+
+    // Run all devices, processors, keyboard, controllers, settings storage and RMK concurrently.
+    // The e-ink controller, the dev tools, the joystick processor and the buzzer are feature
+    // gated, so they can't go into run_all! with the others.
+    rmk::embassy_futures::join::join4(
+        run_all!(
+            matrix,
+            encoder,
+            adc_device,
+            batt_proc,
+            keyboard,
+            status_led,
+            hold_timeout_tuner,
+            idle_monitor,
+            key_odometer,
+            charge_cycle_counter,
+            raw_adc_view,
+            runtime_estimator,
+            low_battery_alert,
+            key_repeater,
+            volume_stepper,
+            profile_layer_switcher,
+            layer_return,
+            bond_manager,
+            output_monitor,
+            battery_calibrator
+        ),
+        rmk,
+        settings_store.run(),
+        rmk::embassy_futures::join::join4(eink, dev_tools, joystick, buzzer),
     )
     .await;
 }
@@ -0,0 +1,136 @@
+use defmt::info;
+use embassy_time::{Duration, Instant};
+use rmk::event::{KeyEvent, KeyboardEventPos};
+use rmk::macros::controller;
+use rmk::types::action::KeyAction;
+use smart_leds::RGB8;
+
+use crate::keymap::{COL, SIZE};
+use crate::led::{LED_HEATMAP, LED_READOUT, LedReadout};
+use crate::repeat::take_injected_press;
+use crate::settings::{SETTINGS_CHANNEL, SettingsUpdate};
+use crate::user_actions::{HEATMAP_SHOW, ODOMETER_SHOW};
+
+// Flash wear: every flush writes ~90 bytes into the 8KB settings region, so a sector gets erased
+// roughly every 45 flushes. Flushing every 500 presses, or after a minute without typing, works
+// out to about one erase per sector per day of normal use - nRF52840 flash is rated for 10k.
+const ODOMETER_FLUSH_PRESSES: u16 = 500;
+const ODOMETER_IDLE_FLUSH: Duration = Duration::from_secs(60);
+
+// Readout shows the order of magnitude of the total: 3 LEDs = hundreds, 4 = thousands, ...
+const READOUT_COLOR: RGB8 = RGB8 { r: 0, g: 40, b: 40 };
+
+// Heatmap: each key's count relative to the most used key, on a blue (cold) to red (hot) scale.
+// It's a snapshot taken on the press, shown for a few seconds and not refreshed while up.
+// Keys go along the bar in matrix order (row by row), see `StatusLedController` for the mapping.
+
+/// Counts keypresses (total and per matrix key) and saves them in batches
+#[controller(subscribe = [KeyEvent], poll_interval = 10000)]
+pub struct KeyOdometer {
+    total: u32,
+    per_key: [u32; SIZE],
+    unsaved: u16,
+    last_press: Instant,
+    show_key_held: bool,
+    heatmap_key_held: bool,
+}
+
+impl KeyOdometer {
+    pub fn new(total: u32, per_key: [u32; SIZE]) -> Self {
+        Self {
+            total,
+            per_key,
+            unsaved: 0,
+            last_press: Instant::now(),
+            show_key_held: false,
+            heatmap_key_held: false,
+        }
+    }
+
+    async fn flush(&mut self) {
+        info!("Saving odometer: {} presses", self.total);
+        self.unsaved = 0;
+        SETTINGS_CHANNEL
+            .send(SettingsUpdate::Odometer {
+                total: self.total,
+                per_key: self.per_key,
+            })
+            .await;
+    }
+
+    fn show(&mut self) {
+        info!(
+            "Odometer: {} presses, per key: {:?}",
+            self.total, self.per_key
+        );
+        // Number of decimal digits, at least 1
+        let mut digits = 1;
+        let mut total = self.total;
+        while total >= 10 {
+            total /= 10;
+            digits += 1;
+        }
+        LED_READOUT.signal(LedReadout {
+            count: digits,
+            color: READOUT_COLOR,
+        });
+    }
+
+    fn show_heatmap(&mut self) {
+        // Normalized to the most used key, so the map is readable no matter the totals
+        let max = self.per_key.iter().copied().max().unwrap_or(0).max(1) as u64;
+        let mut heat = [0u8; SIZE];
+        for (h, &count) in heat.iter_mut().zip(self.per_key.iter()) {
+            *h = (count as u64 * 255 / max) as u8;
+        }
+        info!("Heatmap: {:?}", heat);
+        LED_HEATMAP.signal(heat);
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        if event.key_action == KeyAction::Single(ODOMETER_SHOW) {
+            // Same toggle trick as User7, only show on the press
+            self.show_key_held = !self.show_key_held;
+            if self.show_key_held {
+                self.show();
+            }
+        }
+        if event.key_action == KeyAction::Single(HEATMAP_SHOW) {
+            self.heatmap_key_held = !self.heatmap_key_held;
+            if self.heatmap_key_held {
+                self.show_heatmap();
+            }
+        }
+
+        if !event.keyboard_event.pressed {
+            return;
+        }
+        // Encoder steps arrive as key events too, only count the matrix
+        let KeyboardEventPos::Key(pos) = event.keyboard_event.pos else {
+            return;
+        };
+        // Hold repeats aren't presses, the heatmap counts from here too
+        if take_injected_press(pos.row, pos.col) {
+            return;
+        }
+
+        self.total = self.total.wrapping_add(1);
+        if let Some(count) = self
+            .per_key
+            .get_mut(pos.row as usize * COL + pos.col as usize)
+        {
+            *count = count.wrapping_add(1);
+        }
+        self.last_press = Instant::now();
+        self.unsaved += 1;
+        if self.unsaved >= ODOMETER_FLUSH_PRESSES {
+            self.flush().await;
+        }
+    }
+
+    async fn poll(&mut self) {
+        if self.unsaved > 0 && self.last_press.elapsed() >= ODOMETER_IDLE_FLUSH {
+            self.flush().await;
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use defmt::{Format, info};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+#[cfg(feature = "ble")]
+use rmk::ble::BleState;
+#[cfg(feature = "ble")]
+use rmk::event::BleStateChangeEvent;
+use rmk::event::{ConnectionChangeEvent, ConnectionType};
+use rmk::macros::controller;
+
+// Only USB_BLE_SW moves reports between USB and BLE, RMK switches on its own key handling.
+// The BLE link isn't dropped while USB is active, so switching back is instant - no reconnect.
+//
+// There's no "prefer USB whenever VBUS is there" mode. This tree knows when VBUS comes and
+// goes (vbus.rs), but RMK (ca38784) keeps the connection type inside its own state: it's
+// read from storage at boot and only changed by USB_BLE_SW in its key handling, with no call
+// to set it from outside, and ConnectionChangeEvent only reports RMK's switch after the fact.
+// Saving ConnectionType through FLASH_CHANNEL would only apply on the next boot. Switching on
+// plug-in would need a setter in RMK, the BLE link could then stay up behind USB as it does
+// for USB_BLE_SW.
+
+/// Where key reports actually go, and whether BLE is standing by behind USB
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum EffectiveOutput {
+    Usb,
+    /// USB active, BLE still connected - one switch away
+    UsbBleStandby,
+    Ble,
+    /// BLE selected but not connected (advertising or off)
+    BleDisconnected,
+}
+
+/// Latest effective output, picked up by `StatusLedController` on its next poll
+pub(crate) static EFFECTIVE_OUTPUT: Signal<CriticalSectionRawMutex, EffectiveOutput> =
+    Signal::new();
+
+/// Combines RMK's connection type with the BLE link state into one `EffectiveOutput`
+#[cfg_attr(
+    feature = "ble",
+    controller(subscribe = [ConnectionChangeEvent, BleStateChangeEvent])
+)]
+#[cfg_attr(
+    not(feature = "ble"),
+    controller(subscribe = [ConnectionChangeEvent])
+)]
+pub struct OutputMonitor {
+    usb_selected: bool,
+    ble_connected: bool,
+    reported: Option<EffectiveOutput>,
+}
+
+impl OutputMonitor {
+    pub fn new() -> Self {
+        Self {
+            usb_selected: !cfg!(feature = "ble"),
+            ble_connected: false,
+            reported: None,
+        }
+    }
+
+    fn effective(&self) -> EffectiveOutput {
+        match (self.usb_selected, self.ble_connected) {
+            (true, false) => EffectiveOutput::Usb,
+            (true, true) => EffectiveOutput::UsbBleStandby,
+            (false, true) => EffectiveOutput::Ble,
+            (false, false) => EffectiveOutput::BleDisconnected,
+        }
+    }
+
+    fn publish(&mut self) {
+        let output = self.effective();
+        if self.reported != Some(output) {
+            info!("Effective output: {:?}", output);
+            self.reported = Some(output);
+            EFFECTIVE_OUTPUT.signal(output);
+        }
+    }
+
+    async fn on_connection_change_event(&mut self, event: ConnectionChangeEvent) {
+        self.usb_selected = matches!(event.connection_type, ConnectionType::Usb);
+        self.publish();
+    }
+
+    #[cfg(feature = "ble")]
+    async fn on_ble_state_change_event(&mut self, event: BleStateChangeEvent) {
+        self.ble_connected = matches!(event.state, BleState::Connected);
+        self.publish();
+    }
+}
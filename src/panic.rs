@@ -0,0 +1,35 @@
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::{Display2Format, error};
+use embassy_nrf::pac;
+
+// Our own panic handler in place of panic-probe's, same output (the message over defmt, then
+// a HardFault for probe-rs to catch) but the LED power MOSFET gets switched off first. Without
+// that a crash with the LEDs on leaves the strip powered, and it drains the battery until
+// somebody notices and resets it.
+//
+// The `Output` for the pin lives in StatusLedController, out of reach from here, so the pin is
+// cleared with a single store to P0's OUTCLR. That's safe whatever state the owner left it in:
+// OUTCLR only touches the bits written as 1, there's no read-modify-write that an interrupt
+// could tear, and the pin is already an output. Interrupts are off before that, nothing else
+// runs once we're in here.
+
+/// P0 pin of the LED power MOSFET, must match the `Output` in main.rs
+const LED_POWER_PIN: u32 = 29;
+
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    pac::P0
+        .outclr()
+        .write_value(pac::gpio::regs::Outclr(1 << LED_POWER_PIN));
+
+    // A panic while printing the panic goes straight to the fault
+    if !PANICKED.swap(true, Ordering::Relaxed) {
+        error!("{}", Display2Format(info));
+    }
+    cortex_m::asm::udf()
+}
@@ -0,0 +1,84 @@
+use core::cell::RefCell;
+
+use defmt::info;
+#[cfg(feature = "ble")]
+use rmk::event::BleProfileChangeEvent;
+use rmk::event::{ConnectionChangeEvent, ConnectionType};
+use rmk::keymap::KeyMap;
+use rmk::macros::controller;
+
+use crate::keymap::{COL, NUM_ENCODER, NUM_LAYER, PROFILE_DEFAULT_LAYER, ROW};
+
+// USB isn't a profile, it always gets the normal base layer
+const USB_DEFAULT_LAYER: u8 = 0;
+
+/// Switches the default layer to match the active BLE profile (`keymap::PROFILE_DEFAULT_LAYER`),
+/// e.g. a work layout on BLE1 and a personal one on BLE2. Does nothing while that's `None`.
+///
+/// Layer state lives in RMK's `KeyMap`, which controllers share with the keyboard through the
+/// same `RefCell` (like `HoldTimeoutTuner`). Setting the default layer there is what an
+/// `Action::DefaultLayer` key does, so the next key press already resolves on the new layer.
+/// RMK isn't asked to save it - after a reboot the profile event sets it again.
+#[cfg_attr(
+    feature = "ble",
+    controller(subscribe = [BleProfileChangeEvent, ConnectionChangeEvent])
+)]
+#[cfg_attr(not(feature = "ble"), controller(subscribe = [ConnectionChangeEvent]))]
+pub struct ProfileLayerSwitcher<'a> {
+    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    profile: u8,
+    usb: bool,
+}
+
+impl<'a> ProfileLayerSwitcher<'a> {
+    /// `profile` is the saved active profile, the default layer follows it from the start
+    pub fn new(
+        keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+        profile: u8,
+    ) -> Self {
+        Self {
+            keymap,
+            profile,
+            usb: !cfg!(feature = "ble"),
+        }
+    }
+
+    fn apply(&mut self) {
+        let Some(layers) = PROFILE_DEFAULT_LAYER else {
+            return;
+        };
+        let layer = if self.usb {
+            USB_DEFAULT_LAYER
+        } else {
+            // Profiles past the table keep the normal base layer
+            layers
+                .get(self.profile as usize)
+                .copied()
+                .unwrap_or(USB_DEFAULT_LAYER)
+        };
+        info!("Profile {} - default layer {}", self.profile, layer);
+        self.keymap.borrow_mut().set_default_layer(layer);
+    }
+
+    #[cfg(feature = "ble")]
+    async fn on_ble_profile_change_event(&mut self, event: BleProfileChangeEvent) {
+        self.profile = event.profile;
+        self.apply();
+    }
+
+    async fn on_connection_change_event(&mut self, event: ConnectionChangeEvent) {
+        self.usb = matches!(event.connection_type, ConnectionType::Usb);
+        self.apply();
+    }
+}
+
+// Every configured layer has to exist
+const _: () = {
+    if let Some(layers) = PROFILE_DEFAULT_LAYER {
+        let mut i = 0;
+        while i < layers.len() {
+            assert!((layers[i] as usize) < NUM_LAYER);
+            i += 1;
+        }
+    }
+};
@@ -0,0 +1,100 @@
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use defmt::info;
+use rmk::event::KeyEvent;
+use rmk::macros::controller;
+use rmk::types::action::KeyAction;
+use smart_leds::RGB8;
+
+use crate::led::{LED_READOUT, LedReadout};
+use crate::user_actions::RAW_ADC_SHOW;
+
+// Raw ADC view for divider bring-up: RAW_ADC_SHOW toggles the battery channel's raw 12-bit
+// SAADC reading onto the LED bar, straight from the ADC - no divider math, no calibration and
+// none of the battery curve (battery_curve.rs). Full scale (4095, 3.6V at the pin) lights
+// every LED, so each LED is 4095 / 14 ~= 293 counts ~= 257mV at the pin, rounded up. With the
+// nominal 1000/1400 divider a 3.7V cell reads ~3007 = 11 LEDs, a full 4.2V one ~3413 = 12.
+// A dark bar means the pin is shorted to ground or the divider's top half is missing, a full
+// one that there's no divider at all (the pin sees the whole cell).
+const READOUT_COLOR: RGB8 = RGB8 { r: 30, g: 0, b: 40 };
+
+// Same length as the LED bar, the status controller clamps it anyway
+const READOUT_MAX_LEDS: u8 = 14;
+
+/// No raw reading yet (the ADC never came up)
+pub(crate) const BATTERY_RAW_UNKNOWN: u16 = u16::MAX;
+
+/// Latest raw battery channel reading, `BATTERY_RAW_UNKNOWN` if there is none.
+/// Main stores the boot reading, `CurveBatteryProcessor` every sample after that.
+pub(crate) static BATTERY_RAW: AtomicU16 = AtomicU16::new(BATTERY_RAW_UNKNOWN);
+
+/// Maps a 12-bit reading linearly onto 0..=max LEDs, anything above 0 lights at least one
+const fn raw_to_led_count(raw: u16, max: u8) -> u8 {
+    let raw = if raw > 4095 { 4095 } else { raw };
+    ((raw as u32 * max as u32).div_ceil(4095)) as u8
+}
+
+const _: () = assert!(raw_to_led_count(0, 14) == 0);
+const _: () = assert!(raw_to_led_count(1, 14) == 1);
+const _: () = assert!(raw_to_led_count(3007, 14) == 11);
+const _: () = assert!(raw_to_led_count(4095, 14) == 14);
+
+/// Shows `BATTERY_RAW` on the bar while toggled on
+#[controller(subscribe = [KeyEvent], poll_interval = 1000)]
+pub struct RawAdcView {
+    enabled: bool,
+    show_key_held: bool,
+}
+
+impl RawAdcView {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            show_key_held: false,
+        }
+    }
+
+    fn show(&self) {
+        let raw = BATTERY_RAW.load(Ordering::Relaxed);
+        if raw == BATTERY_RAW_UNKNOWN {
+            return;
+        }
+        LED_READOUT.signal(LedReadout {
+            count: raw_to_led_count(raw, READOUT_MAX_LEDS),
+            color: READOUT_COLOR,
+        });
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        if event.key_action != KeyAction::Single(RAW_ADC_SHOW) {
+            return;
+        }
+        // Same toggle trick as User7, only act on the press
+        self.show_key_held = !self.show_key_held;
+        if !self.show_key_held {
+            return;
+        }
+        self.enabled = !self.enabled;
+        let raw = BATTERY_RAW.load(Ordering::Relaxed);
+        if raw == BATTERY_RAW_UNKNOWN {
+            info!("Raw ADC view {}: no reading", self.enabled);
+        } else {
+            info!(
+                "Raw ADC view {}: {} ({} LEDs)",
+                self.enabled,
+                raw,
+                raw_to_led_count(raw, READOUT_MAX_LEDS)
+            );
+        }
+        if self.enabled {
+            self.show();
+        }
+    }
+
+    async fn poll(&mut self) {
+        // A readout only stays up for a moment, keep refreshing it while the view is on
+        if self.enabled {
+            self.show();
+        }
+    }
+}
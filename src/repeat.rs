@@ -0,0 +1,189 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt::{info, warn};
+use embassy_time::{Duration, Instant};
+use rmk::channel::KEY_EVENT_CHANNEL;
+use rmk::event::{KeyEvent, KeyboardEvent, KeyboardEventPos};
+use rmk::macros::controller;
+use rmk::types::action::KeyAction;
+
+use crate::keymap::{COL, SIZE};
+
+// Size of the repeat table, one entry per action
+const HOLD_REPEAT_MAX: usize = 8;
+
+// Just under the shortest host typematic delay (250ms, Windows and macOS at their fastest
+// setting) - the first repeat has to come before it, or the host repeats the held key too
+const HOLD_REPEAT_DELAY_MAX_MS: u16 = 240;
+
+// Presses we injected that KeyOdometer hasn't seen yet, per matrix position
+static INJECTED_PRESSES: [AtomicU8; SIZE] = [const { AtomicU8::new(0) }; SIZE];
+
+/// True if the press at (`row`, `col`) was a repeat, not a real one. Counts it off, so only
+/// call it once per press event.
+pub(crate) fn take_injected_press(row: u8, col: u8) -> bool {
+    let Some(count) = INJECTED_PRESSES.get(row as usize * COL + col as usize) else {
+        return false;
+    };
+    count
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+        .is_ok()
+}
+
+#[derive(Clone, Copy)]
+struct HoldRepeat {
+    action: KeyAction,
+    delay: Duration,
+    interval: Duration,
+}
+
+/// Actions that repeat while held, filled in by `keymap::configure_hold_repeat`
+pub(crate) struct HoldRepeatConfig {
+    entries: [Option<HoldRepeat>; HOLD_REPEAT_MAX],
+}
+
+impl HoldRepeatConfig {
+    pub fn new() -> Self {
+        Self {
+            entries: [None; HOLD_REPEAT_MAX],
+        }
+    }
+
+    /// Repeats `action` every `interval_ms` once it has been held for `delay_ms` (at most
+    /// HOLD_REPEAT_DELAY_MAX_MS).
+    ///
+    /// Only for plain keys: the repeat is a press + release of the same matrix position, so on
+    /// a tapdance/morse key every repeat would count as another tap. Warns and does nothing
+    /// once `HOLD_REPEAT_MAX` actions are configured.
+    pub fn add(&mut self, action: KeyAction, delay_ms: u16, interval_ms: u16) {
+        let Some(slot) = self.entries.iter_mut().find(|entry| entry.is_none()) else {
+            warn!("Hold repeat table full, ignoring {:?}", action);
+            return;
+        };
+        *slot = Some(HoldRepeat {
+            action,
+            delay: Duration::from_millis(delay_ms.min(HOLD_REPEAT_DELAY_MAX_MS) as u64),
+            interval: Duration::from_millis(interval_ms.max(1) as u64),
+        });
+    }
+
+    fn find(&self, action: KeyAction) -> Option<HoldRepeat> {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|entry| entry.action == action)
+            .copied()
+    }
+}
+
+// Only one key repeats at a time, like host typematic - a new repeat key takes over
+struct ActiveRepeat {
+    row: u8,
+    col: u8,
+    repeat: HoldRepeat,
+    next: Instant,
+    // Whether the key is up on the host yet, the first repeat releases it
+    started: bool,
+    // Releases we injected that haven't come back as key events yet
+    pending_releases: u16,
+}
+
+/// Re-taps held keys from `HoldRepeatConfig` at their own rate.
+///
+/// The first repeat releases the held key, after that every repeat is a press + release, so
+/// the host never sees the key held long enough for its own typematic. They go in on
+/// `KEY_EVENT_CHANNEL`, the same way matrix events reach the keyboard, so they also come back
+/// here as key events. The real release is the one release more than we injected, repeating
+/// stops on it. Injected presses are tagged for KeyOdometer, see `take_injected_press`.
+#[controller(subscribe = [KeyEvent], poll_interval = 10)]
+pub struct KeyRepeater {
+    config: HoldRepeatConfig,
+    active: Option<ActiveRepeat>,
+}
+
+impl KeyRepeater {
+    pub fn new(config: HoldRepeatConfig) -> Self {
+        Self {
+            config,
+            active: None,
+        }
+    }
+
+    async fn inject(row: u8, col: u8, pressed: bool) {
+        KEY_EVENT_CHANNEL
+            .send(KeyboardEvent::key(row, col, pressed))
+            .await;
+    }
+
+    async fn stop(&mut self) {
+        let Some(active) = self.active.take() else {
+            return;
+        };
+        info!("Hold repeat stopped ({}, {})", active.row, active.col);
+        // Before the first repeat the key is still down on the host
+        if !active.started {
+            Self::inject(active.row, active.col, false).await;
+        }
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        let KeyboardEventPos::Key(pos) = event.keyboard_event.pos else {
+            return;
+        };
+
+        if let Some(active) = self.active.as_mut()
+            && active.row == pos.row
+            && active.col == pos.col
+        {
+            if event.keyboard_event.pressed {
+                // One of ours coming back
+                return;
+            }
+            if active.pending_releases > 0 {
+                active.pending_releases -= 1;
+                return;
+            }
+            self.stop().await;
+            return;
+        }
+
+        if !event.keyboard_event.pressed {
+            return;
+        }
+        let Some(repeat) = self.config.find(event.key_action) else {
+            return;
+        };
+        // Don't leave the previous key pressed when another one takes over
+        self.stop().await;
+        self.active = Some(ActiveRepeat {
+            row: pos.row,
+            col: pos.col,
+            repeat,
+            next: Instant::now() + repeat.delay,
+            started: false,
+            pending_releases: 0,
+        });
+    }
+
+    async fn poll(&mut self) {
+        let Some(active) = self.active.as_mut() else {
+            return;
+        };
+        if Instant::now() < active.next {
+            return;
+        }
+        active.next += active.repeat.interval;
+        let (row, col) = (active.row, active.col);
+        if !active.started {
+            active.started = true;
+            active.pending_releases = active.pending_releases.saturating_add(1);
+            Self::inject(row, col, false).await;
+        }
+        active.pending_releases = active.pending_releases.saturating_add(1);
+        if let Some(count) = INJECTED_PRESSES.get(row as usize * COL + col as usize) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        Self::inject(row, col, true).await;
+        Self::inject(row, col, false).await;
+    }
+}
@@ -0,0 +1,57 @@
+use defmt::{Format, info, warn};
+use embassy_nrf::pac;
+
+/// Decoded RESETREAS bits. More than one can be set, e.g. a pin reset while waking from
+/// System OFF.
+#[derive(Clone, Copy, Format)]
+pub(crate) enum ResetReason {
+    /// No bit set: power-on or brownout, the nRF52840 doesn't tell them apart.
+    /// A reset right after the LEDs turn on showing up as this is the LED rail brownout.
+    PowerOnOrBrownout,
+    ResetPin,
+    Watchdog,
+    /// NVIC_SystemReset - RMK's reboot, and the Adafruit bootloader handing back after DFU
+    SoftReset,
+    Lockup,
+    GpioWake,
+    LpcompWake,
+    DebugInterface,
+    NfcWake,
+    VbusWake,
+}
+
+const RESET_REASON_BITS: [(u32, ResetReason); 9] = [
+    (1 << 0, ResetReason::ResetPin),
+    (1 << 1, ResetReason::Watchdog),
+    (1 << 2, ResetReason::SoftReset),
+    (1 << 3, ResetReason::Lockup),
+    (1 << 16, ResetReason::GpioWake),
+    (1 << 17, ResetReason::LpcompWake),
+    (1 << 18, ResetReason::DebugInterface),
+    (1 << 19, ResetReason::NfcWake),
+    (1 << 20, ResetReason::VbusWake),
+];
+
+/// Logs why we reset and clears RESETREAS, otherwise the bits pile up across resets.
+/// Call early in main, before anything could reset us again.
+pub(crate) fn log_and_clear() {
+    let raw = pac::POWER.resetreas().read().0;
+    if raw == 0 {
+        info!("Reset reason: {:?}", ResetReason::PowerOnOrBrownout);
+        return;
+    }
+    for (bit, reason) in RESET_REASON_BITS {
+        if raw & bit != 0 {
+            match reason {
+                ResetReason::Watchdog | ResetReason::Lockup => {
+                    warn!("Reset reason: {:?}", reason)
+                }
+                _ => info!("Reset reason: {:?}", reason),
+            }
+        }
+    }
+    // Bits are cleared by writing 1
+    pac::POWER
+        .resetreas()
+        .write_value(pac::power::regs::Resetreas(raw));
+}
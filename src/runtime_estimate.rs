@@ -0,0 +1,148 @@
+use defmt::info;
+use embassy_time::Instant;
+use rmk::event::{BatteryStateEvent, KeyEvent};
+use rmk::macros::controller;
+use rmk::types::action::KeyAction;
+use smart_leds::RGB8;
+
+use crate::led::{LED_READOUT, LedReadout};
+use crate::user_actions::RUNTIME_SHOW;
+
+// Remaining battery runtime on RUNTIME_SHOW, as a rough LED count. There's no current sensing,
+// so the draw is estimated from how fast the percentage drops:
+// - One (time, percentage) sample every RUNTIME_SAMPLE_MINUTES, the last RUNTIME_SAMPLES of
+//   them are kept, so the slope is the average over the last ~2 hours. Older use (a long
+//   session with the LEDs on) falls out of the window, a single noisy reading barely moves it.
+// - Hours left = percentage now / percentage lost per hour, one LED per RUNTIME_HOURS_PER_LED
+//   started (1 LED = under 12h, a 14 LED bar tops out at a week).
+// - Until the window spans RUNTIME_MIN_HISTORY_MINUTES and the percentage dropped by at least
+//   RUNTIME_MIN_DROP_PERCENT, the whole bar lights dim amber instead - "calculating". The
+//   percentage comes off a voltage curve in whole steps, anything less is just noise.
+// Charging, or the percentage going back up (charging without the charge pin wired), starts
+// the history over. Same accuracy caveats as charge_cycles.rs: flat curve, sag under load.
+const RUNTIME_SAMPLE_MINUTES: u64 = 10;
+const RUNTIME_SAMPLES: usize = 12;
+const RUNTIME_MIN_HISTORY_MINUTES: u64 = 30;
+const RUNTIME_MIN_DROP_PERCENT: u8 = 2;
+const RUNTIME_HOURS_PER_LED: u32 = 12;
+// Rises smaller than this are voltage noise, not charging
+const RUNTIME_RISE_NOISE_PERCENT: u8 = 3;
+
+const RUNTIME_COLOR: RGB8 = RGB8 { r: 0, g: 40, b: 30 };
+const CALCULATING_COLOR: RGB8 = RGB8 { r: 20, g: 10, b: 0 };
+
+/// Hours left from the `oldest` and `newest` (minutes, percentage) samples, None while there
+/// isn't enough history or drop to go on
+pub(crate) const fn runtime_hours(oldest: (u64, u8), newest: (u64, u8)) -> Option<u32> {
+    let minutes = newest.0.saturating_sub(oldest.0);
+    if minutes < RUNTIME_MIN_HISTORY_MINUTES
+        || newest.1 > oldest.1
+        || oldest.1 - newest.1 < RUNTIME_MIN_DROP_PERCENT
+    {
+        return None;
+    }
+    let drop = (oldest.1 - newest.1) as u64;
+    Some((newest.1 as u64 * minutes / (drop * 60)) as u32)
+}
+
+/// LEDs for `hours` left, always at least one
+pub(crate) const fn runtime_leds(hours: u32) -> u8 {
+    let leds = hours / RUNTIME_HOURS_PER_LED + 1;
+    if leds > u8::MAX as u32 {
+        u8::MAX
+    } else {
+        leds as u8
+    }
+}
+
+// 10% an hour with 50% left = 5h
+const _: () = assert!(matches!(runtime_hours((0, 70), (120, 50)), Some(5)));
+// Too short, too little drop, or going up
+const _: () = assert!(runtime_hours((0, 70), (20, 50)).is_none());
+const _: () = assert!(runtime_hours((0, 70), (120, 69)).is_none());
+const _: () = assert!(runtime_hours((0, 50), (120, 60)).is_none());
+const _: () = assert!(runtime_leds(0) == 1 && runtime_leds(11) == 1 && runtime_leds(12) == 2);
+const _: () = assert!(runtime_leds(u32::MAX) == u8::MAX);
+
+/// Tracks the discharge rate and shows the runtime estimate, see above
+#[controller(subscribe = [BatteryStateEvent, KeyEvent])]
+pub struct RuntimeEstimator {
+    // (minutes since boot, percentage), oldest first, `len` of them in use
+    samples: [(u64, u8); RUNTIME_SAMPLES],
+    len: usize,
+    show_key_held: bool,
+}
+
+impl RuntimeEstimator {
+    pub fn new() -> Self {
+        Self {
+            samples: [(0, 0); RUNTIME_SAMPLES],
+            len: 0,
+            show_key_held: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        if self.len > 0 {
+            info!("Runtime estimate: charging, history cleared");
+        }
+        self.len = 0;
+    }
+
+    fn add_sample(&mut self, percent: u8) {
+        let now = Instant::now().as_secs() / 60;
+        if let Some(&(at, last)) = self.samples[..self.len].last() {
+            if percent >= last + RUNTIME_RISE_NOISE_PERCENT {
+                self.reset();
+            } else if now - at < RUNTIME_SAMPLE_MINUTES {
+                return;
+            }
+        }
+        if self.len == RUNTIME_SAMPLES {
+            self.samples.rotate_left(1);
+            self.len -= 1;
+        }
+        self.samples[self.len] = (now, percent);
+        self.len += 1;
+    }
+
+    async fn on_battery_state_event(&mut self, event: BatteryStateEvent) {
+        match event {
+            BatteryStateEvent::Normal(percent) => self.add_sample(percent),
+            BatteryStateEvent::Charging | BatteryStateEvent::Charged => self.reset(),
+            BatteryStateEvent::NotAvailable => {}
+        }
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        if event.key_action != KeyAction::Single(RUNTIME_SHOW) {
+            return;
+        }
+        // Same toggle trick as BATT_CHECK, only show on the press
+        self.show_key_held = !self.show_key_held;
+        if !self.show_key_held {
+            return;
+        }
+        let hours = match self.len {
+            0 => None,
+            len => runtime_hours(self.samples[0], self.samples[len - 1]),
+        };
+        let readout = match hours {
+            Some(hours) => {
+                info!("Runtime estimate: ~{}h left", hours);
+                LedReadout {
+                    count: runtime_leds(hours),
+                    color: RUNTIME_COLOR,
+                }
+            }
+            None => {
+                info!("Runtime estimate: calculating ({} samples)", self.len);
+                LedReadout {
+                    count: u8::MAX,
+                    color: CALCULATING_COLOR,
+                }
+            }
+        };
+        LED_READOUT.signal(readout);
+    }
+}
@@ -0,0 +1,57 @@
+use defmt::{info, warn};
+use embassy_nrf::gpio::{Input, Output};
+use embassy_time::{Duration, block_for};
+use smart_leds::RGB8;
+
+use crate::led::{LED_READOUT, LedReadout};
+
+// Vial-safe mode: hold SAFE_MODE_KEY while the board powers up (or resets) and the firmware
+// default keymap is used for that session instead of the layout stored by Vial. Storage isn't
+// touched, so the next normal boot is back to the stored layout - unless it was fixed in Vial
+// in the meantime. Vial shows (and edits) the defaults while in safe mode, and every key it
+// changes is saved on top of the stored layout, so fixing only the broken keys is enough.
+// The whole bar lights up blue for a moment to confirm.
+//
+// The key is read straight off the matrix pins before RMK has them, so it's a physical position
+// (row, col), not whatever the broken layout put there. Top-left by default.
+pub(crate) const SAFE_MODE_KEY: (usize, usize) = (0, 0);
+const SAFE_MODE_COLOR: RGB8 = RGB8 { r: 0, g: 20, b: 60 };
+
+// Pressed on every one of these samples, so a bounce or a stray contact doesn't count
+const SAMPLES: u32 = 5;
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(2);
+// Lets the row line settle after the column goes high
+const SETTLE: Duration = Duration::from_micros(10);
+
+/// Whether SAFE_MODE_KEY is held right now. Call it once at boot, before the pins go to the
+/// matrix. Inputs are the rows (pulled down), outputs the columns, same as `Matrix` with COL2ROW.
+pub(crate) fn key_held_at_boot(rows: &mut [Input<'_>], cols: &mut [Output<'_>]) -> bool {
+    let (row, col) = SAFE_MODE_KEY;
+    let (Some(row), Some(col)) = (rows.get(row), cols.get_mut(col)) else {
+        warn!("SAFE_MODE_KEY is outside the matrix");
+        return false;
+    };
+    let mut held = true;
+    for _ in 0..SAMPLES {
+        col.set_high();
+        block_for(SETTLE);
+        held &= row.is_high();
+        col.set_low();
+        if !held {
+            break;
+        }
+        block_for(SAMPLE_INTERVAL);
+    }
+    if held {
+        info!("Safe mode: default keymap for this session, stored layout left alone");
+    }
+    held
+}
+
+/// LED confirmation, shown once `StatusLedController` is running
+pub(crate) fn confirm() {
+    LED_READOUT.signal(LedReadout {
+        count: u8::MAX,
+        color: SAFE_MODE_COLOR,
+    });
+}
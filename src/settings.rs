@@ -0,0 +1,257 @@
+use core::ops::Range;
+
+use defmt::{Format, info, warn};
+use embassy_embedded_hal::adapter::BlockingAsync;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embedded_storage::nor_flash::NorFlash;
+use sequential_storage::cache::NoCache;
+use sequential_storage::map::{Value, fetch_item, store_item};
+
+use crate::battery_cal::{BatteryCalibration, calibration_from_bytes, calibration_to_bytes};
+use crate::keymap::SIZE;
+use crate::led::{
+    PROFILE_LEDS_COUNT, PROFILE_LEDS_DEFAULT, ProfileLeds, ProfileLedsBytes, STORAGE_ERROR,
+    UnderglowBytes, profile_leds_from_bytes,
+};
+use crate::tuning::{HOLD_TIMEOUT_DEFAULT_MS, HOLD_TIMEOUT_MAX_MS, HOLD_TIMEOUT_MIN_MS};
+
+// Our own settings (things RMK doesn't know about) live in a separate flash region
+// right after RMK's storage (0xA0000 + 12 sectors), so RMK never sees our keys.
+pub(crate) const SETTINGS_START_ADDR: u32 = 0xAC000;
+pub(crate) const SETTINGS_NUM_SECTORS: u32 = 2; // sequential-storage needs at least 2
+pub(crate) const SETTINGS_SECTOR_SIZE: u32 = 4096;
+
+/// Send an update here to persist it, `SettingsStore::run` writes it to flash
+pub(crate) static SETTINGS_CHANNEL: Channel<CriticalSectionRawMutex, SettingsUpdate, 4> =
+    Channel::new();
+
+#[derive(Clone, Copy, Format)]
+pub(crate) enum SettingsUpdate {
+    HoldTimeout(u16),
+    Odometer { total: u32, per_key: [u32; SIZE] },
+    LedsDisabled(bool),
+    ChargeCycles { cycles: u16, partial_percent: u8 },
+    Underglow(UnderglowBytes),
+    // Only BLE profile events send it
+    #[cfg_attr(not(feature = "ble"), allow(dead_code))]
+    ActiveBleProfile(u8),
+    BatteryCalibration(BatteryCalibration),
+    ProfileLeds(ProfileLedsBytes),
+    #[cfg(feature = "dev-console")]
+    EraseAll,
+}
+
+/// Storage keys - never reuse or renumber, old values stay in flash
+#[repr(u8)]
+#[derive(Clone, Copy)]
+enum SettingsKey {
+    HoldTimeout = 0x00,
+    OdometerTotal = 0x01,
+    OdometerPerKey = 0x02,
+    LedsDisabled = 0x03,
+    ChargeCycles = 0x04,
+    ChargePartial = 0x05,
+    Underglow = 0x06,
+    // Our own copy, in our region - RMK's bonds and its own profile state stay in its storage
+    ActiveBleProfile = 0x07,
+    BatteryCalibration = 0x08,
+    // All three profiles in one item, see ProfileLedsBytes
+    ProfileLeds = 0x09,
+}
+
+/// Settings loaded from flash at boot, fields fall back to defaults when never stored
+#[derive(Clone, Copy, Format)]
+pub(crate) struct Settings {
+    pub hold_timeout_ms: u16,
+    pub odometer_total: u32,
+    pub odometer_per_key: [u32; SIZE],
+    pub leds_disabled: bool,
+    pub charge_cycles: u16,
+    pub charge_partial_percent: u8,
+    /// None = never set, the board default from main.rs applies
+    pub underglow: Option<UnderglowBytes>,
+    /// Last BLE profile RMK reported, so the LEDs and default layer start on it.
+    ///
+    /// Only seeds our controllers: RMK (ca38784) picks the profile it advertises and reconnects
+    /// on inside its BLE task, from its own storage. If RMK ever comes up on a different
+    /// profile, its first profile event moves them over (and saves that one here).
+    pub active_ble_profile: u8,
+    /// Raw readings at the two calibration voltages, see battery_cal.rs
+    pub battery_calibration: BatteryCalibration,
+    /// LED brightness and effect per BLE profile, applied when that profile becomes active
+    pub profile_leds: [ProfileLeds; PROFILE_LEDS_COUNT],
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            hold_timeout_ms: HOLD_TIMEOUT_DEFAULT_MS,
+            odometer_total: 0,
+            odometer_per_key: [0; SIZE],
+            leds_disabled: false,
+            charge_cycles: 0,
+            charge_partial_percent: 0,
+            underglow: None,
+            active_ble_profile: 0,
+            battery_calibration: [0; 2],
+            profile_leds: [PROFILE_LEDS_DEFAULT; PROFILE_LEDS_COUNT],
+        }
+    }
+}
+
+pub(crate) struct SettingsStore<F: NorFlash> {
+    flash: BlockingAsync<F>,
+    buffer: [u8; 128], // Largest item is the per-key odometer (64 bytes) plus item overhead
+}
+
+impl<F: NorFlash> SettingsStore<F> {
+    /// `flash` should be a partition covering only the settings region
+    pub fn new(flash: F) -> Self {
+        Self {
+            flash: BlockingAsync::new(flash),
+            buffer: [0; 128],
+        }
+    }
+
+    fn range() -> Range<u32> {
+        0..SETTINGS_NUM_SECTORS * SETTINGS_SECTOR_SIZE
+    }
+
+    pub async fn load(&mut self) -> Settings {
+        let mut settings = Settings::default();
+        if let Some(ms) = self.fetch::<u16>(SettingsKey::HoldTimeout).await {
+            // A value saved under other limits would underflow the tuner's readout
+            settings.hold_timeout_ms = ms.clamp(HOLD_TIMEOUT_MIN_MS, HOLD_TIMEOUT_MAX_MS);
+        }
+        if let Some(total) = self.fetch::<u32>(SettingsKey::OdometerTotal).await {
+            settings.odometer_total = total;
+        }
+        if let Some(bytes) = self
+            .fetch::<[u8; SIZE * 4]>(SettingsKey::OdometerPerKey)
+            .await
+        {
+            for (count, chunk) in settings
+                .odometer_per_key
+                .iter_mut()
+                .zip(bytes.chunks_exact(4))
+            {
+                *count = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            }
+        }
+        if let Some(disabled) = self.fetch::<bool>(SettingsKey::LedsDisabled).await {
+            settings.leds_disabled = disabled;
+        }
+        if let Some(cycles) = self.fetch::<u16>(SettingsKey::ChargeCycles).await {
+            settings.charge_cycles = cycles;
+        }
+        if let Some(percent) = self.fetch::<u8>(SettingsKey::ChargePartial).await {
+            settings.charge_partial_percent = percent;
+        }
+        if let Some(bytes) = self.fetch::<UnderglowBytes>(SettingsKey::Underglow).await {
+            settings.underglow = Some(bytes);
+        }
+        if let Some(profile) = self.fetch::<u8>(SettingsKey::ActiveBleProfile).await {
+            settings.active_ble_profile = profile;
+        }
+        if let Some(bytes) = self.fetch::<[u8; 4]>(SettingsKey::BatteryCalibration).await {
+            settings.battery_calibration = calibration_from_bytes(bytes);
+        }
+        if let Some(bytes) = self
+            .fetch::<ProfileLedsBytes>(SettingsKey::ProfileLeds)
+            .await
+        {
+            settings.profile_leds = profile_leds_from_bytes(bytes);
+        }
+        info!("Loaded settings: {:?}", settings);
+        settings
+    }
+
+    async fn fetch<V: for<'a> Value<'a>>(&mut self, key: SettingsKey) -> Option<V> {
+        match fetch_item::<u8, V, _>(
+            &mut self.flash,
+            Self::range(),
+            &mut NoCache::new(),
+            &mut self.buffer,
+            &(key as u8),
+        )
+        .await
+        {
+            Ok(value) => value,
+            Err(_) => {
+                warn!("Failed to read setting {}", key as u8);
+                None
+            }
+        }
+    }
+
+    async fn store<V: for<'a> Value<'a>>(&mut self, key: SettingsKey, value: &V) {
+        if store_item(
+            &mut self.flash,
+            Self::range(),
+            &mut NoCache::new(),
+            &mut self.buffer,
+            &(key as u8),
+            value,
+        )
+        .await
+        .is_err()
+        {
+            warn!("Failed to write setting {}", key as u8);
+            STORAGE_ERROR.signal(());
+        }
+    }
+
+    /// Writes updates from `SETTINGS_CHANNEL` to flash, one item per update
+    pub async fn run(&mut self) -> ! {
+        loop {
+            let update = SETTINGS_CHANNEL.receive().await;
+            info!("Saving setting: {:?}", update);
+            match update {
+                SettingsUpdate::HoldTimeout(ms) => self.store(SettingsKey::HoldTimeout, &ms).await,
+                SettingsUpdate::Odometer { total, per_key } => {
+                    let mut bytes = [0u8; SIZE * 4];
+                    for (chunk, count) in bytes.chunks_exact_mut(4).zip(per_key.iter()) {
+                        chunk.copy_from_slice(&count.to_le_bytes());
+                    }
+                    self.store(SettingsKey::OdometerTotal, &total).await;
+                    self.store(SettingsKey::OdometerPerKey, &bytes).await;
+                }
+                SettingsUpdate::LedsDisabled(disabled) => {
+                    self.store(SettingsKey::LedsDisabled, &disabled).await
+                }
+                SettingsUpdate::ChargeCycles {
+                    cycles,
+                    partial_percent,
+                } => {
+                    self.store(SettingsKey::ChargeCycles, &cycles).await;
+                    self.store(SettingsKey::ChargePartial, &partial_percent)
+                        .await;
+                }
+                SettingsUpdate::Underglow(bytes) => {
+                    self.store(SettingsKey::Underglow, &bytes).await
+                }
+                SettingsUpdate::ActiveBleProfile(profile) => {
+                    self.store(SettingsKey::ActiveBleProfile, &profile).await
+                }
+                SettingsUpdate::BatteryCalibration(cal) => {
+                    self.store(SettingsKey::BatteryCalibration, &calibration_to_bytes(cal))
+                        .await
+                }
+                SettingsUpdate::ProfileLeds(bytes) => {
+                    self.store(SettingsKey::ProfileLeds, &bytes).await
+                }
+                #[cfg(feature = "dev-console")]
+                SettingsUpdate::EraseAll => {
+                    if sequential_storage::erase_all(&mut self.flash, Self::range())
+                        .await
+                        .is_err()
+                    {
+                        warn!("Failed to erase settings");
+                        STORAGE_ERROR.signal(());
+                    }
+                }
+            }
+        }
+    }
+}
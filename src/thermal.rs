@@ -0,0 +1,76 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::{info, warn};
+use embassy_time::{Duration, Timer};
+
+#[cfg(feature = "ble")]
+use crate::idle::LOW_POWER_IDLE;
+#[cfg(feature = "ble")]
+use crate::led::LEDS_POWERED;
+
+const THERMAL_READ_INTERVAL: Duration = Duration::from_secs(60);
+
+// Die temperature, not ambient. A shorted LED rail next to the nRF shows up here first.
+pub(crate) const THERMAL_OVERHEAT_C: i32 = 60;
+// Only clear the overheat once it has cooled down a bit, so the LEDs don't flap
+const THERMAL_RECOVER_C: i32 = THERMAL_OVERHEAT_C - 5;
+
+/// While set, `StatusLedController` keeps the LED MOSFET low
+pub(crate) static THERMAL_OVERHEAT: AtomicBool = AtomicBool::new(false);
+
+/// Die temperature in 0.25°C steps.
+///
+/// The TEMP peripheral belongs to MPSL (it uses it for its own clock calibration), so reading
+/// it directly through `embassy_nrf::temp` would race with the radio. MPSL exposes its own
+/// reading instead, which schedules the measurement around its own use of the peripheral.
+#[cfg(feature = "ble")]
+fn read_temperature_quarter_c() -> i32 {
+    // SAFETY: only needs MPSL to be initialized, which happens before this task is spawned
+    unsafe { nrf_sdc::mpsl::raw::mpsl_temperature_get() }
+}
+
+#[cfg(feature = "ble")]
+#[embassy_executor::task]
+pub(crate) async fn thermal_task() -> ! {
+    loop {
+        // Only skipped with the rail off too: a locked color or the focus timer keeps the LEDs
+        // lit through idle. The next read after either changes is at most one interval away.
+        if !LOW_POWER_IDLE.load(Ordering::Relaxed) || LEDS_POWERED.load(Ordering::Relaxed) {
+            check_temperature(read_temperature_quarter_c());
+        }
+        Timer::after(THERMAL_READ_INTERVAL).await;
+    }
+}
+
+/// USB-only builds have no MPSL, so TEMP is read directly
+#[cfg(not(feature = "ble"))]
+#[embassy_executor::task]
+pub(crate) async fn thermal_task(mut temp: embassy_nrf::temp::Temp<'static>) -> ! {
+    loop {
+        // Fixed point with 2 fractional bits, so the raw bits are already quarter degrees
+        check_temperature(temp.read().await.to_bits());
+        Timer::after(THERMAL_READ_INTERVAL).await;
+    }
+}
+
+fn check_temperature(quarter_c: i32) {
+    let celsius = quarter_c / 4;
+    // Sign on its own, -0.75°C has a whole part of 0
+    let sign = if quarter_c < 0 { "-" } else { "" };
+    let quarters = quarter_c.unsigned_abs();
+    info!(
+        "Temperature: {}{}.{}°C",
+        sign,
+        quarters / 4,
+        (quarters % 4) * 25
+    );
+
+    let overheated = THERMAL_OVERHEAT.load(Ordering::Relaxed);
+    if !overheated && celsius >= THERMAL_OVERHEAT_C {
+        warn!("Overheating ({}°C) - shutting off LEDs", celsius);
+        THERMAL_OVERHEAT.store(true, Ordering::Relaxed);
+    } else if overheated && celsius < THERMAL_RECOVER_C {
+        info!("Cooled down ({}°C) - LEDs allowed again", celsius);
+        THERMAL_OVERHEAT.store(false, Ordering::Relaxed);
+    }
+}
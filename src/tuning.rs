@@ -0,0 +1,98 @@
+use core::cell::RefCell;
+
+use defmt::info;
+use rmk::event::KeyEvent;
+use rmk::keymap::KeyMap;
+use rmk::macros::controller;
+use rmk::types::action::KeyAction;
+use smart_leds::RGB8;
+
+use crate::keymap::{BOOTLOADER_TAPDANCE, COL, NUM_ENCODER, NUM_LAYER, ROW};
+use crate::led::{LED_READOUT, LedReadout};
+use crate::settings::{SETTINGS_CHANNEL, SettingsUpdate};
+use crate::user_actions::{HOLD_TIMEOUT_DOWN, HOLD_TIMEOUT_UP};
+
+// Tapdance hold timeout, adjustable live from the encoder on the tuning layer
+pub(crate) const HOLD_TIMEOUT_MIN_MS: u16 = 100;
+pub(crate) const HOLD_TIMEOUT_MAX_MS: u16 = 400;
+pub(crate) const HOLD_TIMEOUT_STEP_MS: u16 = 25;
+pub(crate) const HOLD_TIMEOUT_DEFAULT_MS: u16 = 200;
+
+// 100-400ms in 25ms steps = 1-13 LEDs
+const READOUT_COLOR: RGB8 = RGB8 { r: 60, g: 20, b: 0 };
+
+/// Applies encoder steps from the tuning layer and saves them. Each step overrides the hold
+/// timeout of the default morse profile (so every lt! key) and of every tapdance except the
+/// bootloader one, whatever `keymap::configure_*` gave them at boot.
+#[controller(subscribe = [KeyEvent])]
+pub struct HoldTimeoutTuner<'a> {
+    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    hold_timeout_ms: u16,
+    tune_key_held: bool,
+}
+
+impl<'a> HoldTimeoutTuner<'a> {
+    pub fn new(
+        keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+        hold_timeout_ms: u16,
+    ) -> Self {
+        Self {
+            keymap,
+            hold_timeout_ms,
+            tune_key_held: false,
+        }
+    }
+
+    fn apply(&mut self) {
+        let mut keymap = self.keymap.borrow_mut();
+        // lt! keys use the default profile, see keymap::configure_layer_tap
+        keymap.behavior.morse.default_profile = keymap
+            .behavior
+            .morse
+            .default_profile
+            .with_hold_timeout_ms(Some(self.hold_timeout_ms));
+        for (i, morse) in keymap.behavior.morse.morses.iter_mut().enumerate() {
+            // Bootloader hold stays fixed, StatusLedController times it with its own constant
+            if i == BOOTLOADER_TAPDANCE as usize {
+                continue;
+            }
+            morse.profile = morse
+                .profile
+                .with_hold_timeout_ms(Some(self.hold_timeout_ms));
+        }
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        let increase = if event.key_action == KeyAction::Single(HOLD_TIMEOUT_UP) {
+            true
+        } else if event.key_action == KeyAction::Single(HOLD_TIMEOUT_DOWN) {
+            false
+        } else {
+            return;
+        };
+
+        // Encoder steps arrive as press + release, only act on the press
+        self.tune_key_held = !self.tune_key_held;
+        if !self.tune_key_held {
+            return;
+        }
+
+        self.hold_timeout_ms = if increase {
+            (self.hold_timeout_ms + HOLD_TIMEOUT_STEP_MS).min(HOLD_TIMEOUT_MAX_MS)
+        } else {
+            self.hold_timeout_ms
+                .saturating_sub(HOLD_TIMEOUT_STEP_MS)
+                .max(HOLD_TIMEOUT_MIN_MS)
+        };
+        info!("Hold timeout: {}ms", self.hold_timeout_ms);
+
+        self.apply();
+        SETTINGS_CHANNEL
+            .send(SettingsUpdate::HoldTimeout(self.hold_timeout_ms))
+            .await;
+        LED_READOUT.signal(LedReadout {
+            count: ((self.hold_timeout_ms - HOLD_TIMEOUT_MIN_MS) / HOLD_TIMEOUT_STEP_MS + 1) as u8,
+            color: READOUT_COLOR,
+        });
+    }
+}
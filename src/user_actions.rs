@@ -0,0 +1,124 @@
+use rmk::types::action::Action;
+
+// Every `Action::User(n)` this firmware gives a meaning to, in one place so the keymap and the
+// controllers handling them can't drift apart. The index is also the position in vial.json's
+// customKeycodes, that's how Vial names them. User(4) (RMK's previous profile) is unused.
+
+// BLE profiles, clear and USB/BLE switch - handled by RMK itself
+pub(crate) const BLE1: Action = Action::User(0);
+pub(crate) const BLE2: Action = Action::User(1);
+pub(crate) const BLE3: Action = Action::User(2);
+// Next profile, wrapping BLE3 -> BLE1. RMK switches on its own active profile, so mixing it
+// with BLE1-3 always moves on from wherever those left it.
+pub(crate) const BLE_NEXT: Action = Action::User(3);
+pub(crate) const BLE_CLR: Action = Action::User(5);
+pub(crate) const USB_BLE_SW: Action = Action::User(6);
+
+// Shows the battery level while held, handled by StatusLedController
+pub(crate) const BATT_CHECK: Action = Action::User(7);
+
+// Not handled by RMK - StatusLedController confirms and jumps to the bootloader itself
+pub(crate) const BOOTLOADER_REQ: Action = Action::User(8);
+
+// Encoder on the tuning layer, handled by HoldTimeoutTuner
+pub(crate) const HOLD_TIMEOUT_UP: Action = Action::User(9);
+pub(crate) const HOLD_TIMEOUT_DOWN: Action = Action::User(10);
+
+// Shows the keypress total on the LED bar, handled by KeyOdometer
+pub(crate) const ODOMETER_SHOW: Action = Action::User(11);
+
+// Turns all LEDs off (persisted), handled by StatusLedController
+pub(crate) const LEDS_TOGGLE: Action = Action::User(12);
+
+// Shows the battery charge cycle count on the LED bar, handled by ChargeCycleCounter
+pub(crate) const CHARGE_CYCLES_SHOW: Action = Action::User(13);
+
+// Cycles through the LED effects (status, party, scanner), handled by StatusLedController
+pub(crate) const EFFECT_NEXT: Action = Action::User(14);
+
+// Shows per-key usage as a heatmap on the LED bar, handled by KeyOdometer
+pub(crate) const HEATMAP_SHOW: Action = Action::User(15);
+
+// Locks the whole strip to red, green, blue, white, then off again - for photos and
+// debugging, handled by StatusLedController
+pub(crate) const LED_LOCK: Action = Action::User(16);
+
+// Captures a battery calibration point while BATT_CHECK is held, handled by BatteryCalibrator
+pub(crate) const BATT_CAL: Action = Action::User(17);
+
+// Toggles the raw battery SAADC reading on the LED bar, handled by RawAdcView
+pub(crate) const RAW_ADC_SHOW: Action = Action::User(18);
+
+// Starts, pauses and resumes the focus timer on the LED bar, a long hold resets it.
+// Handled by StatusLedController
+pub(crate) const FOCUS_TIMER: Action = Action::User(19);
+
+// Logs the live keymap over RTT while BATT_CHECK is held, handled by KeymapDumper.
+// Only in `dev` builds, a plain no-op otherwise
+pub(crate) const KEYMAP_DUMP: Action = Action::User(20);
+
+// Shows the estimated battery runtime left on the LED bar, handled by RuntimeEstimator
+pub(crate) const RUNTIME_SHOW: Action = Action::User(21);
+
+// Deletes a single BLE profile's bond, on the hold of the bond management layer's profile
+// keys. Handled by BondManager
+pub(crate) const BOND_DEL_1: Action = Action::User(22);
+pub(crate) const BOND_DEL_2: Action = Action::User(23);
+pub(crate) const BOND_DEL_3: Action = Action::User(24);
+
+// New User actions go here too, or the collision check below won't see them
+const ALL: &[Action] = &[
+    BLE1,
+    BLE2,
+    BLE3,
+    BLE_NEXT,
+    BLE_CLR,
+    USB_BLE_SW,
+    BATT_CHECK,
+    BOOTLOADER_REQ,
+    HOLD_TIMEOUT_UP,
+    HOLD_TIMEOUT_DOWN,
+    ODOMETER_SHOW,
+    LEDS_TOGGLE,
+    CHARGE_CYCLES_SHOW,
+    EFFECT_NEXT,
+    HEATMAP_SHOW,
+    LED_LOCK,
+    BATT_CAL,
+    RAW_ADC_SHOW,
+    FOCUS_TIMER,
+    KEYMAP_DUMP,
+    RUNTIME_SHOW,
+    BOND_DEL_1,
+    BOND_DEL_2,
+    BOND_DEL_3,
+];
+
+const fn user_index(action: Action) -> u8 {
+    match action {
+        Action::User(index) => index,
+        _ => panic!("user_actions only holds Action::User"),
+    }
+}
+
+/// True if no two actions share a User index
+const fn all_distinct(actions: &[Action]) -> bool {
+    // No for loops in const fn
+    let mut i = 0;
+    while i < actions.len() {
+        let mut j = i + 1;
+        while j < actions.len() {
+            if user_index(actions[i]) == user_index(actions[j]) {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+const _: () = assert!(all_distinct(ALL));
+// The check itself has to catch a collision
+const COLLIDING: &[Action] = &[Action::User(3), Action::User(9), Action::User(3)];
+const _: () = assert!(!all_distinct(COLLIDING));
@@ -0,0 +1,76 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::info;
+use embassy_nrf::pac;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+
+// USB power detection for the LEDs. The USB driver owns `HardwareVbusDetect` and its
+// USBDETECTED/USBREMOVED events, so this only reads POWER.USBREGSTATUS, which has no side
+// effects and can't get in the driver's way.
+const VBUS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// VBUS bounces for a moment while the plug goes in or out, only a level that held this long
+// gets reported
+const VBUS_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Settled VBUS state, true = USB power present. Picked up by `StatusLedController` on its next
+/// poll, which dims the strip while on battery.
+pub(crate) static VBUS_PRESENT: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Same settled state for anyone else who needs it (a `Signal` only has one taker)
+pub(crate) static VBUS_POWERED: AtomicBool = AtomicBool::new(false);
+
+fn report(present: bool) {
+    VBUS_POWERED.store(present, Ordering::Relaxed);
+    VBUS_PRESENT.signal(present);
+}
+
+fn vbus_detected() -> bool {
+    pac::POWER.usbregstatus().read().vbusdetect()
+}
+
+// A few quick reads for the boot-time check, the plug can still be bouncing this early
+const VBUS_BOOT_SAMPLES: u32 = 5;
+const VBUS_BOOT_SAMPLE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// VBUS state at boot, for picking the startup animation before `vbus_task` has settled
+/// anything. `HardwareVbusDetect` belongs to the USB driver by then (and only reports through
+/// its own events), so this reads the same USBREGSTATUS bit it's based on. Every sample has
+/// to agree, None means the level changed in between - usually the plug going in right now.
+pub(crate) async fn vbus_at_boot() -> Option<bool> {
+    let first = vbus_detected();
+    for _ in 1..VBUS_BOOT_SAMPLES {
+        Timer::after(VBUS_BOOT_SAMPLE_INTERVAL).await;
+        if vbus_detected() != first {
+            return None;
+        }
+    }
+    Some(first)
+}
+
+#[embassy_executor::task]
+pub(crate) async fn vbus_task() -> ! {
+    // The boot state goes out right away, there's nothing to settle from yet
+    let mut reported = vbus_detected();
+    info!("VBUS at boot: {}", reported);
+    report(reported);
+
+    let mut changed_at: Option<Instant> = None;
+    loop {
+        Timer::after(VBUS_POLL_INTERVAL).await;
+        let present = vbus_detected();
+        if present == reported {
+            // Bounced back before it settled
+            changed_at = None;
+            continue;
+        }
+        let since = *changed_at.get_or_insert_with(Instant::now);
+        if since.elapsed() >= VBUS_SETTLE_TIME {
+            info!("VBUS {}", if present { "connected" } else { "removed" });
+            reported = present;
+            changed_at = None;
+            report(present);
+        }
+    }
+}
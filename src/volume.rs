@@ -0,0 +1,128 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt::{Format, info};
+use rmk::event::{KeyEvent, KeyboardEventPos, LayerChangeEvent};
+use rmk::macros::controller;
+use rmk::types::action::{Action, KeyAction};
+use rmk::types::keycode::KeyCode;
+
+// Volume steps per encoder detent that depend on where the volume is. A volume key is one
+// step on the host's own scale, and the host never tells a keyboard what the volume is, so
+// this keeps an estimate: VOLUME_HOST_STEPS steps from mute to max, moved by every step sent.
+// By thirds of that range, from the bottom:
+// - FastLow: 3 / 2 / 1 steps per detent - quick out of near silence, fine near the top
+// - FastHigh: 1 / 2 / 3 - fine at listening levels for quiet headphones, quick to the top
+// - Linear: always 1, same as before this existed. The default, so the knob behaves like it
+//   always did until VOLUME_CURVE is changed
+// The direction doesn't matter, a detent takes the step count of the third it starts in.
+//
+// The estimate starts in the middle at boot and drifts whenever the volume changes elsewhere
+// (the host's slider, another keyboard). It's clamped to the range, so turning the knob all
+// the way down resyncs it: past mute the host stays at 0 and so does the estimate, everything
+// from there on is right again. Turning all the way up works the same.
+//
+// Nothing is injected: FilteredEncoder (encoder_filter.rs) sends VOLUME_DETENT_STEPS encoder
+// steps per detent, and every one of them goes through the keymap like a plain detent. So
+// every volume key event seen here is one host step, whichever detent it came from. Off the
+// volume actions (another layer, or the numpad profile's wheel) it's back to 1 per detent,
+// and from any layer change until the next volume step.
+#[derive(Clone, Copy, PartialEq, Format)]
+pub(crate) enum VolumeCurve {
+    Linear,
+    FastLow,
+    FastHigh,
+}
+
+pub(crate) const VOLUME_CURVE: VolumeCurve = VolumeCurve::Linear;
+// Windows and most Linux desktops step 2%, macOS has 16 steps
+pub(crate) const VOLUME_HOST_STEPS: u8 = 50;
+
+/// Encoder steps FilteredEncoder sends per detent, kept by VolumeStepper
+pub(crate) static VOLUME_DETENT_STEPS: AtomicU8 = AtomicU8::new(1);
+
+/// Volume steps for one detent with the estimate at `volume` (0..=`max`)
+pub(crate) const fn volume_steps(curve: VolumeCurve, volume: u8, max: u8) -> u8 {
+    let third = (volume as u16 * 3 / (max as u16 + 1)) as u8;
+    match curve {
+        VolumeCurve::Linear => 1,
+        VolumeCurve::FastLow => 3 - third,
+        VolumeCurve::FastHigh => third + 1,
+    }
+}
+
+/// Estimate after `steps` steps up or down, clamped to 0..=`max`
+pub(crate) const fn step_volume(volume: u8, steps: u8, up: bool, max: u8) -> u8 {
+    if up {
+        let volume = volume.saturating_add(steps);
+        if volume > max { max } else { volume }
+    } else {
+        volume.saturating_sub(steps)
+    }
+}
+
+const _: () = assert!(volume_steps(VolumeCurve::Linear, 0, 50) == 1);
+const _: () = assert!(volume_steps(VolumeCurve::FastLow, 0, 50) == 3);
+const _: () = assert!(volume_steps(VolumeCurve::FastLow, 25, 50) == 2);
+const _: () = assert!(volume_steps(VolumeCurve::FastLow, 50, 50) == 1);
+const _: () = assert!(volume_steps(VolumeCurve::FastHigh, 0, 50) == 1);
+const _: () = assert!(volume_steps(VolumeCurve::FastHigh, 50, 50) == 3);
+// Every volume on every curve is 1-3 steps, the thirds never run past the ends
+const fn steps_in_range(max: u8) -> bool {
+    let mut volume = 0;
+    while volume <= max {
+        let low = volume_steps(VolumeCurve::FastLow, volume, max);
+        let high = volume_steps(VolumeCurve::FastHigh, volume, max);
+        if low < 1 || low > 3 || high < 1 || high > 3 {
+            return false;
+        }
+        volume += 1;
+    }
+    true
+}
+const _: () = assert!(steps_in_range(VOLUME_HOST_STEPS) && steps_in_range(16));
+// Clamped at both ends, which is what resyncs it
+const _: () = assert!(step_volume(1, 3, false, 50) == 0 && step_volume(49, 3, true, 50) == 50);
+const _: () = assert!(step_volume(20, 2, true, 50) == 22);
+
+/// Sets VOLUME_DETENT_STEPS from the volume estimate, see above
+#[controller(subscribe = [KeyEvent, LayerChangeEvent])]
+pub struct VolumeStepper {
+    volume: u8,
+}
+
+impl VolumeStepper {
+    pub fn new() -> Self {
+        Self {
+            volume: VOLUME_HOST_STEPS / 2,
+        }
+    }
+
+    fn set_detent_steps(steps: u8) {
+        if VOLUME_DETENT_STEPS.swap(steps, Ordering::Relaxed) != steps && steps > 1 {
+            info!("Volume steps per detent: {}", steps);
+        }
+    }
+
+    async fn on_layer_change_event(&mut self, _event: LayerChangeEvent) {
+        // The encoder may not be on volume any more
+        Self::set_detent_steps(1);
+    }
+
+    async fn on_key_event(&mut self, event: KeyEvent) {
+        if !matches!(event.keyboard_event.pos, KeyboardEventPos::RotaryEncoder(_))
+            || !event.keyboard_event.pressed
+        {
+            return;
+        }
+        let up = match event.key_action {
+            KeyAction::Single(Action::Key(KeyCode::AudioVolUp)) => true,
+            KeyAction::Single(Action::Key(KeyCode::AudioVolDown)) => false,
+            _ => {
+                Self::set_detent_steps(1);
+                return;
+            }
+        };
+        self.volume = step_volume(self.volume, 1, up, VOLUME_HOST_STEPS);
+        Self::set_detent_steps(volume_steps(VOLUME_CURVE, self.volume, VOLUME_HOST_STEPS));
+    }
+}